@@ -0,0 +1,349 @@
+/// How overlapping photos are combined
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum BlendMode {
+    /// equal weights (see average_effect.frag)
+    Average,
+    /// Laplacian pyramid blending: high frequencies switch between photos over narrow transitions,
+    /// low frequencies over wide ones
+    MultiBand,
+    /// weighted average: each photo's weight falls off linearly toward its (distorted) image edges
+    Feather,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Average
+    }
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 3] = [BlendMode::Average, BlendMode::Feather, BlendMode::MultiBand];
+}
+
+/// the most times multi-band blending halves an image
+const MAX_PYRAMID_LEVELS: usize = 7;
+
+/// below this, pyramid coverage and weight sums are treated as 0 (see multiband_fill.frag)
+const MIN_PYRAMID_WEIGHT: f32 = 1e-4;
+
+/// the binomial blur kernel for pyramid levels (see pyramid_reduce.frag and pyramid_expand.frag)
+const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+
+/// the number of times multi-band blending halves a `width` x `height` image:
+/// the coarsest level's shorter side is at least 8 pixels
+pub fn pyramid_levels(width: usize, height: usize) -> usize {
+
+    let mut side = width.min(height);
+    let mut levels = 0;
+
+    while side >= 16 && levels < MAX_PYRAMID_LEVELS {
+        side = (side + 1) / 2;
+        levels += 1;
+    }
+
+    levels
+}
+
+/// distance from source image texture coords to the nearest image edge, in texture coords:
+/// the feather weight, and multi-band blending takes each pixel's high frequencies from the photo where this is largest
+/// (see the photo shaders' `edge_weight` uniform)
+pub fn edge_distance((u, v): (f64, f64)) -> f64 {
+
+    u.min(1.0 - u).min(v).min(1.0 - v)
+}
+
+/// One channel of an image, stored by rows (row 0 at the top)
+#[derive(Debug, PartialEq, Clone)]
+pub struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl Plane {
+
+    /// all zeros
+    pub fn new(width: usize, height: usize) -> Self {
+
+        Self{width, height, data: vec![0.0; width * height]}
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+
+        self.data[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, value: f32) {
+
+        self.data[y * self.width + x] = value;
+    }
+
+    /// the value at (x, y), clamped to the edges
+    fn get_clamped(&self, x: i64, y: i64) -> f32 {
+
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.get(x, y)
+    }
+
+    fn zip_map(&self, other: &Plane, f: impl Fn(f32, f32) -> f32) -> Plane {
+
+        Plane {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().zip(&other.data).map(|(&a, &b)| f(a, b)).collect(),
+        }
+    }
+
+    /// blurs with the binomial kernel, then halves the size (rounding up)
+    pub fn reduce(&self) -> Plane {
+
+        let width = (self.width + 1) / 2;
+        let height = (self.height + 1) / 2;
+
+        let mut rows = Plane::new(width, self.height);
+        for y in 0..self.height {
+            for x in 0..width {
+                let value = (-2..=2).map(|m| KERNEL[(m + 2) as usize] * self.get_clamped(2 * x as i64 + m, y as i64)).sum();
+                rows.set(x, y, value);
+            }
+        }
+
+        let mut reduced = Plane::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = (-2..=2).map(|m| KERNEL[(m + 2) as usize] * rows.get_clamped(x as i64, 2 * y as i64 + m)).sum();
+                reduced.set(x, y, value);
+            }
+        }
+
+        reduced
+    }
+
+    /// doubles the size (cropped to `width` x `height`), interpolating with the binomial kernel: the inverse of `reduce`
+    pub fn expand(&self, width: usize, height: usize) -> Plane {
+
+        //each output pixel gets the kernel taps that land on input pixels
+        let taps = |i: usize| (-2..=2_i64).filter(move |m| (i as i64 - m).rem_euclid(2) == 0).map(move |m| {
+            (2.0 * KERNEL[(m + 2) as usize], (i as i64 - m).div_euclid(2))
+        });
+
+        let mut rows = Plane::new(width, self.height);
+        for y in 0..self.height {
+            for x in 0..width {
+                let value = taps(x).map(|(weight, source_x)| weight * self.get_clamped(source_x, y as i64)).sum();
+                rows.set(x, y, value);
+            }
+        }
+
+        let mut expanded = Plane::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = taps(y).map(|(weight, source_y)| weight * rows.get_clamped(x as i64, source_y)).sum();
+                expanded.set(x, y, value);
+            }
+        }
+
+        expanded
+    }
+}
+
+/// `plane` and `levels` successive reductions of it
+fn gaussian_pyramid(plane: Plane, levels: usize) -> Vec<Plane> {
+
+    let mut pyramid = vec![plane];
+
+    for _ in 0..levels {
+        let next = pyramid.last().unwrap().reduce();
+        pyramid.push(next);
+    }
+
+    pyramid
+}
+
+/// Blends photos by Laplacian pyramid: each level (frequency band) of a photo is weighted by
+/// the same level of its blend mask's Gaussian pyramid (see render_photos_with_multiband_blending)
+pub struct MultiBandBlender {
+    /// per level: (RGB) weighted band sums
+    sums: Vec<[Plane; 3]>,
+    /// per level: weight sums
+    weights: Vec<Plane>,
+}
+
+impl MultiBandBlender {
+
+    pub fn new(width: usize, height: usize) -> Self {
+
+        let weights = gaussian_pyramid(Plane::new(width, height), pyramid_levels(width, height));
+        let sums = weights.iter().map(|level| [level.clone(), level.clone(), level.clone()]).collect();
+
+        Self{sums, weights}
+    }
+
+    /// adds one photo:
+    /// * `color`: RGB, used where `coverage` is 1
+    /// * `coverage`: 1 on the photo, 0 elsewhere
+    /// * `weight`: its blend mask
+    pub fn add(&mut self, color: &[Plane; 3], coverage: &Plane, weight: &Plane) {
+
+        let levels = self.weights.len() - 1;
+
+        let coverage_pyramid = gaussian_pyramid(coverage.clone(), levels);
+        let weight_pyramid = gaussian_pyramid(weight.clone(), levels);
+
+        for (channel, color) in color.iter().enumerate() {
+
+            let premultiplied_pyramid = gaussian_pyramid(color.zip_map(coverage, |c, a| c * a), levels);
+
+            //beyond the photo's edges, colors are filled in from coarser levels,
+            //so its bands don't fade to black there (see multiband_fill.frag)
+            let mut filled: Vec<Plane> = Vec::new();
+            for (premultiplied, coverage) in premultiplied_pyramid.iter().zip(&coverage_pyramid).rev() {
+
+                let expanded = filled.last().map(|coarser| coarser.expand(premultiplied.width, premultiplied.height));
+
+                let mut level = premultiplied.zip_map(coverage, |c, a| if a > MIN_PYRAMID_WEIGHT {c / a} else {0.0});
+                if let Some(expanded) = &expanded {
+                    for ((value, &a), &e) in level.data.iter_mut().zip(&coverage.data).zip(&expanded.data) {
+                        if a <= MIN_PYRAMID_WEIGHT {
+                            *value = e;
+                        }
+                    }
+                }
+
+                //Laplacian band: this level's detail beyond the coarser level (see multiband_accumulate.frag)
+                let band = match &expanded {
+                    Some(expanded) => level.zip_map(expanded, |l, e| l - e),
+                    None => level.clone(),
+                };
+
+                let k = levels - filled.len();
+                let sum = &mut self.sums[k][channel];
+                for ((s, &b), &w) in sum.data.iter_mut().zip(&band.data).zip(&weight_pyramid[k].data) {
+                    *s += w * b;
+                }
+
+                filled.push(level);
+            }
+        }
+
+        for (weights, weight) in self.weights.iter_mut().zip(&weight_pyramid) {
+            for (sum, &w) in weights.data.iter_mut().zip(&weight.data) {
+                *sum += w;
+            }
+        }
+    }
+
+    /// collapses the blended pyramid into RGB (see multiband_collapse.frag)
+    pub fn result(&self) -> [Plane; 3] {
+
+        let collapse = |channel: usize| {
+
+            let mut result: Option<Plane> = None;
+
+            for (sums, weights) in self.sums.iter().zip(&self.weights).rev() {
+
+                let band = sums[channel].zip_map(weights, |s, w| if w > MIN_PYRAMID_WEIGHT {s / w} else {0.0});
+
+                result = Some(match result {
+                    Some(coarser) => band.zip_map(&coarser.expand(band.width, band.height), |b, c| b + c),
+                    None => band,
+                });
+            }
+
+            result.unwrap()
+        };
+
+        [collapse(0), collapse(1), collapse(2)]
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn pyramid_levels_test() {
+
+        assert_eq!(pyramid_levels(8, 8), 0);
+        assert_eq!(pyramid_levels(100, 16), 1);
+        assert_eq!(pyramid_levels(100, 32), 2);
+        assert_eq!(pyramid_levels(1920, 1080), 7);
+        assert_eq!(pyramid_levels(100_000, 100_000), MAX_PYRAMID_LEVELS);
+    }
+
+    #[test]
+    fn plane_test() {
+
+        //constant planes stay constant
+        let mut plane = Plane::new(5, 3);
+        plane.data.iter_mut().for_each(|value| *value = 2.0);
+
+        let reduced = plane.reduce();
+        assert_eq!((reduced.width, reduced.height), (3, 2));
+        reduced.data.iter().for_each(|&value| assert_approx_eq!(value, 2.0, 1e-6));
+
+        let expanded = reduced.expand(5, 3);
+        assert_eq!((expanded.width, expanded.height), (5, 3));
+        expanded.data.iter().for_each(|&value| assert_approx_eq!(value, 2.0, 1e-6));
+
+        //a horizontal ramp stays a ramp away from the edges
+        let mut ramp = Plane::new(16, 4);
+        for y in 0..4 {
+            for x in 0..16 {
+                ramp.set(x, y, x as f32);
+            }
+        }
+        let expanded = ramp.reduce().expand(16, 4);
+        for x in 4..12 {
+            assert_approx_eq!(expanded.get(x, 1), x as f32, 1e-4);
+        }
+    }
+
+    #[test]
+    fn multiband_blender_test() {
+
+        let (width, height) = (64, 32);
+
+        //two overlapping constant photos: left (0.2) and right (0.8), split at the middle
+        let photo = |value: f32, x_range: std::ops::Range<usize>, owned: std::ops::Range<usize>| {
+            let mut color = Plane::new(width, height);
+            let mut coverage = Plane::new(width, height);
+            let mut weight = Plane::new(width, height);
+            for y in 0..height {
+                for x in x_range.clone() {
+                    color.set(x, y, value);
+                    coverage.set(x, y, 1.0);
+                }
+                for x in owned.clone() {
+                    weight.set(x, y, 1.0);
+                }
+            }
+            ([color.clone(), color.clone(), color], coverage, weight)
+        };
+
+        let mut blender = MultiBandBlender::new(width, height);
+
+        let (color, coverage, weight) = photo(0.2, 0..40, 0..32);
+        blender.add(&color, &coverage, &weight);
+        let (color, coverage, weight) = photo(0.8, 24..64, 32..64);
+        blender.add(&color, &coverage, &weight);
+
+        let [red, green, _] = blender.result();
+
+        //far from the seam: each photo's own value
+        assert_approx_eq!(red.get(2, 16), 0.2, 0.02);
+        assert_approx_eq!(red.get(61, 16), 0.8, 0.02);
+
+        //a smooth transition across the seam
+        let row: Vec<f32> = (0..width).map(|x| red.get(x, 16)).collect();
+        assert!(row.windows(2).all(|pair| pair[1] >= pair[0] - 1e-4));
+        assert!(row[31] > 0.3 && row[32] < 0.7);
+        assert!((row[32] - row[31]).abs() < 0.2);
+
+        assert_eq!(red, green);
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use crate::read_pto::ControlPointPair;
+
+/// An unordered pair of image indices (`first <= second`)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub struct ImagePair {
+    pub first: u64,
+    pub second: u64,
+}
+
+impl ImagePair {
+
+    pub fn new(image_a: u64, image_b: u64) -> Self {
+
+        Self {
+            first: image_a.min(image_b),
+            second: image_a.max(image_b),
+        }
+    }
+
+    pub fn of(pair: &ControlPointPair) -> Self {
+
+        Self::new(pair.cp1.image_id, pair.cp2.image_id)
+    }
+}
+
+/// Control point pairs, grouped by the images they connect
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ControlPointStore {
+    pairs: BTreeMap<ImagePair, Vec<ControlPointPair>>,
+}
+
+impl ControlPointStore {
+
+    pub fn from_pairs(pairs: Vec<ControlPointPair>) -> Self {
+
+        let mut store = Self::default();
+
+        for pair in pairs {
+            store.pairs.entry(ImagePair::of(&pair)).or_insert_with(Vec::new).push(pair);
+        }
+
+        store
+    }
+
+    /// all control point pairs, ordered by image pair
+    pub fn all_pairs(&self) -> Vec<ControlPointPair> {
+
+        self.pairs.values().flatten().cloned().collect()
+    }
+
+    /// image pairs (in order) with their control point pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&ImagePair, &Vec<ControlPointPair>)> {
+
+        self.pairs.iter()
+    }
+
+    #[allow(dead_code)]
+    pub fn pairs(&self, image_pair: ImagePair) -> &[ControlPointPair] {
+
+        self.pairs.get(&image_pair).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn len(&self) -> usize {
+
+        self.pairs.values().map(Vec::len).sum()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::read_pto::{ControlPoint, ControlPointType};
+
+    fn pair(image_a: u64, image_b: u64, x: f64) -> ControlPointPair {
+
+        ControlPointPair {
+            cp1: ControlPoint::new(image_a, x, 1.0),
+            cp2: ControlPoint::new(image_b, x, 2.0),
+            point_type: ControlPointType::Normal,
+        }
+    }
+
+    #[test]
+    fn image_pair_test() {
+
+        assert_eq!(ImagePair::new(3, 1), ImagePair{first: 1, second: 3});
+        assert_eq!(ImagePair::new(1, 3), ImagePair::new(3, 1));
+        assert_eq!(ImagePair::of(&pair(2, 0, 0.0)), ImagePair::new(0, 2));
+    }
+
+    #[test]
+    fn control_point_store_test() {
+
+        let store = ControlPointStore::from_pairs(vec![
+            pair(1, 2, 10.0),
+            pair(0, 1, 20.0),
+            pair(2, 1, 30.0),
+            pair(3, 3, 40.0),
+        ]);
+
+        assert_eq!(store.len(), 4);
+
+        assert_eq!(
+            store.iter().map(|(image_pair, pairs)| (*image_pair, pairs.len())).collect::<Vec<_>>(),
+            vec![(ImagePair::new(0, 1), 1), (ImagePair::new(1, 2), 2), (ImagePair::new(3, 3), 1)]
+        );
+
+        assert_eq!(store.pairs(ImagePair::new(2, 1)), &[pair(1, 2, 10.0), pair(2, 1, 30.0)]);
+        assert_eq!(store.pairs(ImagePair::new(0, 2)), &[]);
+
+        assert_eq!(
+            store.all_pairs(),
+            vec![pair(0, 1, 20.0), pair(1, 2, 10.0), pair(2, 1, 30.0), pair(3, 3, 40.0)]
+        );
+
+        assert_eq!(ControlPointStore::default().len(), 0);
+    }
+}
@@ -1,5 +1,9 @@
 use three_d::Vec3;
 use crate::WorldCoords;
+use crate::ransac::RansacOptions;
+use crate::spherical::CameraPose;
+use crate::blend::BlendMode;
+use crate::lens::LensCorrection;
 
 
 #[derive(PartialEq, Debug)]
@@ -16,6 +20,20 @@ pub enum DewarpShader {
     Dewarp2,
 }
 
+impl DewarpShader {
+
+    /// the lens model this shader draws planar photos with
+    ///
+    /// (Dewarp1's `strength` uniform is never set, so it draws photos undistorted)
+    pub fn lens_correction(&self) -> LensCorrection {
+
+        match self {
+            DewarpShader::NoMorph | DewarpShader::Dewarp1 => LensCorrection::Off,
+            DewarpShader::Dewarp2 => LensCorrection::On,
+        }
+    }
+}
+
 pub struct Pan {
     pub mouse_start: (f64,f64),
     pub camera_start: Vec3,
@@ -24,6 +42,8 @@ pub struct Pan {
 pub struct Drag {
     pub mouse_start: (f64,f64),
     pub photo_start: WorldCoords,
+    /// for the spherical camera model
+    pub pose_start: CameraPose,
     pub photo_index: usize, //replace this
 }
 
@@ -36,6 +56,8 @@ pub struct RotateDrag {
     pub mouse_coords: WorldCoords,
     pub translate_start: WorldCoords,
     pub rotate_start: f32, //degrees
+    /// for the spherical camera model
+    pub pose_start: CameraPose,
     pub photo_index: usize,
 }
 
@@ -48,6 +70,8 @@ pub enum MouseTool {
     RotationPoint,
     DragToRotate,
     DragToRotateAllPhotos,
+    /// assign seam map cells to the selected photo
+    PaintSeams,
 }
 
 pub struct ControlState {
@@ -66,9 +90,31 @@ pub struct ControlState {
     pub mouse_location_ui_text: String,
     pub photo_ui_text: String,
     pub control_points_visible: bool,
+    pub residuals_visible: bool,
     pub photo_borders_visible: bool,
 
     pub alignment_mode: bool,
+    /// how overlapping photos are combined (except in alignment mode)
+    pub blend_mode: BlendMode,
+
+    pub seams_visible: bool,
+    /// in pixels
+    pub seam_brush_radius: f64,
+    /// the left mouse button is down with the PaintSeams tool
+    pub active_seam_paint: bool,
+
+    pub optimize_scale: bool,
+    pub optimize_lens: bool,
+    /// after optimizing alignment, estimate each lens' vignetting from the photos' overlaps
+    pub optimize_vignetting: bool,
+    pub ransac_options: RansacOptions,
+
+    /// lensfun database directory and lens query for the Selected Photo panel
+    pub lensfun_directory: String,
+    pub lensfun_maker: String,
+    pub lensfun_model: String,
+    /// in mm
+    pub lensfun_focal: f64,
 }
 
 impl Default for ControlState {
@@ -90,8 +136,24 @@ impl Default for ControlState {
             mouse_location_ui_text: "".to_string(),
             photo_ui_text: "".to_string(),
             control_points_visible: false,
+            residuals_visible: false,
             photo_borders_visible: true,
             alignment_mode: false,
+            blend_mode: BlendMode::default(),
+
+            seams_visible: true,
+            seam_brush_radius: 20.0,
+            active_seam_paint: false,
+
+            optimize_scale: false,
+            optimize_lens: false,
+            optimize_vignetting: false,
+            ransac_options: RansacOptions::default(),
+
+            lensfun_directory: "/usr/share/lensfun/version_1".to_string(),
+            lensfun_maker: "".to_string(),
+            lensfun_model: "".to_string(),
+            lensfun_focal: 50.0,
         }
     }
 }
\ No newline at end of file
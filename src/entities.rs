@@ -1,107 +1,268 @@
 use std::rc::Rc;
+use std::cell::{RefCell, RefMut};
+use std::path::Path;
 
-use three_d::{Loaded, Vec3, Context, ImageEffect, CullType};
-use three_d::definition::{Interpolation, Wrapping};
+use three_d::{Loaded, Context, ImageEffect, CullType, ColorTargetTexture2D, ClearState};
+use three_d::definition::{Interpolation, Wrapping, CPUTexture, Format};
 use three_d::definition::CPUMesh;
 use three_d::core::Texture2D;
 use three_d::object::Mesh;
 
-use log::info;
+use log::{info, warn};
+use image::{RgbaImage, DynamicImage, ImageBuffer};
 
 use crate::read_pto;
-use crate::photo::Photo;
-use crate::viewport_geometry::WorldCoords;
+use crate::read_pto::{PtoFile, PtoParseError, ImageVariable, ControlPointPair};
+use crate::write_pto;
+use crate::photo::{Photo, PhotoFields};
+use crate::viewport_geometry::{ViewportGeometry, WorldCoords, PixelCoords};
+use crate::project::{Project, ProjectView, PROJECT_VERSION};
+use crate::lens::{LensParameters, LensCorrection, Vignetting};
+use crate::lensfun::{LensfunDatabase, LensfunError};
+use crate::exif_metadata::ExifMetadata;
+use crate::image_orientation::ImageOrientation;
+use crate::spherical;
+use crate::spherical::{CameraModel, CameraPose, ProjectionKind, SphereProjection};
+use crate::media_paths;
+use crate::media_paths::MediaPaths;
+use crate::control_points::ControlPointStore;
+use crate::optimize;
+use crate::optimize::{AlignmentPhoto, AlignmentOptions, Alignment, AlignmentError};
+use crate::residuals::ResidualReport;
+use crate::ransac;
+use crate::ransac::RansacOptions;
+use crate::seams::SeamMap;
+use crate::blend;
+use crate::stitch;
+use crate::stitch::SourcePhoto;
+
+/// the longest side in pixels of photos' sample images (for finding seams, color and vignetting corrections)
+const MAX_SAMPLE_IMAGE_SIDE: u32 = 512;
 
 
 pub struct Entities {
 
-    pub image0_control_points: Vec<Vec3>,
-    pub image1_control_points: Vec<Vec3>,
+    pub control_points: ControlPointStore,
+    /// control point pairs classified as outliers by the last `find_outliers`
+    pub outliers: Vec<ControlPointPair>,
     pub photos: Vec<Photo>,
+    /// each lens' vignetting, by lens id (see Photo::lens_id)
+    pub lens_vignetting: Vec<Vignetting>,
+    /// how photos are placed in the world
+    pub camera_model: CameraModel,
+    /// found or painted seams: where exported panoramas are cut (see `find_seams`)
+    pub seam_map: Option<SeamMap>,
+    pub pto_file: PtoFile,
+    pub pto_file_path: Option<String>,
+    pub pto_file_warnings: Vec<PtoParseError>,
+    pub project_file_path: String,
     pub photos_alignment_string: String,
     pub photos_alignment_alt_string: String,
     pub color_mesh: Mesh,
     pub line_mesh: Mesh,
-    pub overlay_mesh: Rc<LoadedImageMesh>,
+    pub overlay_mesh: Option<Rc<LoadedImageMesh>>,
     pub average_effect: ImageEffect,
     pub copy_photos_effect: ImageEffect,
+    pub multiband_effects: MultiBandEffects,
+    /// lens profiles loaded by `load_lensfun_database`
+    pub lensfun_database: LensfunDatabase,
+}
+
+/// The image effects of multi-band blending (see render_photos_with_multiband_blending)
+pub struct MultiBandEffects {
+    pub reduce: ImageEffect,
+    pub expand: ImageEffect,
+    pub prepare: ImageEffect,
+    pub fill: ImageEffect,
+    pub accumulate: ImageEffect,
+    pub collapse: ImageEffect,
+    /// intermediate textures for the last viewport size
+    textures: RefCell<Option<MultiBandTextures>>,
+}
+
+impl MultiBandEffects {
+
+    fn new(context: &Context) -> Result<Self, three_d::Error> {
+
+        Ok(Self {
+            reduce: ImageEffect::new(context, include_str!("shaders/pyramid_reduce.frag"))?,
+            expand: ImageEffect::new(context, include_str!("shaders/pyramid_expand.frag"))?,
+            prepare: ImageEffect::new(context, include_str!("shaders/multiband_prepare.frag"))?,
+            fill: ImageEffect::new(context, include_str!("shaders/multiband_fill.frag"))?,
+            accumulate: ImageEffect::new(context, include_str!("shaders/multiband_accumulate.frag"))?,
+            collapse: ImageEffect::new(context, include_str!("shaders/multiband_collapse.frag"))?,
+            textures: RefCell::new(None),
+        })
+    }
+
+    /// the intermediate textures for a `width` x `height` viewport: recreated only when its size changes
+    pub fn textures(&self, context: &Context, width: usize, height: usize) -> Result<RefMut<'_, MultiBandTextures>, three_d::Error> {
+
+        let mut textures = self.textures.borrow_mut();
+
+        if textures.as_ref().map_or(true, |textures| textures.size != (width, height)) {
+            //release the old textures first
+            *textures = None;
+            *textures = Some(MultiBandTextures::new(context, width, height)?);
+        }
+
+        Ok(RefMut::map(textures, |textures| textures.as_mut().unwrap()))
+    }
+}
+
+/// The intermediate textures of multi-band blending: full size, and pyramids with a texture per level
+pub struct MultiBandTextures {
+    /// (width, height) of the full size textures
+    pub size: (usize, usize),
+    /// the largest edge distance of all photos
+    pub edge: ColorTargetTexture2D<f32>,
+    /// one rendered photo
+    pub photo: ColorTargetTexture2D<f32>,
+    /// per photo: (premultiplied color, coverage) and blend mask Gaussian pyramids
+    pub color: Vec<ColorTargetTexture2D<f32>>,
+    pub weight: Vec<ColorTargetTexture2D<f32>>,
+    pub filled: Vec<ColorTargetTexture2D<f32>>,
+    /// the coarsest level stays 0
+    pub expanded: Vec<ColorTargetTexture2D<f32>>,
+    /// (weighted band sums, weight sums) of all photos
+    pub accumulated: Vec<ColorTargetTexture2D<f32>>,
+    pub collapsed: Vec<ColorTargetTexture2D<f32>>,
+}
+
+impl MultiBandTextures {
+
+    fn new(context: &Context, width: usize, height: usize) -> Result<Self, three_d::Error> {
+
+        let mut sizes = vec![(width, height)];
+        for _ in 0..blend::pyramid_levels(width, height) {
+            let &(width, height) = sizes.last().unwrap();
+            sizes.push(((width + 1) / 2, (height + 1) / 2));
+        }
+
+        let new_pyramid = || -> Result<Vec<ColorTargetTexture2D<f32>>, three_d::Error> {
+            sizes.iter().map(|&(width, height)| float_texture(context, width, height)).collect()
+        };
+
+        Ok(Self {
+            size: (width, height),
+            edge: float_texture(context, width, height)?,
+            photo: float_texture(context, width, height)?,
+            color: new_pyramid()?,
+            weight: new_pyramid()?,
+            filled: new_pyramid()?,
+            expanded: new_pyramid()?,
+            accumulated: new_pyramid()?,
+            collapsed: new_pyramid()?,
+        })
+    }
+}
+
+/// a cleared RGBA texture for intermediate results
+fn float_texture(context: &Context, width: usize, height: usize) -> Result<ColorTargetTexture2D<f32>, three_d::Error> {
+
+    let texture = ColorTargetTexture2D::<f32>::new(
+        context,
+        width,
+        height,
+        Interpolation::Nearest,
+        Interpolation::Nearest,
+        None,
+        Wrapping::ClampToEdge,
+        Wrapping::ClampToEdge,
+        Format::RGBA,
+    )?;
+
+    texture.write(ClearState::color(0.0, 0.0, 0.0, 0.0), || Ok(()))?;
+    Ok(texture)
 }
 
 impl Entities {
 
-    pub fn new(
-        context: &Context,
-        loaded: &Loaded,
-        pto_file: &str,
-        photos_alignment_string_file: &str,
-        photos_alignment_alt_string_file: &str,
-        map_overlay_image: &str,
-        photo_images: &Vec<&str>) -> Entities
+    pub fn new(context: &Context, loaded: &Loaded, media_paths: &MediaPaths) -> Result<Entities, Box<dyn std::error::Error>>
     {
-        let file_u8 = loaded.bytes(photos_alignment_string_file).unwrap();
-        let photos_alignment_string = std::str::from_utf8(file_u8).unwrap().to_string();
+        let optional_string = |path: &Option<String>| -> Result<String, Box<dyn std::error::Error>> {
+            match path {
+                Some(path) => loaded_string(loaded, path),
+                None => Ok(String::new()),
+            }
+        };
 
-        let file_u8 = loaded.bytes(photos_alignment_alt_string_file).unwrap();
-        let photos_alignment_alt_string = std::str::from_utf8(file_u8).unwrap().to_string();
+        let photos_alignment_string = optional_string(&media_paths.photos_alignment_string_file)?;
+        let photos_alignment_alt_string = optional_string(&media_paths.photos_alignment_alt_string_file)?;
+        let s = optional_string(&media_paths.pto_file)?;
 
-        let file_u8 = loaded.bytes(pto_file).unwrap();
-        let s = std::str::from_utf8(file_u8).unwrap();
+        let (pto_file_contents, pto_file_warnings) = read_pto::read_pto_file_lenient(&s)?;
 
-        let pairs = read_pto::read_control_point_pairs(s).unwrap();
+        for warning in &pto_file_warnings {
+            warn!("skipped invalid PTO line: {}", warning);
+        }
 
-        for (ref cp1, ref cp2) in &(*pairs) {
-            info!("({:?}, {:?})", cp1, cp2);
+        for image in pto_file_contents.images() {
+            info!("pto image: {}", image.file_name);
+        }
+
+        let pairs = pto_file_contents.control_point_pairs();
+
+        for ControlPointPair{ref cp1, ref cp2, ref point_type} in &(*pairs) {
+            info!("({:?}, {:?}, {:?})", cp1, cp2, point_type);
         }
 
         info!("pairs size: {}", (*pairs).len());
 
-        let image0_control_points =
-            pairs.iter().filter_map(|(cp1, _cp2)| {
-                match cp1.image_id {
-                    0 => Some(Vec3::new(cp1.x_coord as f32, cp1.y_coord as f32, 0 as f32)),
-                    _ => None,
-                }
-            }).collect::<Vec<Vec3>>();
+        let control_points = ControlPointStore::from_pairs(pairs);
 
-        for &Vec3 { x, y, z } in &image0_control_points {
-            info!("({:?}, {:?}, {:?})", x, y, z);
+        for (image_pair, pairs) in control_points.iter() {
+            info!("images {} and {}: {} control point pairs", image_pair.first, image_pair.second, pairs.len());
         }
 
-        let image1_control_points =
-            pairs.iter().filter_map(|(_cp1, cp2)| {
-                match cp2.image_id {
-                    1 => Some(Vec3::new(cp2.x_coord as f32, cp2.y_coord as f32, 0 as f32)),
-                    _ => None,
-                }
-            }).collect::<Vec<Vec3>>();
+        let mut photos = Vec::new();
 
-        let meshes: Vec<Rc<LoadedImageMesh>> = photo_images.iter().map(|x| {
-            Rc::new(load_mesh_from_filepath(&context, loaded, x))
-        }).collect();
+        for path in &media_paths.photo_images {
+            let metadata = loaded_exif_metadata(loaded, path);
+            let mesh = Rc::new(load_mesh_from_filepath(&context, loaded, path, metadata.image_orientation())?);
+            photos.push(Photo::from_loaded_image_mesh(mesh, path, metadata));
+        }
 
-        let mut photos: Vec<Photo> = meshes.iter().map(|mesh| {
-            Photo::from_loaded_image_mesh(mesh.clone())
-        }).collect();
+        let lens_ids = photo_lens_ids(&pto_file_contents, &photos.iter().map(|photo| &photo.metadata).collect::<Vec<_>>());
+        for (photo, lens_id) in photos.iter_mut().zip(lens_ids) {
+            photo.lens_id = lens_id;
+        }
 
-        photos.get_mut(1).map(
-            |photo| photo.set_translation(WorldCoords { x: 500.0, y: 0.0 })
-        );
+        //lens ids are numbered in order of first appearance: each lens' vignetting is its first photo's
+        let mut lens_vignetting = Vec::new();
+        for (index, photo) in photos.iter().enumerate() {
+            if photo.lens_id == lens_vignetting.len() {
+                lens_vignetting.push(pto_vignetting(&pto_file_contents, index).unwrap_or_default());
+            }
+        }
 
-        photos.get_mut(2).map(
-            |photo| photo.set_translation(WorldCoords { x: 1000.0, y: 0.0 })
-        );
+        //initial layout: a row of photos
+        for (i, photo) in photos.iter_mut().enumerate() {
+            photo.set_translation(WorldCoords { x: i as f64 * 500.0, y: 0.0 });
+        }
 
         let color_mesh = color_mesh(&context);
         let line_mesh = line_mesh(&context);
-        let overlay_mesh = Rc::new(load_mesh_from_filepath(&context, loaded, map_overlay_image));
+        let overlay_mesh = match &media_paths.map_overlay_image {
+            Some(path) => Some(Rc::new(load_mesh_from_filepath(&context, loaded, path, ImageOrientation::Normal)?)),
+            None => None,
+        };
 
         let average_effect = ImageEffect::new(context, include_str!("shaders/average_effect.frag")).unwrap();
         let copy_photos_effect = ImageEffect::new(context, include_str!("shaders/copy_photos.frag")).unwrap();
+        let multiband_effects = MultiBandEffects::new(context).unwrap();
 
         let mut entities = Entities{
-            image0_control_points,
-            image1_control_points,
+            control_points,
+            outliers: Vec::new(),
             photos,
+            lens_vignetting,
+            camera_model: CameraModel::Planar,
+            seam_map: None,
+            pto_file: pto_file_contents,
+            pto_file_path: media_paths.pto_file.clone(),
+            pto_file_warnings,
+            project_file_path: project_file_path(media_paths),
             photos_alignment_string,
             photos_alignment_alt_string,
             color_mesh,
@@ -109,10 +270,21 @@ impl Entities {
             overlay_mesh,
             average_effect,
             copy_photos_effect,
+            multiband_effects,
+            lensfun_database: LensfunDatabase::default(),
         };
 
-        entities.set_photos_from_json_serde_string(&entities.photos_alignment_string.clone()).unwrap();
-        entities
+        for index in 0..entities.photos.len() {
+            if let Some(lens) = entities.lens_parameters(index) {
+                entities.photos[index].lens = lens;
+            }
+            if let Some(pose) = entities.camera_pose(index) {
+                entities.photos[index].pose = pose;
+            }
+        }
+
+        entities.set_photos_from_json_serde_string(&entities.photos_alignment_string.clone())?;
+        Ok(entities)
     }
 
     pub fn set_photos_from_json_serde_string(&mut self, s: &str) -> Result<(), Box<dyn std::error::Error>> {
@@ -121,31 +293,610 @@ impl Entities {
 
             if let Some(photo) = self.photos.get_mut(index) {
                 photo.set_from_json_serde_string(line)?;
+                self.clear_seams();
+            };
+        }
+        Ok(())
+    }
+
+    /// clears the seam map, after photos are moved (or their lenses or visibility change): its cells no longer match the photos
+    pub fn clear_seams(&mut self) {
+
+        if self.seam_map.take().is_some() {
+            info!("photos moved: cleared seams");
+        }
+    }
+
+    /// sets the PTO image lines' yaw, pitch, and roll from the current photo poses
+    /// (or in the planar camera model, approximated from the photo orientations),
+    /// and in the spherical camera model, the 'p' line's output projection (unless it is unsupported)
+    ///
+    /// photos and PTO images are matched by index
+    pub fn update_pto_file_from_photos(&mut self) {
+
+        let camera_model = self.camera_model;
+
+        for index in 0..self.photos.len() {
+
+            let photo = &self.photos[index];
+
+            let pose = match camera_model {
+                CameraModel::Planar =>
+                    self.lens_parameters(index)
+                        .and_then(|lens| photo.pto_angles(lens.fov))
+                        .map(|(yaw, pitch, roll)| CameraPose{yaw, pitch, roll}),
+                CameraModel::Spherical(_) => Some(photo.pose),
             };
+
+            if let Some(pose) = pose {
+                set_pto_camera_pose(&mut self.pto_file, index, photo.image_orientation, pose);
+            }
+        }
+
+        if let CameraModel::Spherical(projection) = camera_model {
+            set_pto_sphere_projection(&mut self.pto_file, projection.kind);
+        }
+
+        for (index, photo) in self.photos.iter().enumerate() {
+            set_pto_lens_parameters(&mut self.pto_file, index, photo.image_orientation, photo.lens);
+            let vignetting = self.vignetting(photo.lens_id);
+            set_pto_vignetting(&mut self.pto_file, index, vignetting);
+        }
+    }
+
+    /// the image orientation of the photo matching a PTO image
+    fn image_orientation(&self, image_index: usize) -> ImageOrientation {
+
+        self.photos.get(image_index).map_or(ImageOrientation::Normal, |photo| photo.image_orientation)
+    }
+
+    /// gets the resolved lens parameters of a PTO image
+    fn lens_parameters(&self, image_index: usize) -> Option<LensParameters> {
+
+        pto_lens_parameters(&self.pto_file, image_index, self.image_orientation(image_index))
+    }
+
+    /// gets the resolved yaw, pitch, and roll of a PTO image
+    fn camera_pose(&self, image_index: usize) -> Option<CameraPose> {
+
+        pto_camera_pose(&self.pto_file, image_index, self.image_orientation(image_index))
+    }
+
+    /// the current sphere projection, or a new one for the loaded PTO file (see `pto_sphere_projection`)
+    pub fn sphere_projection(&self) -> SphereProjection {
+
+        match self.camera_model {
+            CameraModel::Spherical(projection) => projection,
+            CameraModel::Planar => {
+                let first_photo = self.photos.first().map(|photo| (photo.image_width, photo.lens.fov));
+                pto_sphere_projection(&self.pto_file, first_photo)
+            },
+        }
+    }
+
+    /// sets every photo's camera pose from its (planar) orientation
+    pub fn set_poses_from_orientations(&mut self) {
+
+        for photo in &mut self.photos {
+            if let Some(pose) = photo.pose_from_orientation() {
+                photo.pose = pose;
+            }
+        }
+        self.clear_seams();
+    }
+
+    /// moves (or in the spherical camera model, turns) all unlocked photos (except the anchor) to fit the control points
+    /// (and optionally calibrates their lenses: vignetting is estimated afterwards, from the aligned photos' sample images)
+    pub fn optimize_alignment(&mut self, options: AlignmentOptions) -> Result<Alignment, AlignmentError> {
+
+        let photos: Vec<AlignmentPhoto> =
+        self.photos.iter().map(|photo| {
+            AlignmentPhoto {
+                orientation: photo.orientation().clone(),
+                image_width: photo.image_width,
+                image_height: photo.image_height,
+                image_orientation: photo.image_orientation,
+                lens: photo.lens,
+                lens_id: Some(photo.lens_id),
+                pose: photo.pose,
+                locked: photo.locked,
+            }
+        }).collect();
+
+        let alignment = match self.camera_model {
+            CameraModel::Planar => optimize::optimize_alignment(&photos, &self.control_points.all_pairs(), options)?,
+            CameraModel::Spherical(_) => optimize::optimize_spherical_alignment(&photos, &self.control_points.all_pairs(), options)?,
+        };
+
+        for (((photo, orientation), pose), lens) in self.photos.iter_mut().zip(&alignment.orientations).zip(&alignment.poses).zip(&alignment.lenses) {
+            photo.set_orientation(orientation.clone());
+            photo.pose = *pose;
+            photo.lens = *lens;
+        }
+        self.clear_seams();
+
+        if options.optimize_vignetting {
+            self.estimate_vignetting();
+        }
+
+        Ok(alignment)
+    }
+
+    /// the vignetting of the lens `lens_id` (none if it has no saved vignetting)
+    pub fn vignetting(&self, lens_id: usize) -> Vignetting {
+
+        self.lens_vignetting.get(lens_id).copied().unwrap_or_default()
+    }
+
+    /// sets the lens parameters of every photo taken with the lens `lens_id` (clearing seams if they change)
+    pub fn set_lens(&mut self, lens_id: usize, lens: LensParameters) {
+
+        let mut changed = false;
+        for photo in self.photos.iter_mut().filter(|photo| photo.lens_id == lens_id) {
+            changed |= photo.lens != lens;
+            photo.lens = lens;
+        }
+        if changed {
+            self.clear_seams();
+        }
+    }
+
+    /// loads a lensfun XML database directory, returns the number of lenses
+    pub fn load_lensfun_database(&mut self, path: &str) -> Result<usize, LensfunError> {
+
+        self.lensfun_database = LensfunDatabase::load_directory(path)?;
+        Ok(self.lensfun_database.lenses.len())
+    }
+
+    /// the control point residuals of photos drawn with `lens_correction`
+    pub fn residual_report(&self, lens_correction: LensCorrection) -> ResidualReport {
+
+        ResidualReport::new(&self.control_points, |cp| {
+            self.photos.get(cp.image_id as usize).and_then(|photo| {
+                photo.world_coords(&self.camera_model, lens_correction, PixelCoords{x: cp.x_coord, y: cp.y_coord})
+            })
+        })
+    }
+
+    /// classifies control point pairs with RANSAC, returns the number of outliers
+    pub fn find_outliers(&mut self, options: RansacOptions) -> usize {
+
+        self.outliers = ransac::find_outliers(&self.control_points, options);
+        self.outliers.len()
+    }
+
+    /// removes the outliers found by `find_outliers` from the project, returns the number removed
+    pub fn prune_outliers(&mut self) -> usize {
+
+        let pairs = self.control_points.all_pairs();
+        let count = pairs.len();
+
+        let pairs: Vec<ControlPointPair> = pairs.into_iter().filter(|pair| !self.outliers.contains(pair)).collect();
+        let removed = count - pairs.len();
+
+        self.set_control_point_pairs(pairs);
+        removed
+    }
+
+    pub fn set_control_point_pairs(&mut self, pairs: Vec<ControlPointPair>) {
+
+        self.pto_file.set_control_point_pairs(pairs.clone());
+        self.control_points = ControlPointStore::from_pairs(pairs);
+        self.outliers.clear();
+    }
+
+    /// (photo source paths are stored relative to the project file)
+    pub fn project(&self, viewport_geometry: &ViewportGeometry) -> Project {
+
+        Project {
+            version: PROJECT_VERSION,
+            photos: self.photos.iter().map(|photo| PhotoFields {
+                source_path: media_paths::project_relative_image_path(&self.project_file_path, &photo.source_path),
+                ..photo.fields()
+            }).collect(),
+            lens_vignetting: self.lens_vignetting.clone(),
+            control_point_pairs: self.control_points.all_pairs(),
+            view: ProjectView {
+                camera_position: viewport_geometry.camera_position,
+                zoom_value: viewport_geometry.zoom_value,
+            },
+            camera_model: self.camera_model,
+            seam_map: self.seam_map.clone(),
         }
+    }
+
+    /// applies a project to the loaded photos (matched by image path, relative to the project file)
+    pub fn set_project(&mut self, project: &Project, viewport_geometry: &mut ViewportGeometry) {
+
+        let relative_paths: Vec<String> = self.photos.iter()
+            .map(|photo| media_paths::project_relative_image_path(&self.project_file_path, &photo.source_path))
+            .collect();
+
+        //project photo index -> loaded photo index
+        let mut photo_indices = Vec::new();
+
+        for project_photo in &project.photos {
+
+            let index = relative_paths.iter().position(|path| *path == project_photo.source_path);
+
+            match index {
+                Some(index) => self.photos[index].set_fields(project_photo),
+                None => warn!("project photo is not loaded: {}", project_photo.source_path),
+            }
+
+            photo_indices.push(index);
+        }
+
+        let remap = |index: usize| photo_indices.get(index).copied().flatten();
+
+        for (photo, path) in self.photos.iter().zip(&relative_paths) {
+            if !project.photos.iter().any(|project_photo| project_photo.source_path == *path) {
+                warn!("loaded photo is not in project: {}", photo.source_path);
+            }
+        }
+
+        //pairs and seams of photos which aren't loaded are dropped
+        self.set_control_point_pairs(project.control_point_pairs.iter().filter_map(|pair| pair.remap_images(remap)).collect());
+        self.lens_vignetting = project.lens_vignetting.clone();
+        self.camera_model = project.camera_model;
+        self.seam_map = project.seam_map.clone();
+        if let Some(seam_map) = &mut self.seam_map {
+            seam_map.remap_labels(remap);
+        }
+
+        viewport_geometry.camera_position = project.view.camera_position;
+        viewport_geometry.zoom_value = project.view.zoom_value.clamp(viewport_geometry.zoom_min, viewport_geometry.zoom_max);
+    }
+
+    pub fn save_project_file(&self, viewport_geometry: &ViewportGeometry) -> Result<(), Box<dyn std::error::Error>> {
+
+        let s = self.project(viewport_geometry).to_json_string()?;
+        std::fs::write(&self.project_file_path, s)?;
         Ok(())
     }
 
+    pub fn load_project_file(&mut self, viewport_geometry: &mut ViewportGeometry) -> Result<(), Box<dyn std::error::Error>> {
+
+        let s = std::fs::read_to_string(&self.project_file_path)?;
+        let project = Project::from_json_str(&s)?;
+        self.set_project(&project, viewport_geometry);
+        Ok(())
+    }
+
+    /// finds seams between the visible photos (see stitch::find_seams): from their sample images
+    pub fn find_seams(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+
+        self.seam_map = Some(stitch::find_seams(&self.source_photos(), &self.camera_model).ok_or("no visible photos")?);
+        Ok(())
+    }
+
+    /// replaces the color corrections of the visible photos which overlap others with ones that make them match there
+    /// (see stitch::estimate_color_corrections): from their sample images
+    pub fn estimate_color_corrections(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+
+        if !self.photos.iter().any(|photo| photo.visible) {
+            return Err("no visible photos".into());
+        }
+
+        let corrections = stitch::estimate_color_corrections(&self.source_photos(), &self.camera_model);
+
+        for (photo, correction) in self.photos.iter_mut().zip(corrections) {
+            if let Some(correction) = correction {
+                photo.color_correction = correction;
+            }
+        }
+        Ok(())
+    }
+
+    /// replaces each lens' vignetting with one that makes the photos match where they overlap
+    /// (see stitch::estimate_vignetting): from their sample images
+    fn estimate_vignetting(&mut self) {
+
+        let vignettings = stitch::estimate_vignetting(&self.source_photos(), &self.camera_model);
+
+        for (index, vignetting) in vignettings.into_iter().enumerate() {
+            let lens_id = self.photos[index].lens_id;
+
+            if lens_id >= self.lens_vignetting.len() {
+                self.lens_vignetting.resize(lens_id + 1, Vignetting::default());
+            }
+            self.lens_vignetting[lens_id] = vignetting;
+        }
+    }
+
+    /// the photos with their (downscaled) sample images, for sampling colors without reading their image files
+    fn source_photos(&self) -> Vec<SourcePhoto> {
+
+        self.photos.iter().map(|photo| SourcePhoto {
+            fields: photo.fields(),
+            image_orientation: photo.image_orientation,
+            vignetting: self.vignetting(photo.lens_id),
+            image: photo.loaded_image_mesh.sample_image.clone(),
+        }).collect()
+    }
+
+    /// writes the current project next to the loaded PTO file, returns the new file's path
+    pub fn save_pto_file(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+
+        self.update_pto_file_from_photos();
+
+        let pto_file_path = self.pto_file_path.as_ref().ok_or("no PTO file is loaded")?;
+        let path = sibling_file_path(pto_file_path, ".pto", ".edited.pto");
+
+        std::fs::write(&path, write_pto::write_pto_file(&self.pto_file)?)?;
+        Ok(path)
+    }
+
+}
+
+
+/// the images a PTO image's lens (v, a, b, and c) resolves to: None if its links don't resolve
+fn pto_lens_key(pto_file: &PtoFile, image_index: usize) -> Option<Vec<usize>> {
+
+    let variables: [fn(&read_pto::Image) -> ImageVariable; 4] = [|image| image.fov, |image| image.a, |image| image.b, |image| image.c];
+
+    variables.iter().map(|&variable| pto_file.image_variable_source(image_index, variable)).collect()
+}
+
+/// a lens id for each PTO image: images share a lens if their v, a, b, and c are linked
+pub fn pto_lens_ids(pto_file: &PtoFile) -> Vec<usize> {
+
+    dense_ids((0..pto_file.images().len()).map(|index| pto_lens_key(pto_file, index)))
+}
+
+/// a lens id for each photo: photos share a lens if their PTO images' v, a, b, and c are linked,
+/// or (without a PTO image) if their EXIF camera, lens, and focal length match
+fn photo_lens_ids(pto_file: &PtoFile, photos_metadata: &[&ExifMetadata]) -> Vec<usize> {
+
+    #[derive(PartialEq)]
+    enum LensKey<'a> {
+        Pto(Vec<usize>),
+        Exif(&'a Option<String>, &'a Option<String>, &'a Option<String>, f64),
+    }
+
+    dense_ids(photos_metadata.iter().enumerate().map(|(index, metadata)| {
+        match pto_lens_key(pto_file, index) {
+            Some(key) => Some(LensKey::Pto(key)),
+            None => metadata.focal_length.map(|focal_length| LensKey::Exif(&metadata.make, &metadata.model, &metadata.lens_model, focal_length)),
+        }
+    }))
+}
+
+/// numbers distinct keys in order of first appearance (None keys are all distinct)
+fn dense_ids<K: PartialEq>(keys: impl Iterator<Item = Option<K>>) -> Vec<usize> {
+
+    let mut distinct_keys: Vec<Option<K>> = Vec::new();
+
+    keys.map(|key| {
+        match distinct_keys.iter().position(|distinct_key| key.is_some() && *distinct_key == key) {
+            Some(id) => id,
+            None => {
+                distinct_keys.push(key);
+                distinct_keys.len() - 1
+            },
+        }
+    }).collect()
+}
+
+/// the stored image dimensions of a PTO image
+fn pto_image_dimensions(pto_file: &PtoFile, image_index: usize) -> Option<(u32, u32)> {
+
+    pto_file.images().get(image_index).map(|image| (image.width as u32, image.height as u32))
+}
+
+/// gets the resolved lens parameters of a PTO image
+///
+/// (PTO files give the stored image's field of view: `fov` is converted to the upright image's)
+pub fn pto_lens_parameters(pto_file: &PtoFile, image_index: usize, image_orientation: ImageOrientation) -> Option<LensParameters> {
+
+    let variable = |v: fn(&read_pto::Image) -> ImageVariable| {
+        pto_file.resolve_image_variable(image_index, v)
+    };
+
+    let (stored_width, stored_height) = pto_image_dimensions(pto_file, image_index)?;
+
+    Some(LensParameters {
+        fov: image_orientation.upright_fov(variable(|image| image.fov)?, stored_width, stored_height),
+        a: variable(|image| image.a)?,
+        b: variable(|image| image.b)?,
+        c: variable(|image| image.c)?,
+        d: variable(|image| image.d)?,
+        e: variable(|image| image.e)?,
+    })
+}
+
+/// sets a PTO image's lens parameters (only changed values are set, to preserve links)
+///
+/// (`lens.fov` is converted to the stored image's field of view)
+pub fn set_pto_lens_parameters(pto_file: &mut PtoFile, image_index: usize, image_orientation: ImageOrientation, lens: LensParameters) {
+
+    let current = pto_lens_parameters(pto_file, image_index, image_orientation);
+
+    if let Some(image) = pto_file.images_mut().into_iter().nth(image_index) {
+
+        let set = |variable: &mut ImageVariable, current: Option<f64>, value: f64, pto_value: f64| {
+            if current != Some(value) {
+                *variable = ImageVariable::Value(pto_value);
+            }
+        };
+
+        let stored_fov = image_orientation.stored_fov(lens.fov, image.width as u32, image.height as u32);
+
+        set(&mut image.fov, current.map(|c| c.fov), lens.fov, stored_fov);
+        set(&mut image.a, current.map(|c| c.a), lens.a, lens.a);
+        set(&mut image.b, current.map(|c| c.b), lens.b, lens.b);
+        set(&mut image.c, current.map(|c| c.c), lens.c, lens.c);
+        set(&mut image.d, current.map(|c| c.d), lens.d, lens.d);
+        set(&mut image.e, current.map(|c| c.e), lens.e, lens.e);
+    }
+}
+
+/// gets the resolved vignetting (Va, Vb, Vc, Vd) of a PTO image
+pub fn pto_vignetting(pto_file: &PtoFile, image_index: usize) -> Option<Vignetting> {
+
+    let variable = |name: &str, default: f64| {
+        pto_file.resolve_image_variable(image_index, |image| image.other_variable(name, default))
+    };
+
+    Some(Vignetting {
+        a: variable("Va", 1.0)?,
+        b: variable("Vb", 0.0)?,
+        c: variable("Vc", 0.0)?,
+        d: variable("Vd", 0.0)?,
+    })
+}
+
+/// sets a PTO image's vignetting (only changed values are set, to preserve links)
+pub fn set_pto_vignetting(pto_file: &mut PtoFile, image_index: usize, vignetting: Vignetting) {
+
+    let current = pto_vignetting(pto_file, image_index);
+
+    if let Some(image) = pto_file.images_mut().into_iter().nth(image_index) {
+
+        let values = [("Va", vignetting.a), ("Vb", vignetting.b), ("Vc", vignetting.c), ("Vd", vignetting.d)];
+        let current = current.map(|c| [c.a, c.b, c.c, c.d]);
+
+        for (i, &(name, value)) in values.iter().enumerate() {
+            if current.map(|current| current[i]) != Some(value) {
+                image.set_other_variable(name, ImageVariable::Value(value));
+            }
+        }
+    }
+}
+
+/// gets the resolved yaw, pitch, and roll of a PTO image
+///
+/// (PTO files give the stored image's roll: it is converted to the upright image's)
+pub fn pto_camera_pose(pto_file: &PtoFile, image_index: usize, image_orientation: ImageOrientation) -> Option<CameraPose> {
+
+    let variable = |v: fn(&read_pto::Image) -> ImageVariable| {
+        pto_file.resolve_image_variable(image_index, v)
+    };
+
+    Some(CameraPose {
+        yaw: variable(|image| image.yaw)?,
+        pitch: variable(|image| image.pitch)?,
+        roll: image_orientation.upright_roll(variable(|image| image.roll)?),
+    })
+}
+
+/// sets a PTO image's yaw, pitch, and roll (only changed values are set, to preserve links)
+///
+/// (`pose.roll` is converted to the stored image's roll)
+pub fn set_pto_camera_pose(pto_file: &mut PtoFile, image_index: usize, image_orientation: ImageOrientation, pose: CameraPose) {
+
+    let current = pto_camera_pose(pto_file, image_index, image_orientation);
+
+    if let Some(image) = pto_file.images_mut().into_iter().nth(image_index) {
+
+        let set = |variable: &mut ImageVariable, current: Option<f64>, value: f64, pto_value: f64| {
+            if current != Some(value) {
+                *variable = ImageVariable::Value(pto_value);
+            }
+        };
+
+        set(&mut image.yaw, current.map(|c| c.yaw), pose.yaw, pose.yaw);
+        set(&mut image.pitch, current.map(|c| c.pitch), pose.pitch, pose.pitch);
+        set(&mut image.roll, current.map(|c| c.roll), pose.roll, image_orientation.stored_roll(pose.roll));
+    }
+}
+
+/// a sphere projection in the PTO file's output projection (if supported),
+/// sized so the first photo's center is about 1 WorldCoords unit per pixel
+///
+/// `first_photo` is the first photo's upright (width in pixels, fov in degrees)
+pub fn pto_sphere_projection(pto_file: &PtoFile, first_photo: Option<(u32, f64)>) -> SphereProjection {
+
+    let scale = first_photo.map_or(1000.0, |(image_width, fov)| spherical::focal_length(image_width, fov));
+    let kind = match pto_file.panorama() {
+        Some(panorama) => ProjectionKind::from_pto(panorama.projection).unwrap_or_else(|| {
+            warn!("unsupported PTO output projection f{}: showing {:?}", panorama.projection, ProjectionKind::default());
+            ProjectionKind::default()
+        }),
+        None => ProjectionKind::default(),
+    };
+
+    SphereProjection{scale, kind}
+}
+
+/// sets the PTO file's output projection, unless its current one is unsupported (it is kept)
+pub fn set_pto_sphere_projection(pto_file: &mut PtoFile, kind: ProjectionKind) {
+
+    if let Some(panorama) = pto_file.panorama_mut() {
+        if ProjectionKind::from_pto(panorama.projection).is_some() {
+            panorama.projection = kind.pto_projection();
+        }
+    }
 }
 
+/// replaces `extension` at the end of `path` with `new_suffix` (or appends `new_suffix` if `extension` is absent)
+fn sibling_file_path(path: &str, extension: &str, new_suffix: &str) -> String {
+
+    format!("{}{}", path.strip_suffix(extension).unwrap_or(path), new_suffix)
+}
 
 pub struct LoadedImageMesh {
 
     pub mesh: Mesh,
     pub texture_2d: Texture2D,
+    /// a downscaled copy of the (stored) image, for sampling its colors
+    pub sample_image: RgbaImage,
+}
+
+/// where to save the project: the loaded project file, next to the PTO file, or next to the first photo
+fn project_file_path(media_paths: &MediaPaths) -> String {
+
+    if let Some(path) = &media_paths.project_file {
+        return path.clone();
+    }
+
+    if let Some(path) = &media_paths.pto_file {
+        return sibling_file_path(path, ".pto", ".project.json");
+    }
+
+    let dir = media_paths.photo_images.first()
+        .and_then(|path| Path::new(path).parent())
+        .unwrap_or_else(|| Path::new(""));
+
+    dir.join("panorama.project.json").to_string_lossy().to_string()
+}
+
+fn loaded_string(loaded: &Loaded, path: &str) -> Result<String, Box<dyn std::error::Error>> {
+
+    let bytes = loaded.bytes(path).map_err(|e| format!("could not load {}: {:?}", path, e))?;
+    let s = std::str::from_utf8(bytes).map_err(|e| format!("could not read {}: {}", path, e))?;
+    Ok(s.to_string())
+}
+
+/// reads an image's EXIF metadata: empty (with a log message) if it has none
+fn loaded_exif_metadata(loaded: &Loaded, path: &str) -> ExifMetadata {
+
+    let bytes = match loaded.bytes(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("could not load {}: {:?}", path, e);
+            return ExifMetadata::default();
+        },
+    };
+
+    ExifMetadata::from_bytes(bytes).unwrap_or_else(|e| {
+        info!("no EXIF metadata in {}: {}", path, e);
+        ExifMetadata::default()
+    })
 }
 
-fn load_mesh_from_filepath(context: &Context, loaded: &Loaded, image_filepath: &str) -> LoadedImageMesh {
+/// `image_orientation`: how the stored image is displayed upright on the (unit square) mesh
+fn load_mesh_from_filepath(context: &Context, loaded: &Loaded, image_filepath: &str, image_orientation: ImageOrientation) -> Result<LoadedImageMesh, Box<dyn std::error::Error>> {
 
     let mut cpu_mesh = CPUMesh {
         positions: square_positions(),
-        uvs: Some(square_uvs()),
+        uvs: Some(oriented_square_uvs(image_orientation)),
 
         ..Default::default()
     };
     cpu_mesh.compute_normals();
 
-    let mut cpu_texture = loaded.image(image_filepath).unwrap();
+    let mut cpu_texture = loaded.image(image_filepath).map_err(|e| format!("could not load image {}: {:?}", image_filepath, e))?;
     cpu_texture.min_filter = Interpolation::Nearest;
     cpu_texture.mag_filter = Interpolation::Nearest;
     cpu_texture.mip_map_filter = None;
@@ -153,12 +904,33 @@ fn load_mesh_from_filepath(context: &Context, loaded: &Loaded, image_filepath: &
     cpu_texture.wrap_t = Wrapping::ClampToEdge;
     cpu_texture.wrap_r = Wrapping::ClampToEdge;
 
-    let texture_2d = Texture2D::new(&context, &cpu_texture).unwrap();
+    let texture_2d = Texture2D::new(&context, &cpu_texture).map_err(|e| format!("could not create texture for {}: {:?}", image_filepath, e))?;
+    let sample_image = sample_image(&cpu_texture).ok_or_else(|| format!("unsupported image format: {}", image_filepath))?;
 
-    let mut mesh = Mesh::new(&context, &cpu_mesh).unwrap();
+    let mut mesh = Mesh::new(&context, &cpu_mesh).map_err(|e| format!("could not create mesh for {}: {:?}", image_filepath, e))?;
     mesh.cull = CullType::Back;
 
-    LoadedImageMesh {mesh, texture_2d}
+    Ok(LoadedImageMesh {mesh, texture_2d, sample_image})
+}
+
+/// a copy of a loaded 8-bit image, downscaled to at most MAX_SAMPLE_IMAGE_SIDE pixels on a side
+fn sample_image(cpu_texture: &CPUTexture<u8>) -> Option<RgbaImage> {
+
+    let (width, height, data) = (cpu_texture.width as u32, cpu_texture.height as u32, cpu_texture.data.clone());
+
+    let image = match cpu_texture.format {
+        Format::R => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, data)?),
+        Format::RG => DynamicImage::ImageLumaA8(ImageBuffer::from_raw(width, height, data)?),
+        Format::RGB | Format::SRGB => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, height, data)?),
+        Format::RGBA | Format::SRGBA => DynamicImage::ImageRgba8(ImageBuffer::from_raw(width, height, data)?),
+    };
+
+    if width <= MAX_SAMPLE_IMAGE_SIDE && height <= MAX_SAMPLE_IMAGE_SIDE {
+        Some(image.to_rgba8())
+    }
+    else {
+        Some(image.thumbnail(MAX_SAMPLE_IMAGE_SIDE, MAX_SAMPLE_IMAGE_SIDE).to_rgba8())
+    }
 }
 
 fn color_mesh(context: &Context) -> Mesh {
@@ -212,6 +984,15 @@ pub fn square_uvs() -> Vec<f32> {
     ]
 }
 
+/// `square_uvs` mapped to stored image texture coords
+fn oriented_square_uvs(image_orientation: ImageOrientation) -> Vec<f32> {
+
+    square_uvs().chunks(2).flat_map(|uv| {
+        let (u, v) = image_orientation.stored_texture_coords((uv[0] as f64, uv[1] as f64));
+        vec![u as f32, v as f32]
+    }).collect()
+}
+
 fn line_positions() -> Vec<f32> {
     vec![
         0.0, -0.5, 0.0,
@@ -232,4 +1013,154 @@ fn hourglass_positions() -> Vec<f32> {
         -0.5, 0.5, 0.0,
         0.0, 0.0, 0.0,
     ]
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn sample_image_test() {
+
+        let texture = |width: usize, height: usize, format: Format| CPUTexture {
+            data: vec![128; width * height * format.color_channel_count()],
+            width,
+            height,
+            format,
+            ..Default::default()
+        };
+
+        let image = sample_image(&texture(4, 2, Format::RGB)).unwrap();
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(3, 1).0, [128, 128, 128, 255]);
+
+        //downscaled, keeping the aspect ratio
+        let image = sample_image(&texture(2048, 1024, Format::RGBA)).unwrap();
+        assert_eq!(image.dimensions(), (MAX_SAMPLE_IMAGE_SIDE, MAX_SAMPLE_IMAGE_SIDE / 2));
+
+        //too little data
+        assert!(sample_image(&CPUTexture{width: 5, ..texture(4, 2, Format::R)}).is_none());
+    }
+
+    #[test]
+    fn lens_ids_test() {
+
+        //image 1 shares image 0's lens, image 2 only shares its fov, image 3's links don't resolve
+        let pto_file = read_pto::read_pto_file(
+"i w400 h300 f0 v50 a0 b0 c0 n\"a.jpg\"
+i w400 h300 f0 v=0 a=0 b=0 c=0 n\"b.jpg\"
+i w400 h300 f0 v=0 a0 b0 c0 n\"c.jpg\"
+i w400 h300 f0 v=9 a0 b0 c0 n\"d.jpg\"").unwrap();
+
+        assert_eq!(pto_lens_ids(&pto_file), vec![0, 0, 1, 2]);
+
+        //photos without a PTO image (or whose lens doesn't resolve) are grouped by EXIF, if their focal length is known
+        let exif = |model: &str, focal_length: Option<f64>| ExifMetadata{model: Some(model.to_string()), focal_length, ..Default::default()};
+        let metadata = vec![
+            exif("A", Some(50.0)), exif("A", Some(50.0)), exif("A", Some(50.0)), exif("A", Some(50.0)),
+            exif("A", Some(50.0)), exif("A", Some(35.0)), exif("B", Some(50.0)), exif("A", None), exif("A", None),
+        ];
+
+        assert_eq!(photo_lens_ids(&pto_file, &metadata.iter().collect::<Vec<_>>()), vec![0, 0, 1, 2, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn pto_vignetting_test() {
+
+        //image 1 shares image 0's Va and Vb
+        let mut pto_file = read_pto::read_pto_file(
+"i w400 h300 f0 v50 Va1 Vb-0.2 n\"a.jpg\"
+i w400 h300 f0 v=0 Va=0 Vb=0 Vc0.1 n\"b.jpg\"").unwrap();
+
+        assert_eq!(pto_vignetting(&pto_file, 1), Some(Vignetting{a: 1.0, b: -0.2, c: 0.1, d: 0.0}));
+        //missing values are no vignetting
+        assert_eq!(pto_vignetting(&pto_file, 0), Some(Vignetting{a: 1.0, b: -0.2, c: 0.0, d: 0.0}));
+        assert_eq!(pto_vignetting(&pto_file, 2), None);
+
+        //only changed values are set: links are kept
+        set_pto_vignetting(&mut pto_file, 1, Vignetting{a: 1.0, b: -0.2, c: 0.1, d: 0.05});
+
+        let pto_file = read_pto::read_pto_file(&write_pto::write_pto_file(&pto_file).unwrap()).unwrap();
+        assert_eq!(pto_file.images()[1].other_variable("Va", 1.0), ImageVariable::Link(0));
+        assert_eq!(pto_file.images()[1].other_variable("Vb", 0.0), ImageVariable::Link(0));
+        assert_eq!(pto_vignetting(&pto_file, 1), Some(Vignetting{a: 1.0, b: -0.2, c: 0.1, d: 0.05}));
+    }
+
+    #[test]
+    fn pto_sphere_projection_test() {
+
+        let mut pto_file = read_pto::read_pto_file("p f1 w3000 h1500 v360").unwrap();
+        assert_eq!(pto_sphere_projection(&pto_file, None).kind, ProjectionKind::Cylindrical);
+
+        set_pto_sphere_projection(&mut pto_file, ProjectionKind::Stereographic);
+        assert_eq!(pto_file.panorama().unwrap().projection, 4);
+
+        //unsupported: shown as the default, and kept in the file
+        let mut pto_file = read_pto::read_pto_file("p f9 w3000 h1500 v360").unwrap();
+        assert_eq!(pto_sphere_projection(&pto_file, None).kind, ProjectionKind::default());
+
+        set_pto_sphere_projection(&mut pto_file, ProjectionKind::default());
+        assert_eq!(pto_file.panorama().unwrap().projection, 9);
+    }
+
+    #[test]
+    fn pto_rotated_photo_round_trip_test() {
+
+        //a 4000x3000 stored image, displayed upright as 3000x4000 (with the same lens as image 1)
+        let pto_file_contents =
+"i w4000 h3000 f0 v66 r90 p0 y0 a0 b0 c0 d0 e0 n\"rotated.jpg\"
+i w4000 h3000 f0 v=0 r0 p0 y10 a=0 b=0 c=0 d=0 e=0 n\"normal.jpg\"";
+
+        let mut pto_file = read_pto::read_pto_file(pto_file_contents).unwrap();
+        let orientation = ImageOrientation::Rotate90;
+
+        let mut lens = pto_lens_parameters(&pto_file, 0, orientation).unwrap();
+        let mut pose = pto_camera_pose(&pto_file, 0, orientation).unwrap();
+
+        //the upright image is narrower
+        assert_approx_eq!(lens.fov, 2.0 * (0.75 * 33f64.to_radians().tan()).atan().to_degrees());
+        assert_approx_eq!(pose.roll, 0.0);
+
+        lens.b = 0.01;
+        pose.roll = 10.0;
+
+        set_pto_lens_parameters(&mut pto_file, 0, orientation, lens);
+        set_pto_camera_pose(&mut pto_file, 0, orientation, pose);
+
+        let pto_file = read_pto::read_pto_file(&write_pto::write_pto_file(&pto_file).unwrap()).unwrap();
+
+        assert_approx_eq!(pto_file.resolve_image_variable(0, |image| image.fov).unwrap(), 66.0);
+        assert_approx_eq!(pto_file.resolve_image_variable(0, |image| image.roll).unwrap(), 100.0);
+
+        let read_lens = pto_lens_parameters(&pto_file, 0, orientation).unwrap();
+        assert_approx_eq!(read_lens.fov, lens.fov);
+        assert_eq!(read_lens.b, 0.01);
+        assert_approx_eq!(pto_camera_pose(&pto_file, 0, orientation).unwrap().roll, 10.0);
+
+        //the linked lens is unchanged for the normal image
+        assert_eq!(pto_file.images()[1].fov, ImageVariable::Link(0));
+        assert_eq!(pto_lens_parameters(&pto_file, 1, ImageOrientation::Normal).unwrap().fov, 66.0);
+    }
+
+    #[test]
+    fn pto_camera_pose_links_test() {
+
+        let pto_file_contents =
+"i w4000 h3000 f0 v66 r1 p2 y3 a0 b0 c0 d0 e0 n\"a.jpg\"
+i w4000 h3000 f0 v=0 r=0 p=0 y=0 a=0 b=0 c=0 d=0 e=0 n\"b.jpg\"";
+
+        let mut pto_file = read_pto::read_pto_file(pto_file_contents).unwrap();
+
+        //only changed values are set: links are kept
+        let mut pose = pto_camera_pose(&pto_file, 1, ImageOrientation::Normal).unwrap();
+        pose.yaw = 20.0;
+        set_pto_camera_pose(&mut pto_file, 1, ImageOrientation::Normal, pose);
+
+        let image = pto_file.images()[1];
+        assert_eq!(image.yaw, ImageVariable::Value(20.0));
+        assert_eq!(image.pitch, ImageVariable::Link(0));
+        assert_eq!(image.roll, ImageVariable::Link(0));
+    }
+}
@@ -0,0 +1,286 @@
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+
+use exif::{Exif, In, Reader, Tag, Value};
+
+use crate::image_orientation::ImageOrientation;
+
+/// A GPS location, in degrees (positive = north/east) and meters above sea level
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct GpsLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+/// Camera metadata read from a photo's EXIF tags
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ExifMetadata {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens_make: Option<String>,
+    pub lens_model: Option<String>,
+    /// in mm
+    pub focal_length: Option<f64>,
+    /// in mm
+    pub focal_length_35mm: Option<f64>,
+    /// in seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    /// as recorded: "YYYY:MM:DD HH:MM:SS"
+    pub capture_time: Option<String>,
+    /// the EXIF orientation tag value (1-8)
+    pub orientation: Option<u16>,
+    pub gps: Option<GpsLocation>,
+}
+
+impl ExifMetadata {
+
+    /// reads EXIF metadata from image file bytes (JPEG, or any container supported by kamadak-exif)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, exif::Error> {
+
+        let exif = Reader::new().read_from_container(&mut Cursor::new(bytes))?;
+
+        Ok(Self::from_exif(&exif))
+    }
+
+    /// how the stored image is displayed upright (Normal if there is no orientation tag)
+    pub fn image_orientation(&self) -> ImageOrientation {
+
+        self.orientation.map(ImageOrientation::from_exif).unwrap_or_default()
+    }
+
+    fn from_exif(exif: &Exif) -> Self {
+
+        let value = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|field| &field.value);
+
+        let string = |tag: Tag| match value(tag)? {
+            Value::Ascii(strings) => {
+                let string = String::from_utf8_lossy(strings.first()?).trim().to_string();
+                if string.is_empty() {None} else {Some(string)}
+            },
+            _ => None,
+        };
+
+        let rational = |tag: Tag| match value(tag)? {
+            Value::Rational(rationals) => rationals.first().map(|r| r.to_f64()).filter(|r| r.is_finite()),
+            _ => None,
+        };
+
+        let uint = |tag: Tag| value(tag)?.get_uint(0);
+
+        Self {
+            make: string(Tag::Make),
+            model: string(Tag::Model),
+            lens_make: string(Tag::LensMake),
+            lens_model: string(Tag::LensModel),
+            focal_length: rational(Tag::FocalLength),
+            focal_length_35mm: uint(Tag::FocalLengthIn35mmFilm).filter(|&f| f > 0).map(f64::from),
+            exposure_time: rational(Tag::ExposureTime),
+            f_number: rational(Tag::FNumber),
+            iso: uint(Tag::PhotographicSensitivity),
+            capture_time: string(Tag::DateTimeOriginal).or_else(|| string(Tag::DateTime)),
+            orientation: uint(Tag::Orientation).map(|o| o as u16),
+            gps: Self::gps_location(exif),
+        }
+    }
+
+    fn gps_location(exif: &Exif) -> Option<GpsLocation> {
+
+        let value = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|field| &field.value);
+
+        //degrees, minutes, seconds
+        let degrees = |tag: Tag| match value(tag)? {
+            Value::Rational(dms) if dms.len() == 3 => Some(dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0),
+            _ => None,
+        };
+
+        let reference = |tag: Tag| match value(tag)? {
+            Value::Ascii(strings) => strings.first().and_then(|s| s.first()).copied(),
+            _ => None,
+        };
+
+        let sign = |tag: Tag, negative: u8| if reference(tag) == Some(negative) {-1.0} else {1.0};
+
+        let latitude = degrees(Tag::GPSLatitude)? * sign(Tag::GPSLatitudeRef, b'S');
+        let longitude = degrees(Tag::GPSLongitude)? * sign(Tag::GPSLongitudeRef, b'W');
+
+        let altitude = match value(Tag::GPSAltitude) {
+            Some(Value::Rational(altitude)) => altitude.first().map(|a| {
+                //reference 1: below sea level
+                let below = value(Tag::GPSAltitudeRef).and_then(|r| r.get_uint(0)) == Some(1);
+                if below {-a.to_f64()} else {a.to_f64()}
+            }),
+            _ => None,
+        };
+
+        Some(GpsLocation{latitude, longitude, altitude})
+    }
+}
+
+impl Display for ExifMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+
+        let join = |parts: &[&Option<String>]| -> Option<String> {
+            let parts: Vec<&str> = parts.iter().filter_map(|part| part.as_deref()).collect();
+            if parts.is_empty() {None} else {Some(parts.join(" "))}
+        };
+
+        if let Some(camera) = join(&[&self.make, &self.model]) {
+            writeln!(f, "Camera: {}", camera)?;
+        }
+        if let Some(lens) = join(&[&self.lens_make, &self.lens_model]) {
+            writeln!(f, "Lens: {}", lens)?;
+        }
+        if let Some(focal_length) = self.focal_length {
+            write!(f, "Focal length: {:.1} mm", focal_length)?;
+            if let Some(focal_length_35mm) = self.focal_length_35mm {
+                write!(f, " ({:.0} mm in 35mm)", focal_length_35mm)?;
+            }
+            writeln!(f)?;
+        }
+
+        let mut exposure = Vec::new();
+        if let Some(t) = self.exposure_time {
+            exposure.push(if t > 0.0 && t < 1.0 {format!("1/{:.0} s", 1.0 / t)} else {format!("{} s", t)});
+        }
+        if let Some(f_number) = self.f_number {
+            exposure.push(format!("f/{:.1}", f_number));
+        }
+        if let Some(iso) = self.iso {
+            exposure.push(format!("ISO {}", iso));
+        }
+        if !exposure.is_empty() {
+            writeln!(f, "Exposure: {}", exposure.join(" "))?;
+        }
+
+        if let Some(capture_time) = &self.capture_time {
+            writeln!(f, "Captured: {}", capture_time)?;
+        }
+        if let Some(orientation) = self.orientation {
+            writeln!(f, "Orientation: {}", orientation)?;
+        }
+        if let Some(gps) = self.gps {
+            write!(f, "GPS: {:.6}, {:.6}", gps.latitude, gps.longitude)?;
+            if let Some(altitude) = gps.altitude {
+                write!(f, " ({:.1} m)", altitude)?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+    use exif::{Field, Rational};
+    use exif::experimental::Writer;
+
+    /// a JPEG file (without image data) containing an APP1 EXIF segment with these fields
+    fn jpeg_with_exif(fields: &[Field]) -> Vec<u8> {
+
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend(tiff);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend(&((app1.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(app1);
+        jpeg.extend(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field {tag, ifd_num: In::PRIMARY, value}
+    }
+
+    fn ascii(s: &str) -> Value {
+        Value::Ascii(vec![s.as_bytes().to_vec()])
+    }
+
+    fn rationals(values: &[(u32, u32)]) -> Value {
+        Value::Rational(values.iter().map(|&(num, denom)| Rational{num, denom}).collect())
+    }
+
+    #[test]
+    fn from_bytes_test() {
+
+        let jpeg = jpeg_with_exif(&[
+            field(Tag::Make, ascii("NIKON CORPORATION")),
+            field(Tag::Model, ascii("NIKON D750")),
+            field(Tag::Orientation, Value::Short(vec![6])),
+            field(Tag::DateTimeOriginal, ascii("2021:03:04 12:30:45")),
+            field(Tag::ExposureTime, rationals(&[(1, 250)])),
+            field(Tag::FNumber, rationals(&[(80, 10)])),
+            field(Tag::PhotographicSensitivity, Value::Short(vec![200])),
+            field(Tag::FocalLength, rationals(&[(500, 10)])),
+            field(Tag::FocalLengthIn35mmFilm, Value::Short(vec![75])),
+            field(Tag::LensModel, ascii("50.0 mm f/1.8")),
+            field(Tag::GPSLatitudeRef, ascii("N")),
+            field(Tag::GPSLatitude, rationals(&[(47, 1), (30, 1), (36, 1)])),
+            field(Tag::GPSLongitudeRef, ascii("W")),
+            field(Tag::GPSLongitude, rationals(&[(122, 1), (15, 1), (0, 1)])),
+            field(Tag::GPSAltitudeRef, Value::Byte(vec![0])),
+            field(Tag::GPSAltitude, rationals(&[(1205, 10)])),
+        ]);
+
+        let metadata = ExifMetadata::from_bytes(&jpeg).unwrap();
+
+        assert_eq!(metadata.make.as_deref(), Some("NIKON CORPORATION"));
+        assert_eq!(metadata.model.as_deref(), Some("NIKON D750"));
+        assert_eq!(metadata.lens_make, None);
+        assert_eq!(metadata.lens_model.as_deref(), Some("50.0 mm f/1.8"));
+        assert_eq!(metadata.focal_length, Some(50.0));
+        assert_eq!(metadata.focal_length_35mm, Some(75.0));
+        assert_eq!(metadata.exposure_time, Some(0.004));
+        assert_eq!(metadata.f_number, Some(8.0));
+        assert_eq!(metadata.iso, Some(200));
+        assert_eq!(metadata.capture_time.as_deref(), Some("2021:03:04 12:30:45"));
+        assert_eq!(metadata.orientation, Some(6));
+        assert_eq!(metadata.image_orientation(), ImageOrientation::Rotate90);
+
+        let gps = metadata.gps.unwrap();
+        assert_approx_eq!(gps.latitude, 47.51);
+        assert_approx_eq!(gps.longitude, -122.25);
+        assert_eq!(gps.altitude, Some(120.5));
+
+        assert_eq!(
+            metadata.to_string(),
+            "Camera: NIKON CORPORATION NIKON D750\n\
+            Lens: 50.0 mm f/1.8\n\
+            Focal length: 50.0 mm (75 mm in 35mm)\n\
+            Exposure: 1/250 s f/8.0 ISO 200\n\
+            Captured: 2021:03:04 12:30:45\n\
+            Orientation: 6\n\
+            GPS: 47.510000, -122.250000 (120.5 m)\n"
+        );
+    }
+
+    #[test]
+    fn from_bytes_error_test() {
+
+        //no EXIF segment
+        assert!(ExifMetadata::from_bytes(&[0xFF, 0xD8, 0xFF, 0xD9]).is_err());
+        assert!(ExifMetadata::from_bytes(b"not an image").is_err());
+
+        //empty metadata
+        let metadata = ExifMetadata::from_bytes(&jpeg_with_exif(&[field(Tag::Make, ascii(""))])).unwrap();
+        assert_eq!(metadata, ExifMetadata::default());
+        assert_eq!(metadata.image_orientation(), ImageOrientation::Normal);
+        assert_eq!(metadata.to_string(), "");
+    }
+}
@@ -0,0 +1,386 @@
+use serde::{Serialize, Deserialize};
+
+use crate::optimize;
+use crate::lens::Vignetting;
+
+/// the display gamma assumed for image colors: corrections scale linear light
+const GAMMA: f64 = 2.2;
+
+/// gamma encoded sample values outside this range are not used for estimation:
+/// too dark for reliable ratios, or clipped
+const MIN_SAMPLE: f64 = 0.02;
+const MAX_SAMPLE: f64 = 0.98;
+
+/// weight (per overlap sample) of keeping log2 gains near 0:
+/// overlaps only determine relative brightness, so this sets the overall brightness
+const PRIOR_WEIGHT: f64 = 1e-3;
+
+/// radii (from 0 to 1) where estimated vignetting is fit with a polynomial
+const VIGNETTING_FIT_STEPS: usize = 32;
+
+/// A photo's exposure and white balance correction
+///
+/// (like PTO `Eev`, `Er`, `Eb`, but relative: 0 EV and 1x multipliers change nothing)
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct ColorCorrection {
+    /// brightening, in EV (stops)
+    pub exposure: f64,
+    /// red multiplier, relative to green
+    pub red: f64,
+    /// blue multiplier, relative to green
+    pub blue: f64,
+}
+
+impl Default for ColorCorrection {
+
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            red: 1.0,
+            blue: 1.0,
+        }
+    }
+}
+
+impl ColorCorrection {
+
+    /// RGB multipliers of linear light
+    pub fn gains(&self) -> [f64; 3] {
+
+        let gain = self.exposure.exp2();
+        [gain * self.red, gain, gain * self.blue]
+    }
+
+    /// RGB multipliers of gamma encoded colors (the photo shaders' `color_gain` uniform)
+    pub fn encoded_gains(&self) -> [f64; 3] {
+
+        let [red, green, blue] = self.gains();
+        [encoded_gain(red), encoded_gain(green), encoded_gain(blue)]
+    }
+
+    /// corrects a gamma encoded RGB color (results may exceed 1)
+    pub fn apply(&self, color: [f64; 3]) -> [f64; 3] {
+
+        let gains = self.encoded_gains();
+        [color[0] * gains[0], color[1] * gains[1], color[2] * gains[2]]
+    }
+
+    /// the correction with these RGB log2 linear light gains
+    fn from_log_gains([red, green, blue]: [f64; 3]) -> Self {
+
+        Self {
+            exposure: green,
+            red: (red - green).exp2(),
+            blue: (blue - green).exp2(),
+        }
+    }
+}
+
+/// the multiplier of gamma encoded colors that multiplies linear light by `gain`
+pub fn encoded_gain(gain: f64) -> f64 {
+
+    gain.powf(1.0 / GAMMA)
+}
+
+/// One world location where two photos overlap
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct OverlapSample {
+    /// photo indices
+    pub photos: (usize, usize),
+    /// their gamma encoded RGB colors there, in [0,1]: vignetting corrected, but not color corrected
+    pub colors: ([f64; 3], [f64; 3]),
+    /// their source image radii there (see Vignetting::radius)
+    pub radii: (f64, f64),
+}
+
+/// estimates the corrections that make `photo_count` photos match where they overlap:
+/// for each channel, least squares differences of log linear light after correction
+///
+/// photos with no usable samples get None
+pub fn estimate_color_corrections(photo_count: usize, samples: &[OverlapSample]) -> Vec<Option<ColorCorrection>> {
+
+    let log_linear = |value: f64| (value.powf(GAMMA)).log2();
+    let usable = |value: f64| (MIN_SAMPLE..=MAX_SAMPLE).contains(&value);
+
+    let mut log_gains = vec![[0.0; 3]; photo_count];
+    let mut sampled = vec![false; photo_count];
+
+    for channel in 0..3 {
+
+        //normal equations of: log_gain[a] - log_gain[b] = log_linear[b] - log_linear[a]
+        let mut a = vec![vec![0.0; photo_count]; photo_count];
+        let mut b = vec![0.0; photo_count];
+        let mut count = 0;
+
+        for sample in samples {
+            let (i, j) = sample.photos;
+            let (color_i, color_j) = (sample.colors.0[channel], sample.colors.1[channel]);
+
+            if i == j || i >= photo_count || j >= photo_count || !usable(color_i) || !usable(color_j) {
+                continue;
+            }
+
+            let difference = log_linear(color_j) - log_linear(color_i);
+
+            a[i][i] += 1.0;
+            a[j][j] += 1.0;
+            a[i][j] -= 1.0;
+            a[j][i] -= 1.0;
+            b[i] += difference;
+            b[j] -= difference;
+            count += 1;
+            sampled[i] = true;
+            sampled[j] = true;
+        }
+
+        let prior = PRIOR_WEIGHT * count.max(1) as f64;
+        for (i, row) in a.iter_mut().enumerate() {
+            row[i] += prior;
+        }
+
+        if let Some(x) = optimize::solve(a, b) {
+            for (log_gain, x) in log_gains.iter_mut().zip(x) {
+                log_gain[channel] = x;
+            }
+        }
+    }
+
+    log_gains.into_iter().zip(sampled).map(|(log_gains, sampled)| sampled.then(|| ColorCorrection::from_log_gains(log_gains))).collect()
+}
+
+/// estimates the vignetting of each lens that makes photos match where they overlap
+///
+/// `photo_lenses` is each photo's index in `lenses`, their current vignetting (already corrected in the samples):
+/// least squares differences of log linear brightness, with an unknown exposure for each photo
+///
+/// lenses with no usable samples are unchanged
+pub fn estimate_vignetting(photo_lenses: &[usize], lenses: &[Vignetting], samples: &[OverlapSample]) -> Vec<Vignetting> {
+
+    let photo_count = photo_lenses.len();
+    let unknowns = photo_count + 3 * lenses.len();
+
+    //log2 linear brightness (the mean of linear RGB), if every channel is usable
+    let log_brightness = |color: [f64; 3]| -> Option<f64> {
+        if !color.iter().all(|value| (MIN_SAMPLE..=MAX_SAMPLE).contains(value)) {
+            return None;
+        }
+        Some((color.iter().map(|value| value.powf(GAMMA)).sum::<f64>() / 3.0).log2())
+    };
+
+    //unknowns: each photo's log2 gain, then each lens' residual log2 vignetting: x1 r² + x2 r⁴ + x3 r⁶
+    let mut a = vec![vec![0.0; unknowns]; unknowns];
+    let mut b = vec![0.0; unknowns];
+    let mut lens_sample_counts = vec![0; lenses.len()];
+    let mut count = 0;
+
+    for sample in samples {
+        let (i, j) = sample.photos;
+
+        if i == j || i >= photo_count || j >= photo_count {
+            continue;
+        }
+
+        let (lens_i, lens_j) = (photo_lenses[i], photo_lenses[j]);
+
+        if lens_i >= lenses.len() || lens_j >= lenses.len() {
+            continue;
+        }
+
+        let (brightness_i, brightness_j) = match (log_brightness(sample.colors.0), log_brightness(sample.colors.1)) {
+            (Some(brightness_i), Some(brightness_j)) => (brightness_i, brightness_j),
+            _ => continue,
+        };
+
+        //gain[i] - vignetting[i](r_i) - gain[j] + vignetting[j](r_j) = brightness[j] - brightness[i]
+        let mut row = vec![0.0; unknowns];
+        row[i] += 1.0;
+        row[j] -= 1.0;
+
+        let (r2_i, r2_j) = (sample.radii.0.powi(2), sample.radii.1.powi(2));
+        for k in 0..3 {
+            row[photo_count + 3 * lens_i + k] -= r2_i.powi(k as i32 + 1);
+            row[photo_count + 3 * lens_j + k] += r2_j.powi(k as i32 + 1);
+        }
+
+        let difference = brightness_j - brightness_i;
+
+        for (m, &row_m) in row.iter().enumerate().filter(|(_, &value)| value != 0.0) {
+            for (n, &row_n) in row.iter().enumerate() {
+                a[m][n] += row_m * row_n;
+            }
+            b[m] += row_m * difference;
+        }
+
+        lens_sample_counts[lens_i] += 1;
+        lens_sample_counts[lens_j] += 1;
+        count += 1;
+    }
+
+    let prior = PRIOR_WEIGHT * count.max(1) as f64;
+    for (i, row) in a.iter_mut().enumerate() {
+        row[i] += prior;
+    }
+
+    let x = match optimize::solve(a, b) {
+        Some(x) => x,
+        None => return lenses.to_vec(),
+    };
+
+    lenses.iter().enumerate().map(|(lens, &current)| {
+
+        if lens_sample_counts[lens] == 0 {
+            return current;
+        }
+
+        let residual = &x[photo_count + 3 * lens..photo_count + 3 * lens + 3];
+
+        let brightness = |radius: f64| {
+            let r2 = radius * radius;
+            current.brightness(radius) * (residual[0] * r2 + residual[1] * r2.powi(2) + residual[2] * r2.powi(3)).exp2()
+        };
+
+        fit_vignetting(current.a, brightness).unwrap_or(current)
+    }).collect()
+}
+
+/// the vignetting polynomial (with `a` given) that's the least squares fit to `brightness` at radii from 0 to 1
+fn fit_vignetting(a: f64, brightness: impl Fn(f64) -> f64) -> Option<Vignetting> {
+
+    let mut normal = vec![vec![0.0; 3]; 3];
+    let mut rhs = vec![0.0; 3];
+
+    for step in 0..=VIGNETTING_FIT_STEPS {
+        let radius = step as f64 / VIGNETTING_FIT_STEPS as f64;
+        let r2 = radius * radius;
+        let terms = [r2, r2.powi(2), r2.powi(3)];
+
+        for (m, term_m) in terms.iter().enumerate() {
+            for (n, term_n) in terms.iter().enumerate() {
+                normal[m][n] += term_m * term_n;
+            }
+            rhs[m] += term_m * (brightness(radius) - a);
+        }
+    }
+
+    let x = optimize::solve(normal, rhs)?;
+    Some(Vignetting{a, b: x[0], c: x[1], d: x[2]})
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    #[test]
+    fn color_correction_test() {
+
+        let correction = ColorCorrection::default();
+        assert_eq!(correction.gains(), [1.0; 3]);
+        assert_eq!(correction.apply([0.2, 0.5, 0.8]), [0.2, 0.5, 0.8]);
+
+        //+1 EV doubles linear light
+        let correction = ColorCorrection{exposure: 1.0, red: 2.0, blue: 0.5};
+        assert_eq!(correction.gains(), [4.0, 2.0, 1.0]);
+
+        let corrected = correction.apply([0.2, 0.2, 0.2]);
+        assert_approx_eq!(corrected[1].powf(GAMMA), 2.0 * 0.2_f64.powf(GAMMA));
+        assert_approx_eq!(corrected[0].powf(GAMMA), 4.0 * 0.2_f64.powf(GAMMA));
+
+        let round_trip = ColorCorrection::from_log_gains([2.0, 1.0, 0.0]);
+        assert_approx_eq!(round_trip.exposure, 1.0);
+        assert_approx_eq!(round_trip.red, 2.0);
+        assert_approx_eq!(round_trip.blue, 0.5);
+    }
+
+    #[test]
+    fn estimate_color_corrections_test() {
+
+        //the same scene: photo 1 is 1 EV darker than photo 0 and has a blue cast, photo 2 is 1 EV brighter than photo 1
+        let scene = [[0.3, 0.4, 0.5], [0.5, 0.5, 0.5], [0.6, 0.3, 0.2], [0.1, 0.2, 0.3]];
+        let photo_1 = ColorCorrection{exposure: -1.0, red: 1.0, blue: 1.5};
+        let photo_2 = ColorCorrection{exposure: 1.0, red: 1.0, blue: 1.0};
+
+        let mut samples = Vec::new();
+        for &color in &scene {
+            let color_1 = photo_1.apply(color);
+            samples.push(OverlapSample{photos: (0, 1), colors: (color, color_1), radii: (0.5, 0.5)});
+            samples.push(OverlapSample{photos: (1, 2), colors: (color_1, photo_2.apply(color_1)), radii: (0.5, 0.5)});
+        }
+
+        //not usable: clipped
+        samples.push(OverlapSample{photos: (0, 1), colors: ([1.0; 3], [0.5; 3]), radii: (0.5, 0.5)});
+
+        let corrections = estimate_color_corrections(4, &samples);
+
+        //no overlaps: no correction
+        assert_eq!(corrections[3], None);
+        let corrections: Vec<ColorCorrection> = corrections.into_iter().take(3).map(Option::unwrap).collect();
+
+        //corrected photos match
+        for &color in &scene {
+            let color_1 = photo_1.apply(color);
+            let corrected = [
+                corrections[0].apply(color),
+                corrections[1].apply(color_1),
+                corrections[2].apply(photo_2.apply(color_1)),
+            ];
+            for ((&c0, &c1), &c2) in corrected[0].iter().zip(&corrected[1]).zip(&corrected[2]) {
+                assert_approx_eq!(c0, c1, 1e-2);
+                assert_approx_eq!(c1, c2, 1e-2);
+            }
+        }
+
+        //relative to photo 0: photo 1 is brightened 1 EV, and its blue is reduced
+        assert_approx_eq!(corrections[1].exposure - corrections[0].exposure, 1.0, 1e-2);
+        assert_approx_eq!(corrections[1].blue / corrections[0].blue, 1.0 / 1.5, 1e-2);
+    }
+
+    #[test]
+    fn estimate_vignetting_test() {
+
+        let vignetting = Vignetting{a: 1.0, b: -0.3, c: 0.05, d: -0.05};
+
+        //photos 0-2 share a lens; photo 1 is 1 EV darker; photo 3 has no overlaps
+        let photo_gains = [1.0, 0.5, 1.0, 1.0];
+        let photo_lenses = [0, 0, 0, 1];
+
+        //a gray scene, photographed at many pairs of radii
+        let encode = |photo: usize, scene: f64, radius: f64| {
+            let linear = scene * photo_gains[photo] * vignetting.brightness(radius);
+            [linear.powf(1.0 / GAMMA); 3]
+        };
+
+        let mut samples = Vec::new();
+        for step in 0..200 {
+            let scene = 0.2 + 0.3 * (step % 7) as f64 / 7.0;
+            let (r_0, r_1) = ((step % 10) as f64 / 10.0, (step % 13) as f64 / 13.0);
+            let (i, j) = if step % 2 == 0 {(0, 1)} else {(1, 2)};
+            samples.push(OverlapSample{photos: (i, j), colors: (encode(i, scene, r_0), encode(j, scene, r_1)), radii: (r_0, r_1)});
+        }
+
+        let current = [Vignetting::default(), Vignetting{b: -0.1, ..Default::default()}];
+        let estimated = estimate_vignetting(&photo_lenses, &current, &samples);
+
+        for step in 0..=10 {
+            let radius = step as f64 / 10.0;
+            assert_approx_eq!(estimated[0].brightness(radius), vignetting.brightness(radius), 1e-2);
+        }
+        assert_eq!(estimated[0].a, 1.0);
+
+        //no samples: unchanged
+        assert_eq!(estimated[1], current[1]);
+
+        //already corrected: unchanged
+        let corrected: Vec<OverlapSample> = samples.iter().map(|sample| {
+            let devignetted = |color: [f64; 3], radius: f64| color.map(|value| value * encoded_gain(vignetting.gain(radius)));
+            OverlapSample{colors: (devignetted(sample.colors.0, sample.radii.0), devignetted(sample.colors.1, sample.radii.1)), ..*sample}
+        }).collect();
+        let estimated = estimate_vignetting(&photo_lenses, &[vignetting, current[1]], &corrected);
+        for step in 0..=10 {
+            let radius = step as f64 / 10.0;
+            assert_approx_eq!(estimated[0].brightness(radius), vignetting.brightness(radius), 1e-3);
+        }
+    }
+}
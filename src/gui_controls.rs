@@ -2,15 +2,20 @@ use three_d::camera::CameraControl;
 use three_d::frame::FrameInput;
 use three_d::frame::{Event, MouseButton, State};
 use three_d::gui::GUI;
-use three_d::egui::{Window, Button, CollapsingHeader};
+use three_d::egui::{Window, Button, CollapsingHeader, Slider};
 use three_d::math::{Vec2, InnerSpace};
 
-use log::info;
+use log::{info, warn};
 
 use crate::viewport_geometry::{ViewportGeometry, PixelCoords, WorldCoords};
 use crate::control_state::{ControlState, MouseTool, DewarpShader, Pan, Drag, RotateDrag, RotationPoint, UiMode};
 use crate::photo::Photo;
 use crate::entities::Entities;
+use crate::optimize::AlignmentOptions;
+use crate::spherical::{CameraModel, CameraPose, ProjectionKind};
+use crate::blend::BlendMode;
+use crate::seams::SeamMap;
+use crate::exposure::ColorCorrection;
 
 pub fn run_gui_controls(
     frame_input: &mut FrameInput,
@@ -53,6 +58,12 @@ pub fn run_gui_controls(
 
                     ui.separator();
                     ui.checkbox(&mut control_state.photo_borders_visible, "Show Photo Borders");
+                    ui.separator();
+
+                    ui.heading("Blending");
+                    for &mode in &BlendMode::ALL {
+                        ui.radio_value(&mut control_state.blend_mode, mode, format!("{:?}", mode));
+                    }
 
                 },
                 UiMode::Edit => {
@@ -65,6 +76,7 @@ pub fn run_gui_controls(
                     ui.radio_value(&mut control_state.active_mouse_tool, MouseTool::RotationPoint, format!("{:?}", MouseTool::RotationPoint));
                     ui.radio_value(&mut control_state.active_mouse_tool, MouseTool::DragToRotate, format!("{:?}", MouseTool::DragToRotate));
                     ui.radio_value(&mut control_state.active_mouse_tool, MouseTool::DragToRotateAllPhotos, format!("{:?}", MouseTool::DragToRotateAllPhotos));
+                    ui.radio_value(&mut control_state.active_mouse_tool, MouseTool::PaintSeams, format!("{:?}", MouseTool::PaintSeams));
                     ui.separator();
 
                     ui.horizontal(|ui| {
@@ -82,25 +94,210 @@ pub fn run_gui_controls(
                     ui.radio_value(&mut control_state.dewarp_shader, DewarpShader::Dewarp2, format!("On"));
                     ui.separator();
 
+                    ui.heading("Blending");
+                    for &mode in &BlendMode::ALL {
+                        ui.radio_value(&mut control_state.blend_mode, mode, format!("{:?}", mode));
+                    }
+                    ui.separator();
+
+                    ui.heading("Seams");
+                    ui.horizontal(|ui| {
+                        if ui.add(Button::new("find seams")).clicked() {
+                            match entities.find_seams() {
+                                Ok(()) => info!("found seams"),
+                                Err(e) => warn!("failed to find seams: {}", e),
+                            }
+                        }
+                        if ui.add(Button::new("clear seams")).clicked() {
+                            entities.seam_map = None;
+                        }
+                    });
+                    ui.checkbox(&mut control_state.seams_visible, "Show Seams");
+                    ui.add(Slider::f64(&mut control_state.seam_brush_radius, 2.0..=200.0)
+                        .logarithmic(true)
+                        .text("PaintSeams brush (px)"));
+                    ui.separator();
+
+                    ui.heading("Color Correction");
+                    ui.horizontal(|ui| {
+                        if ui.add(Button::new("match colors")).clicked() {
+                            match entities.estimate_color_corrections() {
+                                Ok(()) => info!("estimated color corrections"),
+                                Err(e) => warn!("failed to estimate color corrections: {}", e),
+                            }
+                        }
+                        if ui.add(Button::new("reset colors")).clicked() {
+                            for photo in &mut entities.photos {
+                                photo.color_correction = ColorCorrection::default();
+                            }
+                        }
+                    });
+                    ui.separator();
+
+                    ui.heading("Camera Model");
+                    let camera_model = entities.camera_model;
+                    let spherical = matches!(entities.camera_model, CameraModel::Spherical(_));
+                    if ui.radio(!spherical, "Planar").clicked() {
+                        entities.camera_model = CameraModel::Planar;
+                    }
+                    if ui.radio(spherical, "Spherical").clicked() {
+                        entities.camera_model = CameraModel::Spherical(entities.sphere_projection());
+                    }
+                    if ui.add(Button::new("poses from planar layout")).clicked() {
+                        entities.set_poses_from_orientations();
+                    }
+                    if let CameraModel::Spherical(projection) = &mut entities.camera_model {
+                        ui.label("Projection:");
+                        for &kind in &ProjectionKind::ALL {
+                            ui.radio_value(&mut projection.kind, kind, format!("{:?}", kind));
+                        }
+                    }
+                    //seam map cells are in the camera model's world coords
+                    if entities.camera_model != camera_model {
+                        entities.seam_map = None;
+                    }
+                    ui.separator();
+
                     let mut photo_ui_text = "None".to_string();
 
                     if let Some(i) = control_state.selected_photo_index {
                         if let Some(ph) = entities.photos.get(i) {
+                            let vignetting = entities.vignetting(ph.lens_id);
                             photo_ui_text = format!(
                                 "Photo {}\n\
                                 Center:\n\
                                  x: {:.2}\n\
                                  y: {:.2}\n\
-                                Rotation: {:.2}°",
+                                Rotation: {:.2}°\n\
+                                Pose:\n\
+                                 yaw: {:.2}° pitch: {:.2}° roll: {:.2}°\n\
+                                Lens {}:\n\
+                                 fov: {:.2}°\n\
+                                 a: {:.5} b: {:.5} c: {:.5}\n\
+                                 d: {:.1} e: {:.1}\n\
+                                 Va: {:.3} Vb: {:.3} Vc: {:.3} Vd: {:.3}\n\
+                                Color:\n\
+                                 exposure: {:+.2} EV\n\
+                                 red: {:.3} blue: {:.3}",
                                 i,
                                 ph.orientation().translation().x,
                                 ph.orientation().translation().y,
-                                ph.orientation().rotation()
+                                ph.orientation().rotation(),
+                                ph.pose.yaw, ph.pose.pitch, ph.pose.roll,
+                                ph.lens_id,
+                                ph.lens.fov,
+                                ph.lens.a, ph.lens.b, ph.lens.c,
+                                ph.lens.d, ph.lens.e,
+                                vignetting.a, vignetting.b, vignetting.c, vignetting.d,
+                                ph.color_correction.exposure,
+                                ph.color_correction.red, ph.color_correction.blue,
                             );
                         }
                     }
                     ui.heading("Selected Photo Info");
                     ui.label(&photo_ui_text);
+                    if let Some(ph) = control_state.selected_photo_index.and_then(|i| entities.photos.get(i)) {
+                        CollapsingHeader::new("EXIF")
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                let text = ph.metadata.to_string();
+                                ui.label(if text.is_empty() {"None"} else {text.trim_end()});
+                            });
+                    }
+                    let spherical = matches!(entities.camera_model, CameraModel::Spherical(_));
+                    //(lens id, new parameters) of the selected photo's lens, shared with the photos taken with it
+                    let mut lens_edit = None;
+                    //the selected photo was shown, hidden, or turned: its seams no longer match
+                    let mut photo_moved = false;
+                    if let Some(ph) = control_state.selected_photo_index.and_then(|i| entities.photos.get_mut(i)) {
+                        let (visible, pose) = (ph.visible, ph.pose);
+
+                        ui.checkbox(&mut ph.visible, "visible");
+                        ui.checkbox(&mut ph.locked, "locked");
+
+                        if spherical {
+                            ui.add(Slider::f64(&mut ph.pose.yaw, -180.0..=180.0).text("yaw (°)"));
+                            ui.add(Slider::f64(&mut ph.pose.pitch, -90.0..=90.0).text("pitch (°)"));
+                            ui.add(Slider::f64(&mut ph.pose.roll, -180.0..=180.0).text("roll (°)"));
+                            let mut lens = ph.lens;
+                            ui.add(Slider::f64(&mut lens.fov, 1.0..=179.0).logarithmic(true).text("field of view (°)"));
+                            if lens != ph.lens {
+                                lens_edit = Some((ph.lens_id, lens));
+                            }
+                        }
+
+                        ui.add(Slider::f64(&mut ph.color_correction.exposure, -3.0..=3.0).text("exposure (EV)"));
+                        ui.add(Slider::f64(&mut ph.color_correction.red, 0.25..=4.0).logarithmic(true).text("red multiplier"));
+                        ui.add(Slider::f64(&mut ph.color_correction.blue, 0.25..=4.0).logarithmic(true).text("blue multiplier"));
+
+                        photo_moved = (ph.visible, ph.pose) != (visible, pose);
+                    }
+                    if photo_moved {
+                        entities.clear_seams();
+                    }
+                    if let Some((lens_id, lens)) = lens_edit {
+                        entities.set_lens(lens_id, lens);
+                    }
+
+                    CollapsingHeader::new("Lens Profile (lensfun)")
+                        .default_open(false)
+                        .show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("database:");
+                                ui.text_edit_singleline(&mut control_state.lensfun_directory);
+                            });
+                            ui.horizontal(|ui| {
+                                if ui.add(Button::new("Load")).clicked() {
+                                    match entities.load_lensfun_database(&control_state.lensfun_directory) {
+                                        Ok(count) => info!("loaded {} lensfun lenses from {}", count, control_state.lensfun_directory),
+                                        Err(e) => warn!("failed to load lensfun database: {}", e),
+                                    }
+                                }
+                                ui.label(format!("{} lenses", entities.lensfun_database.lenses.len()));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("maker:");
+                                ui.text_edit_singleline(&mut control_state.lensfun_maker);
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("model:");
+                                ui.text_edit_singleline(&mut control_state.lensfun_model);
+                            });
+                            ui.add(Slider::f64(&mut control_state.lensfun_focal, 8.0..=800.0).logarithmic(true).text("focal length (mm)"));
+
+                            if let Some(ph) = control_state.selected_photo_index.and_then(|i| entities.photos.get(i)) {
+                                if ui.add(Button::new("Fill from EXIF")).clicked() {
+                                    let metadata = &ph.metadata;
+                                    if let Some(maker) = metadata.lens_make.as_ref().or(metadata.make.as_ref()) {
+                                        control_state.lensfun_maker = maker.clone();
+                                    }
+                                    if let Some(model) = &metadata.lens_model {
+                                        control_state.lensfun_model = model.clone();
+                                    }
+                                    if let Some(focal_length) = metadata.focal_length {
+                                        control_state.lensfun_focal = focal_length;
+                                    }
+                                }
+                                if ui.add(Button::new("Apply to selected photo's lens")).clicked() {
+                                    let mut lens = ph.lens;
+                                    match entities.lensfun_database.apply(
+                                        &control_state.lensfun_maker,
+                                        &control_state.lensfun_model,
+                                        control_state.lensfun_focal,
+                                        &mut lens,
+                                    ) {
+                                        Ok(()) => {
+                                            info!("lens {}: a: {:.5} b: {:.5} c: {:.5}", ph.lens_id, lens.a, lens.b, lens.c);
+                                            lens_edit = Some((ph.lens_id, lens));
+                                        },
+                                        Err(e) => warn!("failed to apply lensfun lens: {}", e),
+                                    }
+                                }
+                            }
+                            if let Some((lens_id, lens)) = lens_edit {
+                                entities.set_lens(lens_id, lens);
+                            }
+                        });
                     ui.separator();
 
                     ui.label("Align Photos");
@@ -114,6 +311,118 @@ pub fn run_gui_controls(
                         entities.set_photos_from_json_serde_string(&entities.photos_alignment_alt_string.clone()).unwrap();
                     }
 
+                    //the selected photo stays in place (or the first photo, if none is selected)
+                    let anchor = control_state.selected_photo_index.unwrap_or(0);
+
+                    ui.horizontal(|ui| {
+                        if ui.add(Button::new("Optimize")).clicked() {
+
+                            let options = AlignmentOptions{
+                                anchor,
+                                optimize_scale: control_state.optimize_scale,
+                                optimize_lens: control_state.optimize_lens,
+                                optimize_vignetting: control_state.optimize_vignetting,
+                            };
+
+                            let units = match entities.camera_model {
+                                CameraModel::Planar => "",
+                                CameraModel::Spherical(_) => "°",
+                            };
+
+                            match entities.optimize_alignment(options) {
+                                Ok(alignment) => info!("optimized alignment: RMS distance {:.3}{} -> {:.3}{}", alignment.rms_before, units, alignment.rms_after, units),
+                                Err(e) => warn!("failed to optimize alignment: {}", e),
+                            }
+                        }
+                        ui.label(format!("(anchor: photo {})", anchor));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut control_state.optimize_scale, "optimize scale");
+                        ui.checkbox(&mut control_state.optimize_lens, "calibrate lens");
+                        ui.checkbox(&mut control_state.optimize_vignetting, "calibrate vignetting");
+                    });
+
+                    let report = entities.residual_report(control_state.dewarp_shader.lens_correction());
+
+                    CollapsingHeader::new("Control Point Residuals")
+                        .default_open(false)
+                        .show(ui, |ui| {
+                            match report.overall {
+                                Some(stats) => {
+                                    ui.monospace(format!("all: {} points, RMS {:.2}, max {:.2}", stats.count, stats.rms, stats.max));
+                                    for (image_pair, stats) in &report.image_pairs {
+                                        ui.monospace(format!("{}-{}: {} points, RMS {:.2}, max {:.2}",
+                                            image_pair.first, image_pair.second, stats.count, stats.rms, stats.max));
+                                    }
+                                },
+                                None => { ui.label("no control points"); },
+                            }
+                        });
+                    ui.checkbox(&mut control_state.residuals_visible, "Show Residuals");
+
+                    ui.horizontal(|ui| {
+                        if ui.add(Button::new("Find Outliers")).clicked() {
+
+                            let count = entities.find_outliers(control_state.ransac_options);
+                            info!("found {} outlier control point pairs", count);
+                        }
+                        if ui.add(Button::new(format!("Prune {}", entities.outliers.len()))).clicked() {
+
+                            let count = entities.prune_outliers();
+                            info!("removed {} outlier control point pairs", count);
+                        }
+                    });
+                    ui.add(Slider::f64(&mut control_state.ransac_options.threshold, 1.0..=100.0)
+                        .logarithmic(true)
+                        .text("outlier threshold (px)"));
+
+                    ui.separator();
+
+                    ui.label("Project");
+
+                    ui.horizontal(|ui| {
+                        if ui.add(Button::new("Save")).clicked() {
+
+                            match entities.save_project_file(viewport_geometry) {
+                                Ok(()) => info!("saved {}", entities.project_file_path),
+                                Err(e) => warn!("failed to save project file: {}", e),
+                            }
+                        }
+                        if ui.add(Button::new("Load")).clicked() {
+
+                            match entities.load_project_file(viewport_geometry) {
+                                Ok(()) => info!("loaded {}", entities.project_file_path),
+                                Err(e) => warn!("failed to load project file: {}", e),
+                            }
+                        }
+                    });
+
+                    ui.separator();
+
+                    ui.label("Hugin Project");
+
+                    if ui.add(Button::new("save .pto")).clicked() {
+
+                        match entities.save_pto_file() {
+                            Ok(path) => info!("saved {}", path),
+                            Err(e) => warn!("failed to save .pto file: {}", e),
+                        }
+                    }
+
+                    if !entities.pto_file_warnings.is_empty() {
+
+                        CollapsingHeader::new(format!("PTO Warnings ({})", entities.pto_file_warnings.len()))
+                            .default_open(false)
+                            .show(ui, |ui| {
+
+                                ui.label("These lines were skipped:");
+
+                                for warning in &entities.pto_file_warnings {
+                                    ui.monospace(format!("{}", warning));
+                                }
+                            });
+                    }
+
                     ui.separator();
 
                     CollapsingHeader::new("Help")
@@ -143,6 +452,13 @@ pub fn run_gui_controls(
                         ui.separator();
 
                         ui.checkbox(&mut control_state.control_points_visible, "Show Control Points");
+                        CollapsingHeader::new(format!("Control Point Pairs ({})", entities.control_points.len()))
+                            .default_open(false)
+                            .show(ui, |ui| {
+                                for (image_pair, pairs) in entities.control_points.iter() {
+                                    ui.monospace(format!("images {}-{}: {}", image_pair.first, image_pair.second, pairs.len()));
+                                }
+                            });
                         ui.separator();
 
                         ui.checkbox(&mut control_state.photo_borders_visible, "Show Photo Borders");
@@ -167,11 +483,15 @@ pub fn handle_input_events(
     control_state: &mut ControlState,
     viewport_geometry: &mut ViewportGeometry,
     camera: &mut CameraControl,
+    camera_model: &CameraModel,
     photos: &mut Vec<Photo>,
+    seam_map: &mut Option<SeamMap>,
 ) -> bool {
 
     let mut redraw = false;
 
+    let lens_correction = control_state.dewarp_shader.lens_correction();
+
     for event in frame_input.events.iter() {
         match event {
             Event::MouseClick {state, button, position, handled, ..} => {
@@ -206,11 +526,12 @@ pub fn handle_input_events(
 
                             //only modify the selected photo (if there is one)
                             if let Some(i) = control_state.selected_photo_index {
-                                if photos[i].orientation().contains(world_coords) {
+                                if !photos[i].locked && photos[i].contains(camera_model, lens_correction, world_coords) {
                                         control_state.active_drag =
                                             Some(Drag {
                                                 mouse_start: *position,
                                                 photo_start: photos[i].orientation().translation(),
+                                                pose_start: photos[i].pose,
                                                 photo_index: i,
                                             });
                                     }
@@ -219,11 +540,12 @@ pub fn handle_input_events(
                             //if no photo is selected, allow drags for any photo
                             else {
                                 for (i, ph) in photos.iter().enumerate() {
-                                    if ph.orientation().contains(world_coords) {
+                                    if !ph.locked && ph.contains(camera_model, lens_correction, world_coords) {
                                         control_state.active_drag =
                                             Some(Drag {
                                                 mouse_start: *position,
                                                 photo_start: ph.orientation().translation(),
+                                                pose_start: ph.pose,
                                                 photo_index: i,
                                             });
                                         break;
@@ -242,10 +564,11 @@ pub fn handle_input_events(
 
                             control_state.active_drag_all_photos =
 
-                            photos.iter().enumerate().map(|(i, ph)| {
+                            photos.iter().enumerate().filter(|(_, ph)| !ph.locked).map(|(i, ph)| {
                                 Drag {
                                     mouse_start: *position,
                                     photo_start: ph.orientation().translation(),
+                                    pose_start: ph.pose,
                                     photo_index: i,
                                 }
                             }).collect();
@@ -286,7 +609,7 @@ pub fn handle_input_events(
                                     //collect all photos which are under the cursor
                                     let clicked_photos: Vec<(usize, &Photo)> =
                                         photos.iter().enumerate().filter(|(_, ph)| {
-                                            ph.orientation().contains(world_coords)
+                                            ph.contains(camera_model, lens_correction, world_coords)
                                         }).collect();
 
                                     let next_photo =
@@ -331,12 +654,13 @@ pub fn handle_input_events(
                                 match *state {
                                     State::Pressed => {
                                         control_state.active_rotate_drag =
-                                            control_state.selected_photo_index.map(|index| {
+                                            control_state.selected_photo_index.filter(|index| !photos[*index].locked).map(|index| {
                                                 RotateDrag {
                                                     mouse_start: world_coords,
                                                     mouse_coords: world_coords,
                                                     translate_start: photos[index].orientation().translation(),
                                                     rotate_start: photos[index].orientation().rotation(),
+                                                    pose_start: photos[index].pose,
                                                     photo_index: index,
                                                 }
                                             });
@@ -349,18 +673,27 @@ pub fn handle_input_events(
                                         control_state.active_rotate_all_photos_drag =
 
                                             //create a new active RotateDrag instance for every photo
-                                            photos.iter().enumerate().map(|(index, p)| {
+                                            photos.iter().enumerate().filter(|(_, p)| !p.locked).map(|(index, p)| {
                                                 RotateDrag {
                                                     mouse_start: world_coords,
                                                     mouse_coords: world_coords,
                                                     translate_start: p.orientation().translation(),
                                                     rotate_start: p.orientation().rotation(),
+                                                    pose_start: p.pose,
                                                     photo_index: index,
                                                 }
                                             }).collect();
                                     },
                                     State::Released => control_state.active_rotate_all_photos_drag = Vec::new(),
                                 }
+                            MouseTool::PaintSeams =>
+                                match *state {
+                                    State::Pressed => {
+                                        control_state.active_seam_paint = true;
+                                        redraw |= paint_seams(control_state, seam_map, photos, camera_model, world_coords, viewport_geometry);
+                                    },
+                                    State::Released => control_state.active_seam_paint = false,
+                                }
 
                         }
 
@@ -375,10 +708,27 @@ pub fn handle_input_events(
 
                     control_state.mouse_location_ui_text =
                     format!("pixel_coords: {:?}\nworld_coords: {:?}", pixel_coords, world_coords);
+
+                    if let CameraModel::Spherical(projection) = camera_model {
+                        if let Some((yaw, pitch)) = projection.direction(world_coords).map(|direction| direction.yaw_pitch()) {
+                            control_state.mouse_location_ui_text += &format!("\nyaw: {:.2}° pitch: {:.2}°", yaw, pitch);
+                        }
+                    }
                 }
 
                 if *handled {break};
 
+                let moves_photos =
+                    control_state.active_drag.is_some() ||
+                    !control_state.active_drag_all_photos.is_empty() ||
+                    (control_state.active_rotation_point.is_some() &&
+                        (control_state.active_rotate_drag.is_some() || !control_state.active_rotate_all_photos_drag.is_empty()));
+
+                //moved photos no longer match the seam map
+                if moves_photos {
+                    *seam_map = None;
+                }
+
                 if let Some(ref mut pan) = control_state.active_pan {
 
                     redraw = true;
@@ -387,16 +737,17 @@ pub fn handle_input_events(
                     viewport_geometry.camera_position.y = pan.camera_start.y as f64 + ((position.1 - pan.mouse_start.1) * viewport_geometry.world_units_per_pixel());
                 }
 
+                if control_state.active_seam_paint {
+
+                    let world_coords = viewport_geometry.pixels_to_world(&PixelCoords{x: position.0, y: position.1});
+                    redraw |= paint_seams(control_state, seam_map, photos, camera_model, world_coords, viewport_geometry);
+                }
+
                 if let Some(ref mut drag) = control_state.active_drag {
 
                     redraw = true;
 
-                    let new_translation = WorldCoords {
-                        x: drag.photo_start.x as f64 + ((position.0 - drag.mouse_start.0) * viewport_geometry.world_units_per_pixel()),
-                        y: drag.photo_start.y as f64 - ((position.1 - drag.mouse_start.1) * viewport_geometry.world_units_per_pixel()),
-                    };
-
-                    photos[drag.photo_index].set_translation(new_translation);
+                    drag_photo_to(&mut photos[drag.photo_index], drag, camera_model, *position, viewport_geometry);
                 }
 
                 if !control_state.active_drag_all_photos.is_empty() {
@@ -405,12 +756,7 @@ pub fn handle_input_events(
 
                     for ref drag in &control_state.active_drag_all_photos {
 
-                        let new_translation = WorldCoords {
-                            x: drag.photo_start.x as f64 + ((position.0 - drag.mouse_start.0) * viewport_geometry.world_units_per_pixel()),
-                            y: drag.photo_start.y as f64 - ((position.1 - drag.mouse_start.1) * viewport_geometry.world_units_per_pixel()),
-                        };
-
-                        photos[drag.photo_index].set_translation(new_translation);
+                        drag_photo_to(&mut photos[drag.photo_index], drag, camera_model, *position, viewport_geometry);
                     }
                 }
 
@@ -432,10 +778,18 @@ pub fn handle_input_events(
                     let drag_angle: cgmath::Deg<f32> = axis_to_start.angle(axis_to_drag).into();
                     let drag_angle = drag_angle.0;
 
-                    //reset to values from start of rotation before rotate_around_point
-                    photos[rotate_drag.photo_index].set_rotation(rotate_drag.rotate_start);
-                    photos[rotate_drag.photo_index].set_translation(rotate_drag.translate_start);
-                    photos[rotate_drag.photo_index].rotate_around_point(drag_angle, rp.point);
+                    match camera_model {
+                        CameraModel::Planar => {
+                            //reset to values from start of rotation before rotate_around_point
+                            photos[rotate_drag.photo_index].set_rotation(rotate_drag.rotate_start);
+                            photos[rotate_drag.photo_index].set_translation(rotate_drag.translate_start);
+                            photos[rotate_drag.photo_index].rotate_around_point(drag_angle, rp.point);
+                        },
+                        CameraModel::Spherical(_) => {
+                            //roll around the photo's own center (roll is clockwise)
+                            photos[rotate_drag.photo_index].pose.roll = rotate_drag.pose_start.roll - drag_angle as f64;
+                        },
+                    }
                 };
 
                 if let Some(ref mut rotate_drag) = control_state.active_rotate_drag {
@@ -494,4 +848,57 @@ pub fn handle_input_events(
     }
 
     redraw
-}
\ No newline at end of file
+}
+
+/// PaintSeams: assigns seam map cells under the brush to the selected photo, returns true if any changed
+fn paint_seams(
+    control_state: &ControlState,
+    seam_map: &mut Option<SeamMap>,
+    photos: &[Photo],
+    camera_model: &CameraModel,
+    world_coords: WorldCoords,
+    viewport_geometry: &ViewportGeometry,
+) -> bool {
+
+    match (seam_map, control_state.selected_photo_index) {
+        (Some(seam_map), Some(index)) => {
+            let radius = control_state.seam_brush_radius * viewport_geometry.world_units_per_pixel();
+            seam_map.paint(world_coords, radius, index, |point| photos[index].contains(camera_model, control_state.dewarp_shader.lens_correction(), point))
+        },
+        _ => false,
+    }
+}
+
+/// moves a dragged photo with the mouse:
+/// translates it in the planar camera model, or turns it (yaw and pitch) in the spherical camera model
+fn drag_photo_to(photo: &mut Photo, drag: &Drag, camera_model: &CameraModel, position: (f64, f64), viewport_geometry: &ViewportGeometry) {
+
+    let offset = WorldCoords {
+        x: (position.0 - drag.mouse_start.0) * viewport_geometry.world_units_per_pixel(),
+        y: -(position.1 - drag.mouse_start.1) * viewport_geometry.world_units_per_pixel(),
+    };
+
+    match camera_model {
+        CameraModel::Planar => photo.set_translation(drag.photo_start + offset),
+        CameraModel::Spherical(projection) => {
+            //turn the photo by the yaw, pitch change between the projected mouse positions
+            //(or if either can't be unprojected, by their distance at the projection's center)
+            let start = viewport_geometry.pixels_to_world(&PixelCoords{x: drag.mouse_start.0, y: drag.mouse_start.1});
+            let end = start + offset;
+
+            let (d_yaw, d_pitch) = match (projection.direction(start), projection.direction(end)) {
+                (Some(start), Some(end)) => {
+                    let ((yaw1, pitch1), (yaw2, pitch2)) = (start.yaw_pitch(), end.yaw_pitch());
+                    ((yaw2 - yaw1 + 540.0).rem_euclid(360.0) - 180.0, pitch2 - pitch1)
+                },
+                _ => ((offset.x / projection.scale).to_degrees(), (offset.y / projection.scale).to_degrees()),
+            };
+
+            photo.pose = CameraPose {
+                yaw: drag.pose_start.yaw + d_yaw,
+                pitch: (drag.pose_start.pitch + d_pitch).clamp(-90.0, 90.0),
+                roll: drag.pose_start.roll,
+            };
+        },
+    }
+}
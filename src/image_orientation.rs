@@ -0,0 +1,245 @@
+/// How a stored image is transformed to display it upright (the EXIF orientation tag)
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ImageOrientation {
+    /// 1: stored upright
+    Normal,
+    /// 2: mirrored left to right
+    MirrorHorizontal,
+    /// 3: rotated 180°
+    Rotate180,
+    /// 4: mirrored top to bottom
+    MirrorVertical,
+    /// 5: mirrored across the top left to bottom right diagonal
+    Transpose,
+    /// 6: rotated 90° clockwise for display
+    Rotate90,
+    /// 7: mirrored across the top right to bottom left diagonal
+    Transverse,
+    /// 8: rotated 270° clockwise for display
+    Rotate270,
+}
+
+impl Default for ImageOrientation {
+    fn default() -> Self {
+        ImageOrientation::Normal
+    }
+}
+
+impl ImageOrientation {
+
+    /// from an EXIF orientation tag value: unknown values are treated as Normal
+    pub fn from_exif(value: u16) -> Self {
+
+        match value {
+            2 => ImageOrientation::MirrorHorizontal,
+            3 => ImageOrientation::Rotate180,
+            4 => ImageOrientation::MirrorVertical,
+            5 => ImageOrientation::Transpose,
+            6 => ImageOrientation::Rotate90,
+            7 => ImageOrientation::Transverse,
+            8 => ImageOrientation::Rotate270,
+            _ => ImageOrientation::Normal,
+        }
+    }
+
+    /// true IFF the upright image's width is the stored image's height
+    pub fn swaps_dimensions(&self) -> bool {
+
+        matches!(self, ImageOrientation::Transpose | ImageOrientation::Rotate90 | ImageOrientation::Transverse | ImageOrientation::Rotate270)
+    }
+
+    /// (width, height) of the upright image
+    pub fn upright_dimensions(&self, stored_width: u32, stored_height: u32) -> (u32, u32) {
+
+        if self.swaps_dimensions() {(stored_height, stored_width)} else {(stored_width, stored_height)}
+    }
+
+    /// (width, height) of the stored image
+    pub fn stored_dimensions(&self, upright_width: u32, upright_height: u32) -> (u32, u32) {
+
+        self.upright_dimensions(upright_width, upright_height)
+    }
+
+    /// the clockwise rotation in degrees from the stored image to the upright image
+    /// (mirrored orientations then mirror left to right)
+    pub fn rotation(&self) -> f64 {
+
+        match self {
+            ImageOrientation::Normal | ImageOrientation::MirrorHorizontal => 0.0,
+            ImageOrientation::Rotate90 | ImageOrientation::Transpose => 90.0,
+            ImageOrientation::Rotate180 | ImageOrientation::MirrorVertical => 180.0,
+            ImageOrientation::Rotate270 | ImageOrientation::Transverse => 270.0,
+        }
+    }
+
+    /// converts a stored image roll in degrees (positive = clockwise, as in PTO files) to an upright image roll
+    pub fn upright_roll(&self, stored_roll: f64) -> f64 {
+
+        normalized_degrees(stored_roll - self.rotation())
+    }
+
+    /// converts an upright image roll in degrees to a stored image roll
+    pub fn stored_roll(&self, upright_roll: f64) -> f64 {
+
+        normalized_degrees(upright_roll + self.rotation())
+    }
+
+    /// converts a horizontal field of view in degrees across the stored image (as in PTO files)
+    /// to one across the upright image, for a `stored_width` x `stored_height` stored image
+    pub fn upright_fov(&self, stored_fov: f64, stored_width: u32, stored_height: u32) -> f64 {
+
+        if self.swaps_dimensions() {scaled_fov(stored_fov, stored_height, stored_width)} else {stored_fov}
+    }
+
+    /// converts a horizontal field of view in degrees across the upright image
+    /// to one across the stored image, for a `stored_width` x `stored_height` stored image
+    pub fn stored_fov(&self, upright_fov: f64, stored_width: u32, stored_height: u32) -> f64 {
+
+        if self.swaps_dimensions() {scaled_fov(upright_fov, stored_width, stored_height)} else {upright_fov}
+    }
+
+    /// the transform from upright back to stored
+    fn inverse(&self) -> Self {
+
+        match self {
+            ImageOrientation::Rotate90 => ImageOrientation::Rotate270,
+            ImageOrientation::Rotate270 => ImageOrientation::Rotate90,
+            other => *other,
+        }
+    }
+
+    /// applies this transform to image coords (range [0,1], y down)
+    fn transform(&self, (x, y): (f64, f64)) -> (f64, f64) {
+
+        match self {
+            ImageOrientation::Normal => (x, y),
+            ImageOrientation::MirrorHorizontal => (1.0 - x, y),
+            ImageOrientation::Rotate180 => (1.0 - x, 1.0 - y),
+            ImageOrientation::MirrorVertical => (x, 1.0 - y),
+            ImageOrientation::Transpose => (y, x),
+            ImageOrientation::Rotate90 => (1.0 - y, x),
+            ImageOrientation::Transverse => (1.0 - y, 1.0 - x),
+            ImageOrientation::Rotate270 => (y, 1.0 - x),
+        }
+    }
+
+    /// maps stored image texture coords (range [0,1], y up) to upright image texture coords
+    pub fn upright_texture_coords(&self, (u, v): (f64, f64)) -> (f64, f64) {
+
+        let (x, y) = self.transform((u, 1.0 - v));
+        (x, 1.0 - y)
+    }
+
+    /// maps upright image texture coords (range [0,1], y up) to stored image texture coords
+    pub fn stored_texture_coords(&self, (u, v): (f64, f64)) -> (f64, f64) {
+
+        let (x, y) = self.inverse().transform((u, 1.0 - v));
+        (x, 1.0 - y)
+    }
+}
+
+/// an angle in degrees, in the range (-180, 180]
+fn normalized_degrees(angle: f64) -> f64 {
+
+    180.0 - (180.0 - angle).rem_euclid(360.0)
+}
+
+/// the (rectilinear) field of view in degrees across `new_size` pixels, from `fov` across `size` pixels
+/// (unchanged if it can't be scaled)
+fn scaled_fov(fov: f64, new_size: u32, size: u32) -> f64 {
+
+    if fov <= 0.0 || fov >= 180.0 || size == 0 {
+        return fov;
+    }
+
+    let half_tan = (fov / 2.0).to_radians().tan() * new_size as f64 / size as f64;
+    2.0 * half_tan.atan().to_degrees()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALL: [ImageOrientation; 8] = [
+        ImageOrientation::Normal,
+        ImageOrientation::MirrorHorizontal,
+        ImageOrientation::Rotate180,
+        ImageOrientation::MirrorVertical,
+        ImageOrientation::Transpose,
+        ImageOrientation::Rotate90,
+        ImageOrientation::Transverse,
+        ImageOrientation::Rotate270,
+    ];
+
+    #[test]
+    fn from_exif_test() {
+
+        for (index, orientation) in ALL.iter().enumerate() {
+            assert_eq!(ImageOrientation::from_exif(index as u16 + 1), *orientation);
+        }
+
+        assert_eq!(ImageOrientation::from_exif(0), ImageOrientation::Normal);
+        assert_eq!(ImageOrientation::from_exif(9), ImageOrientation::Normal);
+    }
+
+    #[test]
+    fn dimensions_test() {
+
+        assert_eq!(ImageOrientation::Normal.upright_dimensions(300, 200), (300, 200));
+        assert_eq!(ImageOrientation::Rotate180.upright_dimensions(300, 200), (300, 200));
+        assert_eq!(ImageOrientation::Rotate90.upright_dimensions(300, 200), (200, 300));
+        assert_eq!(ImageOrientation::Transverse.stored_dimensions(200, 300), (300, 200));
+    }
+
+    #[test]
+    fn pto_angles_test() {
+
+        //a 4000x3000 stored image, displayed rotated to 3000x4000
+        let fov = ImageOrientation::Rotate90.upright_fov(90.0, 4000, 3000);
+        assert!((fov - 2.0 * 0.75f64.atan().to_degrees()).abs() < 1e-9);
+        assert_eq!(ImageOrientation::Rotate180.upright_fov(90.0, 4000, 3000), 90.0);
+
+        assert_eq!(ImageOrientation::Rotate90.upright_roll(90.0), 0.0);
+        assert_eq!(ImageOrientation::Rotate270.upright_roll(90.0), 180.0);
+        assert_eq!(ImageOrientation::Rotate270.upright_roll(-90.0), 0.0);
+
+        //round trips
+        for orientation in &ALL {
+            let fov = orientation.stored_fov(orientation.upright_fov(70.0, 4000, 3000), 4000, 3000);
+            assert!((fov - 70.0).abs() < 1e-9);
+            assert_eq!(orientation.stored_roll(orientation.upright_roll(-30.0)), -30.0);
+        }
+    }
+
+    #[test]
+    fn texture_coords_test() {
+
+        //stored top left corner (texture coords y up)
+        let top_left = (0.0, 1.0);
+
+        //where the stored top left corner is displayed
+        let upright = |orientation: ImageOrientation| orientation.upright_texture_coords(top_left);
+
+        assert_eq!(upright(ImageOrientation::Normal), (0.0, 1.0));
+        assert_eq!(upright(ImageOrientation::MirrorHorizontal), (1.0, 1.0));
+        assert_eq!(upright(ImageOrientation::Rotate180), (1.0, 0.0));
+        assert_eq!(upright(ImageOrientation::MirrorVertical), (0.0, 0.0));
+        assert_eq!(upright(ImageOrientation::Transpose), (0.0, 1.0));
+        //rotated clockwise: the top left corner is displayed at the top right
+        assert_eq!(upright(ImageOrientation::Rotate90), (1.0, 1.0));
+        assert_eq!(upright(ImageOrientation::Transverse), (1.0, 0.0));
+        assert_eq!(upright(ImageOrientation::Rotate270), (0.0, 0.0));
+
+        //stored top right corner: Transpose and Rotate90 differ by a mirror
+        assert_eq!(ImageOrientation::Transpose.upright_texture_coords((1.0, 1.0)), (0.0, 0.0));
+        assert_eq!(ImageOrientation::Rotate90.upright_texture_coords((1.0, 1.0)), (1.0, 0.0));
+
+        //round trips
+        for orientation in &ALL {
+            for &uv in &[(0.25, 0.5), (0.0, 0.75), (1.0, 0.125)] {
+                assert_eq!(orientation.stored_texture_coords(orientation.upright_texture_coords(uv)), uv);
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use serde::{Serialize, Deserialize};
+
+/// PTO-style lens parameters
+#[derive(Debug, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct LensParameters {
+    /// horizontal field of view in degrees
+    pub fov: f64,
+    /// ptlens-style radial distortion coefficients
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    /// lens center shift in pixels
+    pub d: f64,
+    pub e: f64,
+}
+
+/// whether photos are drawn with their lens distortion corrected
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum LensCorrection {
+    Off,
+    On,
+}
+
+impl LensCorrection {
+
+    /// the lens model photos are drawn with: `lens`, or (Off) `lens` without distortion or center shift
+    pub fn apply(self, lens: &LensParameters) -> LensParameters {
+
+        match self {
+            LensCorrection::On => *lens,
+            LensCorrection::Off => LensParameters{a: 0.0, b: 0.0, c: 0.0, d: 0.0, e: 0.0, ..*lens},
+        }
+    }
+}
+
+/// the smallest vignetting brightness that is corrected: darker is treated as this
+const MIN_VIGNETTING_BRIGHTNESS: f64 = 0.05;
+
+/// PTO-style radial vignetting polynomial (`Va`..`Vd`): the relative brightness
+/// a + b r² + c r⁴ + d r⁶ at radius r from the image center (1.0 at the image corners)
+///
+/// (a lens' vignetting is shared by all photos taken with it: see Photo::lens_id)
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct Vignetting {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+    pub d: f64,
+}
+
+impl Default for Vignetting {
+
+    fn default() -> Self {
+        Self {
+            a: 1.0,
+            b: 0.0,
+            c: 0.0,
+            d: 0.0,
+        }
+    }
+}
+
+impl Vignetting {
+
+    /// the relative brightness at `radius`
+    pub fn brightness(&self, radius: f64) -> f64 {
+
+        let r2 = radius * radius;
+        self.a + r2 * (self.b + r2 * (self.c + r2 * self.d))
+    }
+
+    /// the radius of stored image texture coords (range [0,1]), as the photo shaders' vignetting correction
+    pub fn radius((u, v): (f64, f64), image_width: u32, image_height: u32) -> f64 {
+
+        let (width, height) = (image_width as f64, image_height as f64);
+        let corner = (width * width + height * height).sqrt() / 2.0;
+
+        if corner == 0.0 {
+            return 0.0;
+        }
+
+        ((u - 0.5) * width).hypot((v - 0.5) * height) / corner
+    }
+
+    /// the multiplier of linear light that corrects vignetting at `radius`
+    pub fn gain(&self, radius: f64) -> f64 {
+
+        1.0 / self.brightness(radius).max(MIN_VIGNETTING_BRIGHTNESS)
+    }
+}
+
+impl LensParameters {
+
+    /// the lens center's offset from the image center in texture coords (range [0,1], y up)
+    ///
+    /// (`d` and `e` are in pixels, with `e` positive = down)
+    pub fn center_shift(&self, image_width: u32, image_height: u32) -> (f64, f64) {
+
+        let shift = |pixels: f64, size: u32| if size == 0 {0.0} else {pixels / size as f64};
+
+        (shift(self.d, image_width), -shift(self.e, image_height))
+    }
+
+    /// the ptlens polynomial: distorted radius for an undistorted radius
+    ///
+    /// (radius 1.0 is half of the shorter image side)
+    pub fn distorted_radius(&self, r_u: f64) -> f64 {
+
+        let LensParameters{a, b, c, ..} = *self;
+
+        a * r_u.powi(4) + b * r_u.powi(3) + c * r_u.powi(2) + (1.0 - a - b - c) * r_u
+    }
+
+    /// derivative of `distorted_radius`
+    fn distorted_radius_slope(&self, r_u: f64) -> f64 {
+
+        let LensParameters{a, b, c, ..} = *self;
+
+        4.0 * a * r_u.powi(3) + 3.0 * b * r_u.powi(2) + 2.0 * c * r_u + (1.0 - a - b - c)
+    }
+
+    /// the inverse of `distorted_radius`, by Newton's method
+    ///
+    /// returns None if the polynomial can't be inverted at this radius
+    pub fn undistorted_radius(&self, r_d: f64) -> Option<f64> {
+
+        const MAX_ITERATIONS: usize = 50;
+        const TOLERANCE: f64 = 1e-12;
+
+        let mut r_u = r_d;
+
+        for _ in 0..MAX_ITERATIONS {
+
+            let error = self.distorted_radius(r_u) - r_d;
+
+            if error.abs() <= TOLERANCE {
+                return Some(r_u);
+            }
+
+            let slope = self.distorted_radius_slope(r_u);
+
+            //past a turning point, radii no longer map one-to-one
+            if slope <= 0.0 {
+                return None;
+            }
+
+            r_u -= error / slope;
+
+            if !r_u.is_finite() || r_u < 0.0 {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    /// Maps undistorted texture coords (range [0,1], y up) to the distorted (source image)
+    /// texture coords that texture_dewarp2.frag samples for them
+    pub fn distort(&self, uv: (f64, f64), image_width: u32, image_height: u32) -> (f64, f64) {
+
+        let geometry = RadiusGeometry::new(self, image_width, image_height);
+        let image_coords = geometry.image_coords(uv);
+
+        let r_u = geometry.radius(image_coords);
+        let ratio = if r_u != 0.0 {self.distorted_radius(r_u) / r_u} else {0.0};
+
+        geometry.texture_coords(image_coords, ratio)
+    }
+
+    /// The inverse of `distort`: maps distorted (source image) texture coords to undistorted texture coords
+    ///
+    /// returns None if the polynomial can't be inverted at this point
+    pub fn undistort(&self, distorted: (f64, f64), image_width: u32, image_height: u32) -> Option<(f64, f64)> {
+
+        let geometry = RadiusGeometry::new(self, image_width, image_height);
+        let image_coords = geometry.image_coords(distorted);
+
+        let r_d = geometry.radius(image_coords);
+        let ratio = if r_d != 0.0 {self.undistorted_radius(r_d)? / r_d} else {1.0};
+
+        Some(geometry.texture_coords(image_coords, ratio))
+    }
+}
+
+/// photo_common.glsl's conversion between texture coords and polynomial radii
+struct RadiusGeometry {
+    image_center: (f64, f64),
+    aspect_x_to_y: f64,
+}
+
+impl RadiusGeometry {
+
+    fn new(lens: &LensParameters, image_width: u32, image_height: u32) -> Self {
+
+        let (shift_x, shift_y) = lens.center_shift(image_width, image_height);
+
+        let aspect_x_to_y =
+        if image_width == 0 || image_height == 0 {
+            1.0
+        }
+        else {
+            image_width as f64 / image_height as f64
+        };
+
+        Self {
+            image_center: (0.5 + shift_x, 0.5 + shift_y),
+            aspect_x_to_y,
+        }
+    }
+
+    /// texture coords relative to the lens center
+    fn image_coords(&self, (x, y): (f64, f64)) -> (f64, f64) {
+
+        (x - self.image_center.0, y - self.image_center.1)
+    }
+
+    /// radius from the lens center, where 1.0 is half of the shorter image side
+    fn radius(&self, (x, y): (f64, f64)) -> f64 {
+
+        //in units of image height
+        let x = x * self.aspect_x_to_y;
+
+        //the polynomial expects texture range |[-1,1]| = 2
+        (x * x + y * y).sqrt() * 2.0 / self.aspect_x_to_y.min(1.0)
+    }
+
+    /// texture coords at image coords scaled by `ratio` from the lens center
+    fn texture_coords(&self, (x, y): (f64, f64), ratio: f64) -> (f64, f64) {
+
+        (self.image_center.0 + x * ratio, self.image_center.1 + y * ratio)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    /// a line-by-line port of photo_common.glsl's distorted_texture_coords()
+    fn shader_distorted(uvs: (f64, f64), aspect_x_to_y: f64, lens_a: f64, lens_b: f64, lens_c: f64, lens_center_shift: (f64, f64)) -> (f64, f64) {
+
+        let image_center = (0.5 + lens_center_shift.0, 0.5 + lens_center_shift.1);
+        let image_coords = (uvs.0 - image_center.0, uvs.1 - image_center.1);
+
+        let mut asp_coords = image_coords;
+        asp_coords.0 *= aspect_x_to_y;
+
+        let mut r_u = (asp_coords.0 * asp_coords.0 + asp_coords.1 * asp_coords.1).sqrt();
+
+        let (a, b, c) = (lens_a, lens_b, lens_c);
+
+        r_u *= 2.0;
+        r_u /= aspect_x_to_y.min(1.0);
+
+        let r_d = a * r_u.powf(4.0) + b * r_u.powf(3.0) + c * r_u.powf(2.0) + (1.0 - a - b - c) * r_u;
+
+        let ratio = if r_u != 0.0 {r_d / r_u} else {0.0};
+
+        (image_center.0 + image_coords.0 * ratio, image_center.1 + image_coords.1 * ratio)
+    }
+
+    fn test_lenses() -> Vec<LensParameters> {
+        vec![
+            LensParameters::default(),
+            //lensfun-style entry (see photo_common.glsl)
+            LensParameters{fov: 50.0, a: 0.0019098468424889991, b: -0.0028266879132016103, c: 0.009532148272374459, d: 0.0, e: 0.0},
+            //strong barrel distortion, shifted center
+            LensParameters{fov: 90.0, a: 0.0, b: 0.0, c: -0.05, d: 30.0, e: -20.0},
+        ]
+    }
+
+    #[test]
+    fn center_shift_test() {
+
+        let lens = LensParameters{d: 46.0, e: -153.5, ..Default::default()};
+
+        assert_eq!(lens.center_shift(920, 614), (0.05, 0.25));
+        assert_eq!(lens.center_shift(0, 0), (0.0, 0.0));
+        assert_eq!(LensParameters::default().center_shift(920, 614), (0.0, 0.0));
+    }
+
+    #[test]
+    fn distort_matches_shader_test() {
+
+        let sizes = [(920, 614), (614, 920), (500, 500)];
+        let uvs = [(0.0, 0.0), (0.5, 0.5), (1.0, 1.0), (0.25, 0.8), (0.9, 0.1), (0.5, 0.0)];
+
+        for lens in test_lenses() {
+            for &(width, height) in &sizes {
+
+                let center_shift = lens.center_shift(width, height);
+                let aspect_x_to_y = width as f64 / height as f64;
+
+                for &uv in &uvs {
+
+                    let expected = shader_distorted(uv, aspect_x_to_y, lens.a, lens.b, lens.c, center_shift);
+                    let actual = lens.distort(uv, width, height);
+
+                    assert_approx_eq!(actual.0, expected.0, 1e-12);
+                    assert_approx_eq!(actual.1, expected.1, 1e-12);
+                }
+            }
+        }
+
+        //no distortion: identity
+        let lens = LensParameters::default();
+        assert_eq!(lens.distort((0.25, 0.75), 920, 614), (0.25, 0.75));
+    }
+
+    #[test]
+    fn undistort_test() {
+
+        for lens in test_lenses() {
+            for &(width, height) in &[(920, 614), (614, 920)] {
+                for &uv in &[(0.0, 0.0), (0.5, 0.5), (1.0, 0.3), (0.1, 0.95)] {
+
+                    let distorted = lens.distort(uv, width, height);
+                    let undistorted = lens.undistort(distorted, width, height).unwrap();
+
+                    assert_approx_eq!(undistorted.0, uv.0, 1e-9);
+                    assert_approx_eq!(undistorted.1, uv.1, 1e-9);
+                }
+            }
+        }
+
+        //radius round trip
+        let lens = test_lenses()[1];
+        for &r_u in &[0.0, 0.3, 1.0, 1.8] {
+            assert_approx_eq!(lens.undistorted_radius(lens.distorted_radius(r_u)).unwrap(), r_u, 1e-9);
+        }
+
+        //beyond the polynomial's turning point, there is no inverse
+        let lens = LensParameters{c: -0.5, ..Default::default()};
+        assert_eq!(lens.undistorted_radius(1.2), None);
+    }
+
+    #[test]
+    fn lens_correction_test() {
+
+        let lens = test_lenses()[2];
+        assert_eq!(LensCorrection::On.apply(&lens), lens);
+
+        //no distortion or center shift: undistort is the identity
+        let uncorrected = LensCorrection::Off.apply(&lens);
+        assert_eq!(uncorrected.fov, lens.fov);
+        let (u, v) = uncorrected.undistort((0.1, 0.9), 400, 300).unwrap();
+        assert_approx_eq!(u, 0.1);
+        assert_approx_eq!(v, 0.9);
+    }
+
+    #[test]
+    fn vignetting_test() {
+
+        //no vignetting
+        let vignetting = Vignetting::default();
+        assert_eq!(vignetting.brightness(1.0), 1.0);
+        assert_eq!(vignetting.gain(0.7), 1.0);
+
+        let vignetting = Vignetting{a: 1.0, b: -0.2, c: -0.1, d: 0.05};
+        assert_eq!(vignetting.brightness(0.0), 1.0);
+        assert_approx_eq!(vignetting.brightness(1.0), 0.75);
+        assert_approx_eq!(vignetting.gain(1.0), 1.0 / 0.75);
+
+        //too dark to correct
+        assert_approx_eq!(Vignetting{a: 0.0, ..vignetting}.gain(0.0), 1.0 / MIN_VIGNETTING_BRIGHTNESS);
+
+        //radius 1.0 is at the corners, for any aspect ratio
+        for &(width, height) in &[(920, 614), (614, 920)] {
+            assert_eq!(Vignetting::radius((0.5, 0.5), width, height), 0.0);
+            assert_approx_eq!(Vignetting::radius((0.0, 1.0), width, height), 1.0);
+            assert_approx_eq!(Vignetting::radius((1.0, 0.0), width, height), 1.0);
+        }
+        assert_approx_eq!(Vignetting::radius((1.0, 0.5), 300, 400), 0.6);
+        assert_eq!(Vignetting::radius((1.0, 1.0), 0, 0), 0.0);
+    }
+}
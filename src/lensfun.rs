@@ -0,0 +1,344 @@
+use std::error::Error;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use crate::lens::LensParameters;
+
+/// ptlens distortion coefficients, calibrated at one focal length
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PtlensCalibration {
+    /// in mm
+    pub focal: f64,
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+/// A lens entry from a lensfun database
+#[derive(Debug, PartialEq, Clone)]
+pub struct LensfunLens {
+    /// every (possibly localized) maker name
+    pub makers: Vec<String>,
+    /// every (possibly localized) model name
+    pub models: Vec<String>,
+    /// distortion calibrations, sorted by focal length
+    pub distortion: Vec<PtlensCalibration>,
+}
+
+impl LensfunLens {
+
+    /// ptlens coefficients at a focal length (in mm):
+    /// linearly interpolated between calibrated focal lengths, or the nearest calibration outside them
+    ///
+    /// returns None if this lens has no distortion calibration
+    pub fn ptlens_coefficients(&self, focal: f64) -> Option<(f64, f64, f64)> {
+
+        let first = self.distortion.first()?;
+        let last = self.distortion.last()?;
+
+        let coefficients = |c: &PtlensCalibration| (c.a, c.b, c.c);
+
+        if focal <= first.focal {
+            return Some(coefficients(first));
+        }
+        if focal >= last.focal {
+            return Some(coefficients(last));
+        }
+
+        let upper = self.distortion.iter().position(|c| c.focal >= focal)?;
+        let (c0, c1) = (&self.distortion[upper - 1], &self.distortion[upper]);
+
+        let t = (focal - c0.focal) / (c1.focal - c0.focal);
+        let lerp = |v0: f64, v1: f64| v0 + (v1 - v0) * t;
+
+        Some((lerp(c0.a, c1.a), lerp(c0.b, c1.b), lerp(c0.c, c1.c)))
+    }
+
+    /// sets a lens' a/b/c distortion coefficients for a focal length (in mm)
+    ///
+    /// returns false (and leaves the lens unchanged) if this lens has no distortion calibration
+    pub fn apply(&self, focal: f64, lens: &mut LensParameters) -> bool {
+
+        match self.ptlens_coefficients(focal) {
+            Some((a, b, c)) => {
+                lens.a = a;
+                lens.b = b;
+                lens.c = c;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LensfunError {
+    Io(String, std::io::Error),
+    Xml(String, roxmltree::Error),
+    /// no lens matches this maker and model
+    LensNotFound(String, String),
+    /// the lens has no ptlens or poly3 distortion calibration
+    NoDistortion(String),
+}
+
+impl Display for LensfunError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LensfunError::Io(path, e) => write!(f, "could not read {}: {}", path, e),
+            LensfunError::Xml(path, e) => write!(f, "could not parse {}: {}", path, e),
+            LensfunError::LensNotFound(maker, model) => write!(f, "no lensfun lens matches {} {}", maker, model),
+            LensfunError::NoDistortion(model) => write!(f, "lensfun lens {} has no ptlens distortion calibration", model),
+        }
+    }
+}
+
+impl Error for LensfunError {}
+
+/// Lens entries from a lensfun XML database
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LensfunDatabase {
+    pub lenses: Vec<LensfunLens>,
+}
+
+impl LensfunDatabase {
+
+    /// loads every .xml file in a lensfun database directory (e.g. /usr/share/lensfun/version_1)
+    pub fn load_directory(path: &str) -> Result<Self, LensfunError> {
+
+        let io_error = |e| LensfunError::Io(path.to_string(), e);
+
+        let mut files: Vec<String> =
+        std::fs::read_dir(path).map_err(io_error)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("xml")))
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+
+        files.sort();
+
+        let mut database = Self::default();
+
+        for file in files {
+            let xml = std::fs::read_to_string(Path::new(&file)).map_err(|e| LensfunError::Io(file.clone(), e))?;
+            database.lenses.extend(Self::parse_lenses(&xml).map_err(|e| LensfunError::Xml(file.clone(), e))?);
+        }
+
+        Ok(database)
+    }
+
+    /// reads the `<lens>` entries of one lensfun XML file
+    pub fn parse_lenses(xml: &str) -> Result<Vec<LensfunLens>, roxmltree::Error> {
+
+        let document = roxmltree::Document::parse(xml)?;
+
+        let lenses =
+        document.root_element().children().filter(|node| node.has_tag_name("lens")).map(|lens| {
+
+            let texts = |tag: &str| -> Vec<String> {
+                lens.children()
+                    .filter(|node| node.has_tag_name(tag))
+                    .filter_map(|node| node.text())
+                    .map(|text| text.trim().to_string())
+                    .collect()
+            };
+
+            let mut distortion: Vec<PtlensCalibration> =
+            lens.children()
+                .filter(|node| node.has_tag_name("calibration"))
+                .flat_map(|calibration| calibration.children())
+                .filter(|node| node.has_tag_name("distortion"))
+                .filter_map(|node| {
+                    let attribute = |name: &str| node.attribute(name).and_then(|value| value.trim().parse::<f64>().ok());
+                    let focal = attribute("focal")?;
+
+                    match node.attribute("model")? {
+                        "ptlens" => Some(PtlensCalibration {
+                            focal,
+                            a: attribute("a").unwrap_or(0.0),
+                            b: attribute("b").unwrap_or(0.0),
+                            c: attribute("c").unwrap_or(0.0),
+                        }),
+                        //Rd = Ru * (1 - k1 + k1 * Ru^2): ptlens with b = k1
+                        "poly3" => Some(PtlensCalibration {
+                            focal,
+                            a: 0.0,
+                            b: attribute("k1").unwrap_or(0.0),
+                            c: 0.0,
+                        }),
+                        _ => None,
+                    }
+                })
+                .collect();
+
+            distortion.sort_by(|c0, c1| c0.focal.partial_cmp(&c1.focal).unwrap_or(Ordering::Equal));
+
+            LensfunLens {
+                makers: texts("maker"),
+                models: texts("model"),
+                distortion,
+            }
+        }).collect();
+
+        Ok(lenses)
+    }
+
+    /// finds a lens by maker and model name (case insensitive, any localized name):
+    /// an exact model match if there is one, otherwise the shortest model name containing `model`
+    pub fn find(&self, maker: &str, model: &str) -> Option<&LensfunLens> {
+
+        let maker = maker.trim().to_lowercase();
+        let model = model.trim().to_lowercase();
+
+        let maker_matches = |lens: &&LensfunLens| {
+            maker.is_empty() || lens.makers.iter().any(|m| m.to_lowercase() == maker)
+        };
+        let by_maker = || self.lenses.iter().filter(maker_matches);
+
+        let names = |lens: &LensfunLens| -> Vec<String> {lens.models.iter().map(|m| m.to_lowercase()).collect()};
+
+        if let Some(lens) = by_maker().find(|lens| names(lens).contains(&model)) {
+            return Some(lens);
+        }
+
+        by_maker()
+            .filter_map(|lens| {
+                names(lens).into_iter().filter(|name| name.contains(&model)).map(|name| name.len()).min().map(|len| (len, lens))
+            })
+            .min_by_key(|(len, _)| *len)
+            .map(|(_, lens)| lens)
+    }
+
+    /// sets a lens' distortion coefficients from the database entry for `maker` and `model` at `focal` mm
+    pub fn apply(&self, maker: &str, model: &str, focal: f64, lens: &mut LensParameters) -> Result<(), LensfunError> {
+
+        let entry = self.find(maker, model).ok_or_else(|| LensfunError::LensNotFound(maker.to_string(), model.to_string()))?;
+
+        if entry.apply(focal, lens) {
+            Ok(())
+        }
+        else {
+            Err(LensfunError::NoDistortion(model.to_string()))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_matches::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<lensdatabase version="2">
+    <mount>
+        <name>Nikon F</name>
+    </mount>
+    <lens>
+        <maker>Nikon</maker>
+        <model>Nikkor 70-200mm f/2.8G ED VR II AF-S</model>
+        <model lang="de">Nikkor 70-200mm 1:2.8G ED VR II AF-S</model>
+        <mount>Nikon F AF</mount>
+        <cropfactor>1.0</cropfactor>
+        <calibration>
+            <distortion model="ptlens" focal="200" a="0.0019098468424889991" b="-0.0028266879132016103" c="0.009532148272374459"/>
+            <distortion model="ptlens" focal="70" a="0.01" b="-0.03" c="0.02"/>
+            <tca model="poly3" focal="70" br="1.0" vr="1.0"/>
+        </calibration>
+    </lens>
+    <lens>
+        <maker>Nikon</maker>
+        <model>Nikkor 50mm f/1.8G AF-S</model>
+        <calibration>
+            <distortion model="poly3" focal="50" k1="-0.012"/>
+        </calibration>
+    </lens>
+    <lens>
+        <maker>Nikon</maker>
+        <model>Nikkor 50mm f/1.8G AF-S Special Edition</model>
+    </lens>
+</lensdatabase>
+"#;
+
+    fn database() -> LensfunDatabase {
+        LensfunDatabase {lenses: LensfunDatabase::parse_lenses(XML).unwrap()}
+    }
+
+    #[test]
+    fn parse_lenses_test() {
+
+        let database = database();
+
+        assert_eq!(database.lenses.len(), 3);
+        assert_eq!(database.lenses[0].makers, vec!["Nikon".to_string()]);
+        assert_eq!(database.lenses[0].models.len(), 2);
+
+        //sorted by focal length, other calibration types ignored
+        assert_eq!(database.lenses[0].distortion.iter().map(|c| c.focal).collect::<Vec<f64>>(), vec![70.0, 200.0]);
+
+        //poly3 as ptlens
+        assert_eq!(database.lenses[1].distortion, vec![PtlensCalibration{focal: 50.0, a: 0.0, b: -0.012, c: 0.0}]);
+
+        assert!(database.lenses[2].distortion.is_empty());
+
+        assert!(LensfunDatabase::parse_lenses("<lensdatabase><lens></lensdatabase>").is_err());
+    }
+
+    #[test]
+    fn find_test() {
+
+        let database = database();
+
+        let model = |lens: Option<&LensfunLens>| lens.map(|lens| lens.models[0].clone());
+
+        assert_eq!(model(database.find("nikon", "nikkor 50mm f/1.8g af-s")), Some("Nikkor 50mm f/1.8G AF-S".to_string()));
+        //localized name
+        assert_eq!(model(database.find("Nikon", "Nikkor 70-200mm 1:2.8G ED VR II AF-S")), Some("Nikkor 70-200mm f/2.8G ED VR II AF-S".to_string()));
+        //shortest partial match
+        assert_eq!(model(database.find("Nikon", "50mm")), Some("Nikkor 50mm f/1.8G AF-S".to_string()));
+        //any maker
+        assert_eq!(model(database.find("", "Special Edition")), Some("Nikkor 50mm f/1.8G AF-S Special Edition".to_string()));
+
+        assert_eq!(database.find("Canon", "50mm"), None);
+        assert_eq!(database.find("Nikon", "85mm"), None);
+    }
+
+    #[test]
+    fn ptlens_coefficients_test() {
+
+        let lens = &database().lenses[0];
+
+        //calibrated
+        let (a, b, c) = lens.ptlens_coefficients(200.0).unwrap();
+        assert_eq!((a, b, c), (0.0019098468424889991, -0.0028266879132016103, 0.009532148272374459));
+
+        //interpolated
+        let (a, b, c) = lens.ptlens_coefficients(135.0).unwrap();
+        assert_approx_eq!(a, (0.01 + 0.0019098468424889991) / 2.0);
+        assert_approx_eq!(b, (-0.03 - 0.0028266879132016103) / 2.0);
+        assert_approx_eq!(c, (0.02 + 0.009532148272374459) / 2.0);
+
+        //clamped
+        assert_eq!(lens.ptlens_coefficients(24.0), Some((0.01, -0.03, 0.02)));
+        assert_eq!(lens.ptlens_coefficients(300.0), lens.ptlens_coefficients(200.0));
+
+        assert_eq!(database().lenses[2].ptlens_coefficients(50.0), None);
+    }
+
+    #[test]
+    fn apply_test() {
+
+        let database = database();
+        let mut lens = LensParameters{fov: 10.0, a: 1.0, b: 1.0, c: 1.0, d: 5.0, e: 6.0};
+
+        database.apply("Nikon", "Nikkor 50mm f/1.8G AF-S", 50.0, &mut lens).unwrap();
+        assert_eq!(lens, LensParameters{fov: 10.0, a: 0.0, b: -0.012, c: 0.0, d: 5.0, e: 6.0});
+
+        assert_matches!(database.apply("Nikon", "Special Edition", 50.0, &mut lens), Err(LensfunError::NoDistortion(_)));
+        assert_matches!(database.apply("Canon", "EF 50mm", 50.0, &mut lens), Err(LensfunError::LensNotFound(_, _)));
+        assert_eq!(lens.b, -0.012);
+    }
+}
@@ -8,12 +8,30 @@ use three_d::math::vec3;
 
 mod viewport_geometry;
 mod read_pto;
+mod write_pto;
 mod photo;
+mod lens;
+mod lensfun;
+mod exif_metadata;
+mod image_orientation;
+mod spherical;
 mod world_rectangle;
 mod control_state;
 mod gui_controls;
 mod render;
 mod entities;
+mod project;
+mod media_paths;
+mod control_points;
+mod optimize;
+mod residuals;
+mod ransac;
+mod blend;
+mod seams;
+mod exposure;
+mod stitch;
+
+use log::error;
 
 use viewport_geometry::{ViewportGeometry, WorldCoords};
 
@@ -24,6 +42,44 @@ fn main() {
         env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     }
 
+    //headless: stitch to an image file without opening a window
+    if cfg!(not(target_arch = "wasm32")) {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        match stitch::StitchArgs::from_args(&args) {
+            Ok(None) => {},
+            Ok(Some(stitch_args)) => {
+                if let Err(e) = stitch::run(&stitch_args) {
+                    error!("{}", e);
+                    std::process::exit(1);
+                }
+                return;
+            },
+            Err(e) => {
+                error!("{}", e);
+                eprintln!("{}", stitch::USAGE);
+                std::process::exit(1);
+            },
+        }
+    }
+
+    let media_paths =
+    if cfg!(target_arch = "wasm32") {
+        media_paths::MediaPaths::dev_media()
+    }
+    else {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+
+        match media_paths::MediaPaths::from_args(&args) {
+            Ok(media_paths) => media_paths,
+            Err(e) => {
+                error!("{}", e);
+                eprintln!("{}", media_paths::USAGE);
+                std::process::exit(1);
+            },
+        }
+    };
+
     let window = Window::new(WindowSettings{title: "panorama_tool".to_string(), ..Default::default()}).unwrap();
     let context = window.gl().unwrap();
 
@@ -53,50 +109,33 @@ fn main() {
     let mut gui = three_d::GUI::new(&context).unwrap();
 
 
-    let media_path = "../panorama-explorer-dev-media/";
-    let photo_dir = media_path.to_string() + "shoreline_2020_12_31_eighth_scale/";
-
-    let pto_file = photo_dir.clone() + "DSC_9108_12_5 - DSC_9109_12_5.pto";
-    let photos_alignment_string_file = photo_dir.clone() + "photos_alignment_string";
-    let photos_alignment_alt_string_file = photo_dir.clone() + "photos_alignment_alt_string";
-    let map_overlay_image = media_path.to_string() + "misc/4x4.png";
-    let photo_images = vec!(
-        photo_dir.clone() + "DSC_9108_12_5.JPG",
-        photo_dir.clone() + "DSC_9109_12_5.JPG",
-        photo_dir.clone() + "DSC_9110_12_5.JPG",
-    );
-
-    //assemble filepaths to be loaded
-    let filepaths =
+    Loader::load(&media_paths.filepaths(), move |loaded|
     {
-        let mut filepaths =
-        vec!(
-            pto_file.clone(),
-            photos_alignment_string_file.clone(),
-            photos_alignment_alt_string_file.clone(),
-            map_overlay_image.clone(),
-        );
-
-        filepaths.append(&mut photo_images.clone());
-        filepaths
-    };
 
-    Loader::load(&filepaths, move |loaded|
-    {
+        let mut entities = match entities::Entities::new(&context, loaded, &media_paths) {
+            Ok(entities) => entities,
+            Err(e) => {
+                error!("{}", e);
+                if cfg!(not(target_arch = "wasm32")) {
+                    std::process::exit(1);
+                }
+                return;
+            },
+        };
+
+        if media_paths.project_file.is_some() {
+            if let Err(e) = entities.load_project_file(&mut viewport_geometry) {
+                error!("could not load project {}: {}", entities.project_file_path, e);
+            }
+        }
+
+        //the photo shaders share lens distortion and color correction
+        let photo_program = |source: &str| MeshProgram::new(&context, &format!("{}\n{}", include_str!("shaders/photo_common.glsl"), source));
 
-        let mut entities = entities::Entities::new(
-            &context,
-            loaded,
-            &pto_file,
-            &photos_alignment_string_file,
-            &photos_alignment_alt_string_file,
-            &map_overlay_image,
-            &photo_images.iter().map(|s| s.as_ref()).collect()
-        );
-
-        let         texture_program = MeshProgram::new(&context, include_str!("shaders/texture.frag")).unwrap();
-        let  texture_dewarp_program = MeshProgram::new(&context, include_str!("shaders/texture_dewarp.frag")).unwrap();
-        let texture_dewarp2_program = MeshProgram::new(&context, include_str!("shaders/texture_dewarp2.frag")).unwrap();
+        let         texture_program = photo_program(include_str!("shaders/texture.frag")).unwrap();
+        let  texture_dewarp_program = photo_program(include_str!("shaders/texture_dewarp.frag")).unwrap();
+        let texture_dewarp2_program = photo_program(include_str!("shaders/texture_dewarp2.frag")).unwrap();
+        let texture_spherical_program = photo_program(include_str!("shaders/texture_spherical.frag")).unwrap();
         let           color_program = MeshProgram::new(&context, include_str!("shaders/color.frag")).unwrap();
 
 
@@ -127,7 +166,9 @@ fn main() {
                 &mut control_state,
                 &mut viewport_geometry,
                 &mut camera,
+                &entities.camera_model,
                 &mut entities.photos,
+                &mut entities.seam_map,
             );
 
 
@@ -153,6 +194,7 @@ fn main() {
                     &texture_program,
                     &texture_dewarp_program,
                     &texture_dewarp2_program,
+                    &texture_spherical_program,
                     &color_program,
 
                     &viewport_geometry,
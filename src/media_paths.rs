@@ -0,0 +1,336 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf, Component};
+
+use crate::read_pto::{self, PtoFile, PtoParseError};
+use crate::project::{Project, ProjectError};
+
+pub const USAGE: &str = "usage: panorama_tool [PTO_FILE | PROJECT_FILE | IMAGE_FILE... | DIRECTORY]";
+
+/// The files to load at startup
+#[derive(Debug, PartialEq, Clone)]
+pub struct MediaPaths {
+    pub pto_file: Option<String>,
+    /// a project file to apply after loading
+    pub project_file: Option<String>,
+    pub photos_alignment_string_file: Option<String>,
+    pub photos_alignment_alt_string_file: Option<String>,
+    pub map_overlay_image: Option<String>,
+    pub photo_images: Vec<String>,
+}
+
+/// What the command line arguments name
+#[derive(Debug, PartialEq)]
+enum Input {
+    Pto(String),
+    Project(String),
+    Directory(String),
+    Images(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum ArgsError {
+    /// a PTO file, project file, or directory was given with other arguments
+    NotAlone(String),
+    NoImages(String),
+    MissingFiles(Vec<String>),
+    Io(String, std::io::Error),
+    Project(String, ProjectError),
+    Pto(String, PtoParseError),
+}
+
+impl Display for ArgsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArgsError::NotAlone(path) => write!(f, "{} must be the only argument", path),
+            ArgsError::NoImages(dir) => write!(f, "no images found in {}", dir),
+            ArgsError::MissingFiles(paths) => write!(f, "file(s) not found: {}", paths.join(", ")),
+            ArgsError::Io(path, e) => write!(f, "could not read {}: {}", path, e),
+            ArgsError::Project(path, e) => write!(f, "could not read {}: {}", path, e),
+            ArgsError::Pto(path, e) => write!(f, "could not read {}: {}", path, e),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+impl MediaPaths {
+
+    /// the demo panorama in the dev media directory
+    pub fn dev_media() -> Self {
+
+        let media_path = "../panorama-explorer-dev-media/";
+        let photo_dir = media_path.to_string() + "shoreline_2020_12_31_eighth_scale/";
+
+        Self {
+            pto_file: Some(photo_dir.clone() + "DSC_9108_12_5 - DSC_9109_12_5.pto"),
+            project_file: None,
+            photos_alignment_string_file: Some(photo_dir.clone() + "photos_alignment_string"),
+            photos_alignment_alt_string_file: Some(photo_dir.clone() + "photos_alignment_alt_string"),
+            map_overlay_image: Some(media_path.to_string() + "misc/4x4.png"),
+            photo_images: vec!(
+                photo_dir.clone() + "DSC_9108_12_5.JPG",
+                photo_dir.clone() + "DSC_9109_12_5.JPG",
+                photo_dir + "DSC_9110_12_5.JPG",
+            ),
+        }
+    }
+
+    /// gets the files to load from command line arguments (without the program name)
+    ///
+    /// with no arguments, loads the dev media demo
+    pub fn from_args(args: &[String]) -> Result<Self, ArgsError> {
+
+        let input = match classify_args(args, |path| Path::new(path).is_dir())? {
+            Some(input) => input,
+            None => return Ok(Self::dev_media()),
+        };
+
+        let media_paths = match input {
+            Input::Pto(path) => {
+                let s = std::fs::read_to_string(&path).map_err(|e| ArgsError::Io(path.clone(), e))?;
+                let (pto_file, _) = read_pto::read_pto_file_lenient(&s).map_err(|e| ArgsError::Pto(path.clone(), e))?;
+
+                Self {
+                    photo_images: pto_image_paths(&path, &pto_file),
+                    pto_file: Some(path),
+                    ..Self::images(Vec::new())
+                }
+            },
+            Input::Project(path) => {
+                let s = std::fs::read_to_string(&path).map_err(|e| ArgsError::Io(path.clone(), e))?;
+                let project = Project::from_json_str(&s).map_err(|e| ArgsError::Project(path.clone(), e))?;
+
+                Self {
+                    photo_images: project.photos.iter().map(|photo| project_image_path(&path, &photo.source_path)).collect(),
+                    project_file: Some(path),
+                    ..Self::images(Vec::new())
+                }
+            },
+            Input::Directory(path) => {
+                let entries = std::fs::read_dir(&path).map_err(|e| ArgsError::Io(path.clone(), e))?;
+
+                let mut photo_images: Vec<String> =
+                    entries.filter_map(|entry| entry.ok())
+                        .map(|entry| entry.path().to_string_lossy().to_string())
+                        .filter(|path| is_image_file(path))
+                        .collect();
+                photo_images.sort();
+
+                if photo_images.is_empty() {
+                    return Err(ArgsError::NoImages(path));
+                }
+
+                Self::images(photo_images)
+            },
+            Input::Images(paths) => Self::images(paths),
+        };
+
+        let missing_files: Vec<String> =
+            media_paths.filepaths().into_iter().filter(|path| !Path::new(path).is_file()).collect();
+
+        if !missing_files.is_empty() {
+            return Err(ArgsError::MissingFiles(missing_files));
+        }
+
+        Ok(media_paths)
+    }
+
+    fn images(photo_images: Vec<String>) -> Self {
+
+        Self {
+            pto_file: None,
+            project_file: None,
+            photos_alignment_string_file: None,
+            photos_alignment_alt_string_file: None,
+            map_overlay_image: None,
+            photo_images,
+        }
+    }
+
+    /// all files for `three_d::Loader` to load
+    pub fn filepaths(&self) -> Vec<String> {
+
+        let mut filepaths: Vec<String> = vec!(
+            &self.pto_file,
+            &self.photos_alignment_string_file,
+            &self.photos_alignment_alt_string_file,
+            &self.map_overlay_image,
+        ).into_iter().flatten().cloned().collect();
+
+        filepaths.extend(self.photo_images.iter().cloned());
+        filepaths
+    }
+}
+
+fn classify_args(args: &[String], is_dir: impl Fn(&str) -> bool) -> Result<Option<Input>, ArgsError> {
+
+    let input_type = |path: &String| {
+        if path.to_lowercase().ends_with(".pto") {
+            Some(Input::Pto(path.clone()))
+        }
+        else if path.to_lowercase().ends_with(".json") {
+            Some(Input::Project(path.clone()))
+        }
+        else if is_dir(path) {
+            Some(Input::Directory(path.clone()))
+        }
+        else {
+            None
+        }
+    };
+
+    match args {
+        [] => Ok(None),
+        [path] => Ok(Some(input_type(path).unwrap_or_else(|| Input::Images(vec![path.clone()])))),
+        paths => {
+            if let Some(path) = paths.iter().find(|path| input_type(path).is_some()) {
+                return Err(ArgsError::NotAlone(path.clone()));
+            }
+            Ok(Some(Input::Images(paths.to_vec())))
+        },
+    }
+}
+
+fn is_image_file(path: &str) -> bool {
+
+    let path = path.to_lowercase();
+    [".jpg", ".jpeg", ".png"].iter().any(|extension| path.ends_with(extension))
+}
+
+/// PTO image file names are relative to the PTO file's directory
+pub fn pto_image_paths(pto_file_path: &str, pto_file: &PtoFile) -> Vec<String> {
+
+    pto_file.images().iter().map(|image| sibling_path(pto_file_path, &image.file_name)).collect()
+}
+
+/// project image paths are relative to the project file's directory
+pub fn project_image_path(project_file_path: &str, image_path: &str) -> String {
+
+    sibling_path(project_file_path, image_path)
+}
+
+/// the path a project file stores for an image: relative to the project file's directory
+/// (absolute if it can't be made relative, e.g. on another drive)
+pub fn project_relative_image_path(project_file_path: &str, image_path: &str) -> String {
+
+    let dir = Path::new(project_file_path).parent().unwrap_or_else(|| Path::new(""));
+
+    match relative_path(dir, Path::new(image_path)) {
+        Some(path) => path.to_string_lossy().to_string(),
+        None => std::env::current_dir().map_or_else(|_| image_path.to_string(), |cwd| cwd.join(image_path).to_string_lossy().to_string()),
+    }
+}
+
+/// `path` relative to the directory `dir`: None if they aren't both relative or both absolute,
+/// or if `dir` goes up (`..`) after their common prefix
+fn relative_path(dir: &Path, path: &Path) -> Option<PathBuf> {
+
+    if dir.is_absolute() != path.is_absolute() {
+        return None;
+    }
+
+    let dir: Vec<Component> = dir.components().filter(|c| *c != Component::CurDir).collect();
+    let path: Vec<Component> = path.components().filter(|c| *c != Component::CurDir).collect();
+
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    if dir[common..].iter().any(|c| !matches!(c, Component::Normal(_))) {
+        return None;
+    }
+
+    Some(std::iter::repeat(Component::ParentDir).take(dir.len() - common).chain(path[common..].iter().copied()).collect())
+}
+
+/// joins `path` to the directory of `file_path` (absolute paths are kept)
+fn sibling_path(file_path: &str, path: &str) -> String {
+
+    let dir = Path::new(file_path).parent().unwrap_or_else(|| Path::new(""));
+    dir.join(path).to_string_lossy().to_string()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_matches::*;
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn classify_args_test() {
+
+        let is_dir = |path: &str| path == "photos/";
+
+        assert_matches!(classify_args(&[], is_dir), Ok(None));
+
+        assert_eq!(classify_args(&strings(&["a/b.pto"]), is_dir).unwrap(), Some(Input::Pto("a/b.pto".to_string())));
+        assert_eq!(classify_args(&strings(&["a/B.PTO"]), is_dir).unwrap(), Some(Input::Pto("a/B.PTO".to_string())));
+        assert_eq!(classify_args(&strings(&["b.project.json"]), is_dir).unwrap(), Some(Input::Project("b.project.json".to_string())));
+        assert_eq!(classify_args(&strings(&["photos/"]), is_dir).unwrap(), Some(Input::Directory("photos/".to_string())));
+        assert_eq!(classify_args(&strings(&["a.jpg"]), is_dir).unwrap(), Some(Input::Images(strings(&["a.jpg"]))));
+        assert_eq!(classify_args(&strings(&["a.jpg", "b.jpg"]), is_dir).unwrap(), Some(Input::Images(strings(&["a.jpg", "b.jpg"]))));
+
+        assert_matches!(classify_args(&strings(&["a.jpg", "b.pto"]), is_dir), Err(ArgsError::NotAlone(path)) if path == "b.pto");
+        assert_matches!(classify_args(&strings(&["photos/", "a.jpg"]), is_dir), Err(ArgsError::NotAlone(path)) if path == "photos/");
+    }
+
+    #[test]
+    fn is_image_file_test() {
+
+        assert!(is_image_file("a/DSC_9108.JPG"));
+        assert!(is_image_file("a.jpeg"));
+        assert!(is_image_file("a.png"));
+        assert!(!is_image_file("a.pto"));
+        assert!(!is_image_file("jpg"));
+    }
+
+    #[test]
+    fn pto_image_paths_test() {
+
+        let pto_file = read_pto::read_pto_file(
+            "i w920 h614 f0 v50 r0 p0 y0 n\"DSC_9108.JPG\"\n\
+             i w920 h614 f0 v=0 r0 p0 y0 n\"/absolute/DSC_9109.JPG\""
+        ).unwrap();
+
+        assert_eq!(pto_image_paths("media/pano.pto", &pto_file), strings(&["media/DSC_9108.JPG", "/absolute/DSC_9109.JPG"]));
+        assert_eq!(pto_image_paths("pano.pto", &pto_file), strings(&["DSC_9108.JPG", "/absolute/DSC_9109.JPG"]));
+    }
+
+    #[test]
+    fn project_image_path_test() {
+
+        assert_eq!(project_image_path("media/pano.project.json", "photos/a.jpg"), "media/photos/a.jpg");
+        assert_eq!(project_image_path("media/pano.project.json", "/absolute/a.jpg"), "/absolute/a.jpg");
+        assert_eq!(project_image_path("pano.project.json", "a.jpg"), "a.jpg");
+    }
+
+    #[test]
+    fn project_relative_image_path_test() {
+
+        assert_eq!(project_relative_image_path("media/pano.project.json", "media/photos/a.jpg"), "photos/a.jpg");
+        assert_eq!(project_relative_image_path("./media/pano.project.json", "media/a.jpg"), "a.jpg");
+        assert_eq!(project_relative_image_path("media/out/pano.project.json", "media/photos/a.jpg"), "../photos/a.jpg");
+        assert_eq!(project_relative_image_path("pano.project.json", "photos/a.jpg"), "photos/a.jpg");
+        assert_eq!(project_relative_image_path("/media/pano.project.json", "/media/photos/a.jpg"), "photos/a.jpg");
+        assert_eq!(project_relative_image_path("media/pano.project.json", "/absolute/a.jpg"), "/absolute/a.jpg");
+        assert!(Path::new(&project_relative_image_path("../pano.project.json", "a.jpg")).is_absolute());
+
+        //resolving the stored path finds the image again
+        let stored = project_relative_image_path("media/out/pano.project.json", "media/photos/a.jpg");
+        assert_eq!(Path::new(&project_image_path("media/out/pano.project.json", &stored)), Path::new("media/out/../photos/a.jpg"));
+    }
+
+    #[test]
+    fn from_args_missing_files_test() {
+
+        assert_matches!(
+            MediaPaths::from_args(&strings(&["no_such_dir/a.jpg", "no_such_dir/b.jpg"])),
+            Err(ArgsError::MissingFiles(paths)) if paths == strings(&["no_such_dir/a.jpg", "no_such_dir/b.jpg"])
+        );
+
+        assert_matches!(MediaPaths::from_args(&strings(&["no_such_dir/a.pto"])), Err(ArgsError::Io(_, _)));
+    }
+}
@@ -0,0 +1,1062 @@
+use std::error::Error;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+
+use crate::image_orientation::ImageOrientation;
+use crate::lens::LensParameters;
+use crate::photo::Photo;
+use crate::read_pto::{ControlPointPair, ControlPointType};
+use crate::spherical;
+use crate::spherical::{CameraPose, Direction};
+use crate::viewport_geometry::{WorldCoords, PixelCoords};
+use crate::world_rectangle::WorldRectangle;
+
+/// A photo's placement, as seen by the optimizer
+#[derive(Debug, PartialEq, Clone)]
+pub struct AlignmentPhoto {
+    pub orientation: WorldRectangle,
+    /// upright image dimensions in pixels
+    pub image_width: u32,
+    pub image_height: u32,
+    /// control points are in stored image pixel coords, reoriented with this
+    pub image_orientation: ImageOrientation,
+    /// control points are distortion corrected with this lens
+    pub lens: LensParameters,
+    /// photos with the same lens id share a lens (None: this photo's lens is its own)
+    pub lens_id: Option<usize>,
+    /// the camera orientation, for spherical alignment
+    pub pose: CameraPose,
+    /// if true, this photo is not moved
+    pub locked: bool,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct AlignmentOptions {
+    /// the index of a photo which is never moved
+    pub anchor: usize,
+    /// if true, also solve for each photo's uniform scale
+    /// (spherical alignment: each lens' field of view)
+    pub optimize_scale: bool,
+    /// if true, also solve for the a/b/c distortion coefficients of each lens
+    /// (photos with the same lens id share a lens)
+    pub optimize_lens: bool,
+    /// if true, afterwards estimate each lens' vignetting from the photos' colors where they overlap
+    /// (not from control points: see Entities::optimize_alignment)
+    pub optimize_vignetting: bool,
+}
+
+/// An optimized alignment
+#[derive(Debug, PartialEq, Clone)]
+pub struct Alignment {
+    /// new orientations for every photo (in input order)
+    pub orientations: Vec<WorldRectangle>,
+    /// new camera poses for every photo (in input order)
+    pub poses: Vec<CameraPose>,
+    /// new lens parameters for every photo (in input order)
+    pub lenses: Vec<LensParameters>,
+    /// RMS control point distance before optimization:
+    /// in WorldCoords units (planar alignment) or degrees (spherical alignment)
+    pub rms_before: f64,
+    /// RMS control point distance after optimization, in the same units
+    pub rms_after: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AlignmentError {
+    AnchorOutOfRange(usize),
+    /// no normal control point pairs connect two different photos
+    NoControlPoints,
+}
+
+impl Display for AlignmentError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlignmentError::AnchorOutOfRange(index) => write!(f, "anchor photo {} does not exist", index),
+            AlignmentError::NoControlPoints => write!(f, "no control points connect different photos"),
+        }
+    }
+}
+
+impl Error for AlignmentError {}
+
+/// A photo's similarity transform:
+/// world coords = (x, y) + scale * rotate(angle) * centered pixel coords
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Placement {
+    x: f64,
+    y: f64,
+    /// counterclockwise, in radians
+    angle: f64,
+    /// WorldCoords units per pixel
+    scale: f64,
+}
+
+impl Placement {
+
+    fn from_photo(photo: &AlignmentPhoto) -> Self {
+
+        let center = photo.orientation.translation();
+
+        let scale =
+        if photo.image_width == 0 {
+            1.0
+        }
+        else {
+            photo.orientation.width() as f64 / photo.image_width as f64
+        };
+
+        Self {
+            x: center.x,
+            y: center.y,
+            angle: (photo.orientation.rotation() as f64).to_radians(),
+            scale,
+        }
+    }
+
+    fn world_rectangle(&self, photo: &AlignmentPhoto) -> WorldRectangle {
+
+        let mut world_rectangle = WorldRectangle::new(
+            (self.scale * photo.image_width as f64) as f32,
+            (self.scale * photo.image_height as f64) as f32,
+        );
+        world_rectangle.set_rotation(self.angle.to_degrees() as f32);
+        world_rectangle.set_translation(WorldCoords{x: self.x, y: self.y});
+        world_rectangle
+    }
+
+    fn world_coords(&self, centered: (f64, f64)) -> (f64, f64) {
+
+        let (qx, qy) = centered;
+        let (sin, cos) = self.angle.sin_cos();
+
+        (
+            self.x + self.scale * (cos * qx - sin * qy),
+            self.y + self.scale * (sin * qx + cos * qy),
+        )
+    }
+
+    /// partial derivatives of `world_coords` by (x, y, angle, scale)
+    fn derivatives(&self, centered: (f64, f64)) -> [(f64, f64); 4] {
+
+        let (qx, qy) = centered;
+        let (sin, cos) = self.angle.sin_cos();
+
+        [
+            (1.0, 0.0),
+            (0.0, 1.0),
+            (self.scale * (-sin * qx - cos * qy), self.scale * (cos * qx - sin * qy)),
+            (cos * qx - sin * qy, sin * qx + cos * qy),
+        ]
+    }
+}
+
+/// orientation and distortion corrected pixel coords relative to the image center, with y up
+fn centered_pixel_coords(photo: &AlignmentPhoto, lens: &LensParameters, x: f64, y: f64) -> (f64, f64) {
+
+    let (u, v) = Photo::corrected_texture_coords(photo.image_width, photo.image_height, photo.image_orientation, lens, PixelCoords{x, y});
+
+    ((u - 0.5) * photo.image_width as f64, (v - 0.5) * photo.image_height as f64)
+}
+
+/// a control point pair in pixel coords
+struct Constraint {
+    photo1: usize,
+    pixel1: (f64, f64),
+    photo2: usize,
+    pixel2: (f64, f64),
+}
+
+/// photo placements and (shared) lenses
+#[derive(Debug, Clone)]
+struct Solution {
+    placements: Vec<Placement>,
+    lenses: Vec<LensParameters>,
+}
+
+/// The photos and control points being optimized
+struct Problem<'a> {
+    photos: &'a [AlignmentPhoto],
+    /// each photo's index in `Solution::lenses`
+    lens_index: Vec<usize>,
+    constraints: Vec<Constraint>,
+}
+
+impl Problem<'_> {
+
+    fn centered(&self, solution: &Solution, photo: usize, (x, y): (f64, f64)) -> (f64, f64) {
+
+        centered_pixel_coords(&self.photos[photo], &solution.lenses[self.lens_index[photo]], x, y)
+    }
+
+    fn world_coords(&self, solution: &Solution, photo: usize, pixel: (f64, f64)) -> (f64, f64) {
+
+        solution.placements[photo].world_coords(self.centered(solution, photo, pixel))
+    }
+
+    fn sum_of_squares(&self, solution: &Solution) -> f64 {
+
+        self.constraints.iter().map(|c| {
+            let (x1, y1) = self.world_coords(solution, c.photo1, c.pixel1);
+            let (x2, y2) = self.world_coords(solution, c.photo2, c.pixel2);
+            (x1 - x2).powi(2) + (y1 - y2).powi(2)
+        }).sum()
+    }
+
+    /// partial derivatives of a pixel's WorldCoords by its lens' (a, b, c), by central differences
+    fn lens_derivatives(&self, solution: &Solution, photo: usize, pixel: (f64, f64)) -> [(f64, f64); 3] {
+
+        const STEP: f64 = 1e-6;
+
+        let lens = solution.lenses[self.lens_index[photo]];
+        let placement = &solution.placements[photo];
+
+        let world_coords = |lens: LensParameters| {
+            placement.world_coords(centered_pixel_coords(&self.photos[photo], &lens, pixel.0, pixel.1))
+        };
+
+        let derivative = |adjust: fn(&mut LensParameters, f64)| {
+            let mut plus = lens;
+            let mut minus = lens;
+            adjust(&mut plus, STEP);
+            adjust(&mut minus, -STEP);
+            let (plus, minus) = (world_coords(plus), world_coords(minus));
+            ((plus.0 - minus.0) / (2.0 * STEP), (plus.1 - minus.1) / (2.0 * STEP))
+        };
+
+        [
+            derivative(|lens, step| lens.a += step),
+            derivative(|lens, step| lens.b += step),
+            derivative(|lens, step| lens.c += step),
+        ]
+    }
+}
+
+/// solves `a * x = b` by Gaussian elimination with partial pivoting
+pub fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+
+    let n = b.len();
+
+    for column in 0..n {
+
+        let pivot = (column..n).max_by(|&i, &j| a[i][column].abs().partial_cmp(&a[j][column].abs()).unwrap_or(Ordering::Equal))?;
+
+        if a[pivot][column] == 0.0 {
+            return None;
+        }
+
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+
+        for row in column + 1..n {
+            let factor = a[row][column] / a[column][column];
+            let (upper, lower) = a.split_at_mut(row);
+            for (value, pivot_value) in lower[0][column..].iter_mut().zip(&upper[column][column..]) {
+                *value -= factor * pivot_value;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+
+    Some(x)
+}
+
+/// the normal control point pairs between two different (existing) photos
+fn constraints(photos: &[AlignmentPhoto], pairs: &[ControlPointPair]) -> Result<Vec<Constraint>, AlignmentError> {
+
+    let constraints: Vec<Constraint> =
+    pairs.iter().filter_map(|pair| {
+
+        let photo1 = pair.cp1.image_id as usize;
+        let photo2 = pair.cp2.image_id as usize;
+
+        if pair.point_type != ControlPointType::Normal || photo1 == photo2 || photo1 >= photos.len() || photo2 >= photos.len() {
+            return None;
+        }
+
+        Some(Constraint {
+            photo1,
+            pixel1: (pair.cp1.x_coord, pair.cp1.y_coord),
+            photo2,
+            pixel2: (pair.cp2.x_coord, pair.cp2.y_coord),
+        })
+    }).collect();
+
+    if constraints.is_empty() {
+        return Err(AlignmentError::NoControlPoints);
+    }
+
+    Ok(constraints)
+}
+
+/// photos with the same lens id share a lens, with the first such photo's parameters
+/// (photos without a lens id have their own):
+/// returns the shared lenses and each photo's index in them
+///
+/// `photo_lenses` is each photo's (lens id, lens parameters or vignetting)
+pub fn lens_groups<T: Copy>(photo_lenses: impl Iterator<Item = (Option<usize>, T)>) -> (Vec<T>, Vec<usize>) {
+
+    let mut lens_ids: Vec<Option<usize>> = Vec::new();
+    let mut lenses: Vec<T> = Vec::new();
+    let lens_index: Vec<usize> =
+    photo_lenses.map(|(photo_lens_id, photo_lens)| {
+        match lens_ids.iter().position(|&lens_id| photo_lens_id.is_some() && lens_id == photo_lens_id) {
+            Some(index) => index,
+            None => {
+                lens_ids.push(photo_lens_id);
+                lenses.push(photo_lens);
+                lenses.len() - 1
+            },
+        }
+    }).collect();
+
+    (lenses, lens_index)
+}
+
+const MAX_ITERATIONS: usize = 100;
+
+/// Finds the translation and rotation (and optionally, uniform scale and lens distortion) of every photo
+/// which minimizes the squared WorldCoords distances between normal control point pairs.
+///
+/// The anchor photo and locked photos are not moved.
+/// Control point pairs referencing missing photos are ignored.
+pub fn optimize_alignment(
+    photos: &[AlignmentPhoto],
+    pairs: &[ControlPointPair],
+    options: AlignmentOptions,
+) -> Result<Alignment, AlignmentError> {
+
+    if options.anchor >= photos.len() {
+        return Err(AlignmentError::AnchorOutOfRange(options.anchor));
+    }
+
+    let constraints = constraints(photos, pairs)?;
+    let (lenses, lens_index) = lens_groups(photos.iter().map(|photo| (photo.lens_id, photo.lens)));
+
+    let problem = Problem {
+        photos,
+        lens_index,
+        constraints,
+    };
+
+    let parameters_per_photo = if options.optimize_scale {4} else {3};
+
+    //index of each photo's first parameter (None if the photo is fixed)
+    let mut parameter_count = 0;
+    let first_parameter: Vec<Option<usize>> =
+    photos.iter().enumerate().map(|(index, photo)| {
+        if index == options.anchor || photo.locked {
+            None
+        }
+        else {
+            parameter_count += parameters_per_photo;
+            Some(parameter_count - parameters_per_photo)
+        }
+    }).collect();
+
+    //index of each lens' first parameter (a, b, c)
+    let first_lens_parameter: Vec<Option<usize>> =
+    lenses.iter().map(|_| {
+        if options.optimize_lens {
+            parameter_count += 3;
+            Some(parameter_count - 3)
+        }
+        else {
+            None
+        }
+    }).collect();
+
+    let mut solution = Solution {
+        placements: photos.iter().map(Placement::from_photo).collect(),
+        lenses,
+    };
+    let mut cost = problem.sum_of_squares(&solution);
+    let rms = |cost: f64| (cost / problem.constraints.len() as f64).sqrt();
+    let rms_before = rms(cost);
+
+    //Levenberg-Marquardt
+    let mut damping = 1e-3;
+
+    for _ in 0..MAX_ITERATIONS {
+
+        let mut jtj = vec![vec![0.0; parameter_count]; parameter_count];
+        let mut jtr = vec![0.0; parameter_count];
+
+        for c in &problem.constraints {
+
+            let (x1, y1) = problem.world_coords(&solution, c.photo1, c.pixel1);
+            let (x2, y2) = problem.world_coords(&solution, c.photo2, c.pixel2);
+            let residual = (x1 - x2, y1 - y2);
+
+            //(parameter index, d(residual)/d(parameter))
+            let mut terms: Vec<(usize, (f64, f64))> = Vec::new();
+
+            for (photo, pixel, sign) in [(c.photo1, c.pixel1, 1.0), (c.photo2, c.pixel2, -1.0)] {
+
+                if let Some(first) = first_parameter[photo] {
+                    let derivatives = solution.placements[photo].derivatives(problem.centered(&solution, photo, pixel));
+                    for (k, (dx, dy)) in derivatives.iter().take(parameters_per_photo).enumerate() {
+                        terms.push((first + k, (sign * dx, sign * dy)));
+                    }
+                }
+
+                if let Some(first) = first_lens_parameter[problem.lens_index[photo]] {
+                    let derivatives = problem.lens_derivatives(&solution, photo, pixel);
+                    for (k, (dx, dy)) in derivatives.iter().enumerate() {
+                        terms.push((first + k, (sign * dx, sign * dy)));
+                    }
+                }
+            }
+
+            for &(i, (dxi, dyi)) in &terms {
+                jtr[i] += dxi * residual.0 + dyi * residual.1;
+                for &(j, (dxj, dyj)) in &terms {
+                    jtj[i][j] += dxi * dxj + dyi * dyj;
+                }
+            }
+        }
+
+        let mut improved = false;
+
+        while damping < 1e10 {
+
+            let mut a = jtj.clone();
+            for (i, row) in a.iter_mut().enumerate() {
+                row[i] += damping * jtj[i][i] + 1e-9;
+            }
+
+            let step = match solve(a, jtr.iter().map(|v| -v).collect()) {
+                Some(step) => step,
+                None => break,
+            };
+
+            let mut candidate = solution.clone();
+            for (photo, first) in first_parameter.iter().enumerate() {
+                if let Some(first) = *first {
+                    let placement = &mut candidate.placements[photo];
+                    placement.x += step[first];
+                    placement.y += step[first + 1];
+                    placement.angle += step[first + 2];
+                    if options.optimize_scale {
+                        placement.scale += step[first + 3];
+                    }
+                }
+            }
+            for (lens, first) in candidate.lenses.iter_mut().zip(&first_lens_parameter) {
+                if let Some(first) = *first {
+                    lens.a += step[first];
+                    lens.b += step[first + 1];
+                    lens.c += step[first + 2];
+                }
+            }
+
+            let candidate_cost = problem.sum_of_squares(&candidate);
+
+            if candidate_cost < cost {
+                improved = cost - candidate_cost > cost * 1e-12;
+                solution = candidate;
+                cost = candidate_cost;
+                damping = (damping * 0.1).max(1e-12);
+                break;
+            }
+
+            damping *= 10.0;
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    let orientations =
+    photos.iter().zip(&solution.placements).zip(&first_parameter).map(|((photo, placement), first)| {
+        match first {
+            Some(_) => placement.world_rectangle(photo),
+            None => photo.orientation.clone(),
+        }
+    }).collect();
+
+    let lenses = problem.lens_index.iter().map(|&index| solution.lenses[index]).collect();
+
+    Ok(Alignment {
+        orientations,
+        poses: photos.iter().map(|photo| photo.pose).collect(),
+        lenses,
+        rms_before,
+        rms_after: rms(cost),
+    })
+}
+
+/// the panorama sphere direction of a photo's pixel, with this pose and lens
+fn world_direction(photo: &AlignmentPhoto, pose: &CameraPose, lens: &LensParameters, (x, y): (f64, f64)) -> Direction {
+
+    Photo::world_direction_impl(pose, photo.image_width, photo.image_height, photo.image_orientation, lens, PixelCoords{x, y})
+}
+
+/// minimizes the sum of squares of `residuals(parameters)` by Levenberg-Marquardt,
+/// with central difference derivatives (`steps` are the difference step sizes of each parameter)
+fn levenberg_marquardt(mut parameters: Vec<f64>, steps: &[f64], residuals: impl Fn(&[f64]) -> Vec<f64>) -> Vec<f64> {
+
+    let sum_of_squares = |r: &[f64]| r.iter().map(|r| r * r).sum::<f64>();
+
+    let mut current = residuals(&parameters);
+    let mut cost = sum_of_squares(&current);
+    let mut damping = 1e-3;
+
+    for _ in 0..MAX_ITERATIONS {
+
+        //columns of the jacobian
+        let jacobian: Vec<Vec<f64>> =
+        steps.iter().enumerate().map(|(k, &step)| {
+            let mut plus = parameters.clone();
+            let mut minus = parameters.clone();
+            plus[k] += step;
+            minus[k] -= step;
+            residuals(&plus).iter().zip(residuals(&minus)).map(|(p, m)| (p - m) / (2.0 * step)).collect()
+        }).collect();
+
+        let jtj: Vec<Vec<f64>> = jacobian.iter().map(|i| {
+            jacobian.iter().map(|j| i.iter().zip(j).map(|(a, b)| a * b).sum()).collect()
+        }).collect();
+        let jtr: Vec<f64> = jacobian.iter().map(|i| i.iter().zip(&current).map(|(a, b)| a * b).sum()).collect();
+
+        let mut improved = false;
+
+        while damping < 1e10 {
+
+            let mut a = jtj.clone();
+            for (i, row) in a.iter_mut().enumerate() {
+                row[i] += damping * jtj[i][i] + 1e-9;
+            }
+
+            let step = match solve(a, jtr.iter().map(|v| -v).collect()) {
+                Some(step) => step,
+                None => break,
+            };
+
+            let candidate: Vec<f64> = parameters.iter().zip(&step).map(|(p, s)| p + s).collect();
+            let candidate_residuals = residuals(&candidate);
+            let candidate_cost = sum_of_squares(&candidate_residuals);
+
+            if candidate_cost < cost {
+                improved = cost - candidate_cost > cost * 1e-12;
+                parameters = candidate;
+                current = candidate_residuals;
+                cost = candidate_cost;
+                damping = (damping * 0.1).max(1e-12);
+                break;
+            }
+
+            damping *= 10.0;
+        }
+
+        if !improved {
+            break;
+        }
+    }
+
+    parameters
+}
+
+/// Finds the yaw, pitch, and roll (and optionally, the field of view and distortion of each lens) of every photo
+/// which minimizes the squared angles between the panorama sphere directions of normal control point pairs.
+///
+/// The anchor photo and locked photos are not moved.
+/// Control point pairs referencing missing photos are ignored.
+pub fn optimize_spherical_alignment(
+    photos: &[AlignmentPhoto],
+    pairs: &[ControlPointPair],
+    options: AlignmentOptions,
+) -> Result<Alignment, AlignmentError> {
+
+    const ANGLE_STEP: f64 = 1e-4;
+    const LENS_STEP: f64 = 1e-6;
+
+    if options.anchor >= photos.len() {
+        return Err(AlignmentError::AnchorOutOfRange(options.anchor));
+    }
+
+    let constraints = constraints(photos, pairs)?;
+    let (lenses, lens_index) = lens_groups(photos.iter().map(|photo| (photo.lens_id, photo.lens)));
+
+    //parameters: (yaw, pitch, roll) of each moving photo, then (fov?, a?, b?, c?) of each lens
+    let mut parameters = Vec::new();
+    let mut steps = Vec::new();
+
+    let moving: Vec<bool> = photos.iter().enumerate().map(|(index, photo)| index != options.anchor && !photo.locked).collect();
+
+    for (photo, _) in photos.iter().zip(&moving).filter(|(_, &moving)| moving) {
+        parameters.extend_from_slice(&[photo.pose.yaw, photo.pose.pitch, photo.pose.roll]);
+        steps.extend_from_slice(&[ANGLE_STEP; 3]);
+    }
+    for lens in &lenses {
+        if options.optimize_scale {
+            parameters.push(spherical::usable_fov(lens.fov));
+            steps.push(ANGLE_STEP);
+        }
+        if options.optimize_lens {
+            parameters.extend_from_slice(&[lens.a, lens.b, lens.c]);
+            steps.extend_from_slice(&[LENS_STEP; 3]);
+        }
+    }
+
+    //the poses and lenses in `parameters`
+    let solution = |parameters: &[f64]| -> (Vec<CameraPose>, Vec<LensParameters>) {
+
+        let mut values = parameters.iter().copied();
+
+        let poses = photos.iter().zip(&moving).map(|(photo, &moving)| {
+            if moving {
+                CameraPose{yaw: values.next().unwrap(), pitch: values.next().unwrap(), roll: values.next().unwrap()}
+            }
+            else {
+                photo.pose
+            }
+        }).collect();
+
+        let lenses = lenses.iter().map(|&lens| {
+            let mut lens = lens;
+            if options.optimize_scale {
+                lens.fov = values.next().unwrap();
+            }
+            if options.optimize_lens {
+                lens.a = values.next().unwrap();
+                lens.b = values.next().unwrap();
+                lens.c = values.next().unwrap();
+            }
+            lens
+        }).collect();
+
+        (poses, lenses)
+    };
+
+    let directions = |poses: &[CameraPose], lenses: &[LensParameters], c: &Constraint| {
+        (
+            world_direction(&photos[c.photo1], &poses[c.photo1], &lenses[lens_index[c.photo1]], c.pixel1),
+            world_direction(&photos[c.photo2], &poses[c.photo2], &lenses[lens_index[c.photo2]], c.pixel2),
+        )
+    };
+
+    let residuals = |parameters: &[f64]| -> Vec<f64> {
+        let (poses, lenses) = solution(parameters);
+        constraints.iter().flat_map(|c| {
+            let (d1, d2) = directions(&poses, &lenses, c);
+            vec![d1.x - d2.x, d1.y - d2.y, d1.z - d2.z]
+        }).collect()
+    };
+
+    let rms = |parameters: &[f64]| -> f64 {
+        let (poses, lenses) = solution(parameters);
+        let sum: f64 = constraints.iter().map(|c| {
+            let (d1, d2) = directions(&poses, &lenses, c);
+            d1.angle_to(&d2).to_degrees().powi(2)
+        }).sum();
+        (sum / constraints.len() as f64).sqrt()
+    };
+
+    let rms_before = rms(&parameters);
+    let parameters = levenberg_marquardt(parameters, &steps, residuals);
+    let rms_after = rms(&parameters);
+
+    let (poses, shared_lenses) = solution(&parameters);
+
+    Ok(Alignment {
+        orientations: photos.iter().map(|photo| photo.orientation.clone()).collect(),
+        poses,
+        lenses: lens_index.iter().map(|&index| shared_lenses[index]).collect(),
+        rms_before,
+        rms_after,
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_matches::*;
+    use assert_approx_eq::assert_approx_eq;
+    use crate::read_pto::ControlPoint;
+
+    fn photo(x: f64, y: f64, angle: f32, scale: f32) -> AlignmentPhoto {
+
+        let mut orientation = WorldRectangle::new(300.0 * scale, 200.0 * scale);
+        orientation.set_rotation(angle);
+        orientation.set_translation(WorldCoords{x, y});
+
+        AlignmentPhoto {
+            orientation,
+            image_width: 300,
+            image_height: 200,
+            image_orientation: ImageOrientation::Normal,
+            lens: LensParameters::default(),
+            lens_id: Some(0),
+            pose: CameraPose::default(),
+            locked: false,
+        }
+    }
+
+    /// the pixel coords of `world` in `photo`
+    fn pixel_coords(photo: &AlignmentPhoto, world: (f64, f64)) -> (f64, f64) {
+
+        let p = Placement::from_photo(photo);
+        let (sin, cos) = (-p.angle).sin_cos();
+        let (dx, dy) = ((world.0 - p.x) / p.scale, (world.1 - p.y) / p.scale);
+        let (qx, qy) = (cos * dx - sin * dy, sin * dx + cos * dy);
+
+        //upright -> stored, undistorted -> distorted texture coords
+        let (width, height) = (photo.image_width as f64, photo.image_height as f64);
+        let (stored_width, stored_height) = photo.image_orientation.stored_dimensions(photo.image_width, photo.image_height);
+        let stored = photo.image_orientation.stored_texture_coords((qx / width + 0.5, qy / height + 0.5));
+        let (u, v) = photo.lens.distort(stored, stored_width, stored_height);
+
+        (u * stored_width as f64, (1.0 - v) * stored_height as f64)
+    }
+
+    /// control point pairs for world points seen by both photos
+    fn pairs(photos: &[AlignmentPhoto], image1: usize, image2: usize, world_points: &[(f64, f64)]) -> Vec<ControlPointPair> {
+
+        world_points.iter().map(|&world| {
+            let (x1, y1) = pixel_coords(&photos[image1], world);
+            let (x2, y2) = pixel_coords(&photos[image2], world);
+            ControlPointPair {
+                cp1: ControlPoint::new(image1 as u64, x1, y1),
+                cp2: ControlPoint::new(image2 as u64, x2, y2),
+                point_type: ControlPointType::Normal,
+            }
+        }).collect()
+    }
+
+    fn assert_orientation_eq(a: &WorldRectangle, b: &WorldRectangle) {
+
+        assert_approx_eq!(a.translation().x, b.translation().x, 1e-2);
+        assert_approx_eq!(a.translation().y, b.translation().y, 1e-2);
+        assert_approx_eq!(a.rotation(), b.rotation(), 1e-3);
+        assert_approx_eq!(a.width(), b.width(), 1e-2);
+        assert_approx_eq!(a.height(), b.height(), 1e-2);
+    }
+
+    fn test_scene() -> (Vec<AlignmentPhoto>, Vec<ControlPointPair>) {
+
+        let truth = vec![
+            photo(0.0, 0.0, 0.0, 1.0),
+            photo(200.0, 10.0, 5.0, 1.0),
+            photo(100.0, 150.0, -8.0, 1.0),
+        ];
+
+        let mut control_point_pairs = pairs(&truth, 0, 1, &[(120.0, 50.0), (140.0, -60.0), (80.0, 0.0), (110.0, 80.0)]);
+        control_point_pairs.append(&mut pairs(&truth, 0, 2, &[(20.0, 90.0), (120.0, 70.0), (60.0, 95.0)]));
+        control_point_pairs.append(&mut pairs(&truth, 1, 2, &[(180.0, 100.0), (150.0, 90.0), (190.0, 80.0)]));
+
+        (truth, control_point_pairs)
+    }
+
+    #[test]
+    fn placement_test() {
+
+        let photo = photo(120.0, -40.0, 30.0, 2.0);
+        let placement = Placement::from_photo(&photo);
+
+        assert_approx_eq!(placement.scale, 2.0);
+
+        //matches Photo's pixel -> world mapping
+        for &(x, y) in &[(0.0, 0.0), (300.0, 200.0), (75.0, 160.0)] {
+
+            let expected = Photo::world_coords_impl(&photo.orientation, 300, 200, photo.image_orientation, &photo.lens, PixelCoords{x, y});
+            let actual = placement.world_coords(centered_pixel_coords(&photo, &photo.lens, x, y));
+
+            assert_approx_eq!(actual.0, expected.x, 1e-3);
+            assert_approx_eq!(actual.1, expected.y, 1e-3);
+
+            let (px, py) = pixel_coords(&photo, actual);
+            assert_approx_eq!(px, x, 1e-6);
+            assert_approx_eq!(py, y, 1e-6);
+        }
+
+        assert_orientation_eq(&placement.world_rectangle(&photo), &photo.orientation);
+
+        //with lens distortion
+        let photo = AlignmentPhoto{lens: LensParameters{c: -0.05, e: 12.0, ..Default::default()}, ..photo};
+        for &(x, y) in &[(0.0, 0.0), (300.0, 200.0), (75.0, 160.0)] {
+
+            let expected = Photo::world_coords_impl(&photo.orientation, 300, 200, photo.image_orientation, &photo.lens, PixelCoords{x, y});
+            let actual = placement.world_coords(centered_pixel_coords(&photo, &photo.lens, x, y));
+
+            assert_approx_eq!(actual.0, expected.x, 1e-3);
+            assert_approx_eq!(actual.1, expected.y, 1e-3);
+        }
+
+        //stored rotated (200 x 300 pixels), with lens distortion
+        let photo = AlignmentPhoto{image_orientation: ImageOrientation::Rotate90, ..photo};
+        for &(x, y) in &[(0.0, 0.0), (200.0, 300.0), (160.0, 75.0)] {
+
+            let actual = placement.world_coords(centered_pixel_coords(&photo, &photo.lens, x, y));
+
+            let (px, py) = pixel_coords(&photo, actual);
+            assert_approx_eq!(px, x, 1e-6);
+            assert_approx_eq!(py, y, 1e-6);
+        }
+    }
+
+    #[test]
+    fn solve_test() {
+
+        let x = solve(vec![vec![0.0, 2.0], vec![3.0, 1.0]], vec![4.0, 5.0]).unwrap();
+        assert_approx_eq!(x[0], 1.0);
+        assert_approx_eq!(x[1], 2.0);
+
+        assert_eq!(solve(vec![vec![1.0, 2.0], vec![2.0, 4.0]], vec![1.0, 1.0]), None);
+    }
+
+    #[test]
+    fn optimize_alignment_test() {
+
+        let (truth, control_point_pairs) = test_scene();
+
+        let mut start = truth.clone();
+        start[1] = photo(170.0, 40.0, 0.0, 1.0);
+        start[2] = photo(140.0, 120.0, 0.0, 1.0);
+
+        let options = AlignmentOptions{anchor: 0, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+
+        assert!(alignment.rms_before > 10.0);
+        assert!(alignment.rms_after < 1e-3);
+
+        //anchor is unchanged
+        assert_eq!(alignment.orientations[0], start[0].orientation);
+
+        for (orientation, photo) in alignment.orientations.iter().zip(&truth) {
+            assert_orientation_eq(orientation, &photo.orientation);
+        }
+    }
+
+    #[test]
+    fn optimize_alignment_scale_test() {
+
+        let (mut truth, _) = test_scene();
+        truth[1] = photo(200.0, 10.0, 5.0, 1.2);
+        truth[2] = photo(100.0, 150.0, -8.0, 0.9);
+
+        let mut control_point_pairs = pairs(&truth, 0, 1, &[(120.0, 50.0), (140.0, -60.0), (80.0, 0.0), (110.0, 80.0)]);
+        control_point_pairs.append(&mut pairs(&truth, 0, 2, &[(20.0, 90.0), (120.0, 70.0), (60.0, 95.0)]));
+
+        let mut start = truth.clone();
+        start[1] = photo(180.0, 0.0, 0.0, 1.0);
+        start[2] = photo(110.0, 140.0, 0.0, 1.0);
+
+        //without scale, the fit is approximate
+        let options = AlignmentOptions{anchor: 0, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+        assert!(alignment.rms_after > 1.0);
+
+        let options = AlignmentOptions{anchor: 0, optimize_scale: true, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+        assert!(alignment.rms_after < 1e-3);
+
+        for (orientation, photo) in alignment.orientations.iter().zip(&truth) {
+            assert_orientation_eq(orientation, &photo.orientation);
+        }
+    }
+
+    #[test]
+    fn lens_groups_test() {
+
+        let lens = |fov: f64| LensParameters{fov, ..Default::default()};
+
+        //grouped by lens id (not by parameters), with the first photo's parameters
+        let photo_lenses = vec![(Some(3), lens(50.0)), (None, lens(50.0)), (Some(1), lens(50.0)), (Some(3), lens(60.0)), (None, lens(50.0))];
+        let (lenses, lens_index) = lens_groups(photo_lenses.into_iter());
+
+        assert_eq!(lenses, vec![lens(50.0); 4]);
+        assert_eq!(lens_index, vec![0, 1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn optimize_alignment_lens_test() {
+
+        let lens = LensParameters{fov: 50.0, a: 0.0, b: 0.01, c: -0.04, d: 0.0, e: 0.0};
+        let with_lens = |photo: AlignmentPhoto| AlignmentPhoto{lens, ..photo};
+
+        let truth = vec![
+            with_lens(photo(0.0, 0.0, 0.0, 1.0)),
+            with_lens(photo(120.0, 10.0, 5.0, 1.0)),
+            with_lens(photo(60.0, 90.0, -4.0, 1.0)),
+        ];
+
+        let grid = |xs: &[f64], ys: &[f64]| -> Vec<(f64, f64)> {
+            xs.iter().flat_map(|&x| ys.iter().map(move |&y| (x, y))).collect()
+        };
+
+        let mut control_point_pairs = pairs(&truth, 0, 1, &grid(&[-20.0, 20.0, 60.0, 100.0, 140.0], &[-80.0, -40.0, 0.0, 40.0, 80.0]));
+        control_point_pairs.append(&mut pairs(&truth, 0, 2, &grid(&[-80.0, -20.0, 40.0, 100.0], &[0.0, 40.0, 90.0])));
+        control_point_pairs.append(&mut pairs(&truth, 1, 2, &grid(&[0.0, 60.0, 120.0, 180.0], &[-5.0, 40.0, 90.0])));
+
+        //unknown lens, approximate placement
+        let start: Vec<AlignmentPhoto> = vec![
+            photo(0.0, 0.0, 0.0, 1.0),
+            photo(115.0, 5.0, 3.0, 1.0),
+            photo(65.0, 85.0, 0.0, 1.0),
+        ];
+
+        //without lens calibration, the fit is approximate
+        let options = AlignmentOptions{anchor: 0, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+        assert!(alignment.rms_after > 0.1);
+        assert_eq!(alignment.lenses, vec![LensParameters::default(); 3]);
+
+        let options = AlignmentOptions{anchor: 0, optimize_scale: false, optimize_lens: true, optimize_vignetting: false};
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+        assert!(alignment.rms_after < 1e-3);
+
+        for (orientation, photo) in alignment.orientations.iter().zip(&truth) {
+            assert_orientation_eq(orientation, &photo.orientation);
+        }
+
+        //the shared lens is recovered; other lens parameters are unchanged
+        for result in &alignment.lenses {
+            assert_approx_eq!(result.a, lens.a, 1e-4);
+            assert_approx_eq!(result.b, lens.b, 1e-4);
+            assert_approx_eq!(result.c, lens.c, 1e-4);
+            assert_eq!(result.fov, 0.0);
+        }
+    }
+
+    #[test]
+    fn optimize_alignment_fixed_photos_test() {
+
+        let (truth, control_point_pairs) = test_scene();
+
+        //anchor photo 1, lock photo 2
+        let mut start = truth.clone();
+        start[0] = photo(-20.0, 20.0, 3.0, 1.0);
+        start[2].locked = true;
+
+        let options = AlignmentOptions{anchor: 1, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+
+        assert_eq!(alignment.orientations[1], start[1].orientation);
+        assert_eq!(alignment.orientations[2], start[2].orientation);
+        assert_orientation_eq(&alignment.orientations[0], &truth[0].orientation);
+
+        //a photo without control points is not moved
+        let mut start = truth.clone();
+        start.push(photo(1000.0, 1000.0, 10.0, 1.0));
+
+        let alignment = optimize_alignment(&start, &control_point_pairs, options).unwrap();
+        assert_orientation_eq(&alignment.orientations[3], &start[3].orientation);
+    }
+
+    fn spherical_photo(yaw: f64, pitch: f64, roll: f64, fov: f64) -> AlignmentPhoto {
+
+        AlignmentPhoto {
+            pose: CameraPose{yaw, pitch, roll},
+            lens: LensParameters{fov, ..Default::default()},
+            ..photo(0.0, 0.0, 0.0, 1.0)
+        }
+    }
+
+    /// control point pairs for directions (longitude, latitude in degrees) seen by both photos
+    fn spherical_pairs(photos: &[AlignmentPhoto], image1: usize, image2: usize, directions: &[(f64, f64)]) -> Vec<ControlPointPair> {
+
+        let pixel_coords = |photo: &AlignmentPhoto, direction: Direction| {
+            let camera_direction = photo.pose.camera_direction(direction);
+            let uv = spherical::camera_texture_coords(photo.image_width, photo.image_height, photo.lens.fov, camera_direction).unwrap();
+            let (width, height) = (photo.image_width as f64, photo.image_height as f64);
+            (uv.0 * width, (1.0 - uv.1) * height)
+        };
+
+        directions.iter().map(|&(longitude, latitude): &(f64, f64)| {
+            let (longitude, latitude) = (longitude.to_radians(), latitude.to_radians());
+            let direction = Direction{x: latitude.cos() * longitude.sin(), y: latitude.sin(), z: latitude.cos() * longitude.cos()};
+            let (x1, y1) = pixel_coords(&photos[image1], direction);
+            let (x2, y2) = pixel_coords(&photos[image2], direction);
+            ControlPointPair {
+                cp1: ControlPoint::new(image1 as u64, x1, y1),
+                cp2: ControlPoint::new(image2 as u64, x2, y2),
+                point_type: ControlPointType::Normal,
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn optimize_spherical_alignment_test() {
+
+        //two rows
+        let truth = vec![
+            spherical_photo(0.0, 0.0, 0.0, 60.0),
+            spherical_photo(40.0, 5.0, 2.0, 60.0),
+            spherical_photo(20.0, 25.0, -3.0, 60.0),
+        ];
+
+        let mut control_point_pairs = spherical_pairs(&truth, 0, 1, &[(15.0, -10.0), (20.0, 0.0), (25.0, 10.0), (18.0, 8.0)]);
+        control_point_pairs.append(&mut spherical_pairs(&truth, 0, 2, &[(5.0, 8.0), (15.0, 14.0), (25.0, 10.0)]));
+        control_point_pairs.append(&mut spherical_pairs(&truth, 1, 2, &[(30.0, 12.0), (40.0, 18.0), (35.0, 15.0)]));
+
+        let start = vec![
+            truth[0].clone(),
+            spherical_photo(35.0, 0.0, 0.0, 60.0),
+            spherical_photo(15.0, 30.0, 0.0, 60.0),
+        ];
+
+        let options = AlignmentOptions{anchor: 0, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_spherical_alignment(&start, &control_point_pairs, options).unwrap();
+
+        assert!(alignment.rms_before > 1.0);
+        assert!(alignment.rms_after < 1e-4);
+
+        //orientations are unchanged
+        assert_eq!(alignment.orientations[1], start[1].orientation);
+
+        for (pose, photo) in alignment.poses.iter().zip(&truth) {
+            assert_approx_eq!(pose.yaw, photo.pose.yaw, 1e-3);
+            assert_approx_eq!(pose.pitch, photo.pose.pitch, 1e-3);
+            assert_approx_eq!(pose.roll, photo.pose.roll, 1e-3);
+        }
+
+        //unknown field of view
+        let start: Vec<AlignmentPhoto> = start.iter().map(|photo| AlignmentPhoto{lens: LensParameters{fov: 55.0, ..photo.lens}, ..photo.clone()}).collect();
+
+        let options = AlignmentOptions{anchor: 0, optimize_scale: true, optimize_lens: false, optimize_vignetting: false};
+        let alignment = optimize_spherical_alignment(&start, &control_point_pairs, options).unwrap();
+
+        assert!(alignment.rms_after < 1e-4);
+        for lens in &alignment.lenses {
+            assert_approx_eq!(lens.fov, 60.0, 1e-3);
+        }
+    }
+
+    #[test]
+    fn optimize_alignment_error_test() {
+
+        let (truth, control_point_pairs) = test_scene();
+
+        let options = AlignmentOptions{anchor: 3, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        assert_matches!(optimize_alignment(&truth, &control_point_pairs, options), Err(AlignmentError::AnchorOutOfRange(3)));
+
+        let options = AlignmentOptions{anchor: 0, optimize_scale: false, optimize_lens: false, optimize_vignetting: false};
+        assert_matches!(optimize_alignment(&truth, &[], options), Err(AlignmentError::NoControlPoints));
+
+        //line-type and out of range pairs are ignored
+        let ignored = vec![
+            ControlPointPair {
+                cp1: ControlPoint::new(0, 1.0, 2.0),
+                cp2: ControlPoint::new(1, 3.0, 4.0),
+                point_type: ControlPointType::VerticalLine,
+            },
+            ControlPointPair {
+                cp1: ControlPoint::new(0, 1.0, 2.0),
+                cp2: ControlPoint::new(5, 3.0, 4.0),
+                point_type: ControlPointType::Normal,
+            },
+        ];
+        assert_matches!(optimize_alignment(&truth, &ignored, options), Err(AlignmentError::NoControlPoints));
+        assert_matches!(optimize_spherical_alignment(&truth, &ignored, options), Err(AlignmentError::NoControlPoints));
+    }
+}
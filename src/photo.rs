@@ -3,36 +3,84 @@ use std::fmt::{Display,Formatter};
 
 use three_d::{Mat4,Texture,InnerSpace};
 
+use log::warn;
 use serde::{Serialize, Deserialize, Serializer};
-use serde::ser::SerializeStruct;
 
 pub use crate::entities::LoadedImageMesh;
+use crate::lens::{LensParameters, LensCorrection};
+use crate::exif_metadata::ExifMetadata;
+use crate::image_orientation::ImageOrientation;
+use crate::spherical;
+use crate::spherical::{CameraModel, CameraPose, Direction, SphereProjection};
 use crate::viewport_geometry::{WorldCoords, PixelCoords};
 use crate::world_rectangle::{WorldRectangle,LocalCoords};
+use crate::exposure::ColorCorrection;
 
 
 pub struct Photo {
 
     pub loaded_image_mesh: Rc<LoadedImageMesh>,
 
+    ///the image file this Photo was loaded from
+    pub source_path: String,
+
     ///this Photo's world space orientation:
     ///* scales 1 (unwarped) pixel to 1 WorldCoords unit
     ///* translates center from world origin in WorldCoords units
     ///* rotates around photo center
     orientation: WorldRectangle,
 
+    ///upright image dimensions in pixels
+    pub image_width: u32,
+    pub image_height: u32,
+
+    ///how the stored image is displayed upright (from EXIF)
+    pub image_orientation: ImageOrientation,
+
+    pub lens: LensParameters,
+    ///photos with the same lens id were taken with the same lens (and share its parameters)
+    pub lens_id: usize,
+
+    ///this Photo's camera orientation in the spherical camera model
+    pub pose: CameraPose,
+
+    ///exposure and white balance correction
+    pub color_correction: ColorCorrection,
+
+    ///camera metadata from the source image (empty if it has none)
+    pub metadata: ExifMetadata,
+
+    ///if false, this Photo is not rendered
+    pub visible: bool,
+    ///if true, mouse tools do not move or rotate this Photo
+    pub locked: bool,
+}
+
+/// The serialized form of a Photo: everything except its loaded image
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct PhotoFields {
+    pub source_path: String,
+    pub image_width: u32,
+    pub image_height: u32,
+    pub orientation: WorldRectangle,
+    pub lens: LensParameters,
+    /// None in older projects: the loaded photo's lens id is kept
+    #[serde(default)]
+    pub lens_id: Option<usize>,
+    #[serde(default)]
+    pub pose: CameraPose,
+    #[serde(default)]
+    pub color_correction: ColorCorrection,
+    pub visible: bool,
+    pub locked: bool,
 }
 
-//todo: make this complete?
 impl Serialize for Photo {
 
     fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error> where
         S: Serializer {
 
-        let mut state = serializer.serialize_struct("Photo", 2)?;
-        state.serialize_field("translate", &self.orientation.translate)?;
-        state.serialize_field("rotate", &self.orientation.rotate)?;
-        state.end()
+        self.fields().serialize(serializer)
     }
 }
 
@@ -46,27 +94,84 @@ impl Display for Photo {
 
 impl Photo {
 
-    pub fn from_loaded_image_mesh(m: Rc<LoadedImageMesh>) -> Self {
+    pub fn from_loaded_image_mesh(m: Rc<LoadedImageMesh>, source_path: &str, metadata: ExifMetadata) -> Self {
 
-        let orientation = WorldRectangle::new(m.texture_2d.width() as f32,m.texture_2d.height() as f32);
+        let image_orientation = metadata.image_orientation();
+        let (image_width, image_height) = image_orientation.upright_dimensions(m.texture_2d.width() as u32, m.texture_2d.height() as u32);
+        let orientation = WorldRectangle::new(image_width as f32, image_height as f32);
 
         Self {
             loaded_image_mesh: m,
+            source_path: source_path.to_string(),
             orientation,
+            image_width,
+            image_height,
+            image_orientation,
+            lens: LensParameters::default(),
+            lens_id: 0,
+            pose: CameraPose::default(),
+            color_correction: ColorCorrection::default(),
+            metadata,
+            visible: true,
+            locked: false,
+        }
+    }
+
+    pub fn fields(&self) -> PhotoFields {
+
+        PhotoFields {
+            source_path: self.source_path.clone(),
+            image_width: self.image_width,
+            image_height: self.image_height,
+            orientation: self.orientation.clone(),
+            lens: self.lens,
+            lens_id: Some(self.lens_id),
+            pose: self.pose,
+            color_correction: self.color_correction,
+            visible: self.visible,
+            locked: self.locked,
         }
     }
 
+    /// sets everything but the source path and image dimensions, which belong to the loaded image
+    pub fn set_fields(&mut self, fields: &PhotoFields) {
+
+        if (fields.image_width, fields.image_height) != (self.image_width, self.image_height) {
+            warn!("saved image dimensions {}x{} do not match {} ({}x{})",
+                fields.image_width, fields.image_height, self.source_path, self.image_width, self.image_height);
+        }
+
+        self.orientation = fields.orientation.clone();
+        self.lens = fields.lens;
+        if let Some(lens_id) = fields.lens_id {
+            self.lens_id = lens_id;
+        }
+        self.pose = fields.pose;
+        self.color_correction = fields.color_correction;
+        self.visible = fields.visible;
+        self.locked = fields.locked;
+    }
+
+    /// reads a serialized Photo, or the older translate/rotate-only format
     pub fn set_from_json_serde_string(&mut self, s: &str) -> Result<(), Box<dyn std::error::Error>> {
 
         #[derive(Deserialize)]
-        struct SavedFields {
-            translate: Mat4,
-            rotate: Mat4,
+        #[serde(untagged)]
+        enum SavedFields {
+            Photo(Box<PhotoFields>),
+            Legacy {
+                translate: Mat4,
+                rotate: Mat4,
+            },
         }
 
-        let saved_fields: SavedFields = serde_json::from_str(s)?;
-        self.orientation.translate = saved_fields.translate;
-        self.orientation.rotate = saved_fields.rotate;
+        match serde_json::from_str(s)? {
+            SavedFields::Photo(fields) => self.set_fields(&fields),
+            SavedFields::Legacy {translate, rotate} => {
+                self.orientation.translate = translate;
+                self.orientation.rotate = rotate;
+            },
+        }
 
         Ok(())
     }
@@ -75,6 +180,11 @@ impl Photo {
         &self.orientation
     }
 
+    pub fn set_orientation(&mut self, orientation: WorldRectangle) {
+
+        self.orientation = orientation
+    }
+
     pub fn set_translation(&mut self, center: WorldCoords) {
 
         self.orientation.set_translation(center)
@@ -90,54 +200,375 @@ impl Photo {
         self.orientation.rotate_around_point(angle, point)
     }
 
-    /// gets the WorldCoords location of pixel coords in this photo
-    pub fn world_coords(&self, pixel_coords: PixelCoords) -> WorldCoords {
+    /// gets the WorldCoords location of (stored image) pixel coords in this photo,
+    /// after orientation and lens distortion correction: None if the projection can't show it
+    ///
+    /// (`lens_correction` applies to the planar camera model: spherical projections always correct the lens)
+    pub fn world_coords(&self, camera_model: &CameraModel, lens_correction: LensCorrection, pixel_coords: PixelCoords) -> Option<WorldCoords> {
+
+        match camera_model {
+            CameraModel::Planar =>
+                Some(Self::world_coords_impl(&self.orientation, self.image_width, self.image_height, self.image_orientation, &lens_correction.apply(&self.lens), pixel_coords)),
+            CameraModel::Spherical(projection) =>
+                projection.world_coords(self.world_direction(pixel_coords)),
+        }
+    }
+
+    /// gets the panorama sphere direction of (stored image) pixel coords in this photo,
+    /// after orientation and lens distortion correction
+    pub fn world_direction(&self, pixel_coords: PixelCoords) -> Direction {
+
+        Self::world_direction_impl(&self.pose, self.image_width, self.image_height, self.image_orientation, &self.lens, pixel_coords)
+    }
+
+    /// `image_width` and `image_height` are the upright image's dimensions in pixels: `lens.fov` spans `image_width`
+    pub fn world_direction_impl(
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        pixel_coords: PixelCoords,
+    ) -> Direction {
+
+        let uv = Self::corrected_texture_coords(image_width, image_height, image_orientation, lens, pixel_coords);
+
+        pose.world_direction(spherical::camera_direction(image_width, image_height, lens.fov, uv))
+    }
+
+    /// `image_width` and `image_height` are the upright image's dimensions in pixels: the WorldRectangle may be scaled differently
+    pub fn world_coords_impl(
+        world_rectangle: &WorldRectangle,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        pixel_coords: PixelCoords,
+    ) -> WorldCoords {
+
+        let (u, v) = Self::corrected_texture_coords(image_width, image_height, image_orientation, lens, pixel_coords);
+
+        world_rectangle.world_coords(LocalCoords{x: u - 0.5, y: v - 0.5})
+    }
+
+    /// Maps stored image pixel coords to texture coords (range [0,1], y up) in the upright image,
+    /// where texture_dewarp2.frag renders them
+    ///
+    /// (points which can't be undistorted are only reoriented)
+    pub fn corrected_texture_coords(
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        pixel_coords: PixelCoords,
+    ) -> (f64, f64) {
+
+        let (stored_width, stored_height) = image_orientation.stored_dimensions(image_width, image_height);
+
+        if stored_width == 0 || stored_height == 0 {
+            //if the image is somehow empty, center on origin
+            return (0.5, 0.5);
+        }
+
+        //flip y-coords to positive = up
+        let distorted = (pixel_coords.x / stored_width as f64, 1.0 - pixel_coords.y / stored_height as f64);
+
+        //the shader's lens model works in stored image texture coords
+        let undistorted = lens.undistort(distorted, stored_width, stored_height).unwrap_or(distorted);
+
+        image_orientation.upright_texture_coords(undistorted)
+    }
+
+    /// true IFF the point is on this photo's visible (distortion corrected) image
+    ///
+    /// (`lens_correction` as for `world_coords`)
+    pub fn contains(&self, camera_model: &CameraModel, lens_correction: LensCorrection, point: WorldCoords) -> bool {
 
-        Self::world_coords_impl(&self.orientation, pixel_coords)
+        match camera_model {
+            CameraModel::Planar =>
+                Self::contains_impl(&self.orientation, self.image_width, self.image_height, self.image_orientation, &lens_correction.apply(&self.lens), point),
+            CameraModel::Spherical(projection) =>
+                Self::contains_spherical_impl(projection, &self.pose, self.image_width, self.image_height, self.image_orientation, &self.lens, point),
+        }
     }
 
-    fn world_coords_impl(world_rectangle: &WorldRectangle, pixel_coords: PixelCoords) -> WorldCoords {
+    fn contains_impl(
+        world_rectangle: &WorldRectangle,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        point: WorldCoords,
+    ) -> bool {
+
+        Self::source_texture_coords_impl(world_rectangle, image_width, image_height, image_orientation, lens, point).is_some()
+    }
 
-        let local_coords = Self::local_coords(world_rectangle, pixel_coords);
+    fn contains_spherical_impl(
+        projection: &SphereProjection,
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        point: WorldCoords,
+    ) -> bool {
+
+        Self::spherical_source_texture_coords_impl(projection, pose, image_width, image_height, image_orientation, lens, point).is_some()
+    }
 
-        world_rectangle.world_coords(local_coords)
+    /// true IFF this photo's (distortion corrected) image includes the panorama sphere direction `direction`
+    fn contains_direction_impl(
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        direction: Direction,
+    ) -> bool {
+
+        Self::direction_source_texture_coords_impl(pose, image_width, image_height, image_orientation, lens, direction).is_some()
     }
 
-    fn local_coords(world_rectangle: &WorldRectangle, pixel_coords: PixelCoords) -> LocalCoords {
+    /// gets the source image texture coords (stored image, range [0,1], y up) rendered at a WorldCoords point:
+    /// None if the point is not on this photo's visible (distortion corrected) image
+    pub fn source_texture_coords_impl(
+        world_rectangle: &WorldRectangle,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        point: WorldCoords,
+    ) -> Option<(f64, f64)> {
+
+        //the rendered mesh
+        if !world_rectangle.contains(point) {
+            return None;
+        }
+
+        let local_coords = world_rectangle.local_coords(point)?;
+
+        Self::distorted_texture_coords(image_width, image_height, image_orientation, lens, (local_coords.x + 0.5, local_coords.y + 0.5))
+    }
+
+    /// as `source_texture_coords_impl`, in the spherical camera model (see texture_spherical.frag)
+    pub fn spherical_source_texture_coords_impl(
+        projection: &SphereProjection,
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        point: WorldCoords,
+    ) -> Option<(f64, f64)> {
+
+        let direction = projection.direction(point)?;
+
+        Self::direction_source_texture_coords_impl(pose, image_width, image_height, image_orientation, lens, direction)
+    }
+
+    fn direction_source_texture_coords_impl(
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        direction: Direction,
+    ) -> Option<(f64, f64)> {
+
+        let camera_direction = pose.camera_direction(direction);
+        let uv = spherical::camera_texture_coords(image_width, image_height, lens.fov, camera_direction)?;
+
+        Self::distorted_texture_coords(image_width, image_height, image_orientation, lens, uv)
+    }
+
+    /// Maps (upright, undistorted) image texture coords to the source image texture coords they sample:
+    /// None if outside of the source image (see texture_dewarp2.frag)
+    fn distorted_texture_coords(
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        upright: (f64, f64),
+    ) -> Option<(f64, f64)> {
+
+        let (stored_width, stored_height) = image_orientation.stored_dimensions(image_width, image_height);
+        let stored = image_orientation.stored_texture_coords(upright);
+        let (x, y) = lens.distort(stored, stored_width, stored_height);
+
+        let in_range = |x: f64| (0.0..=1.0).contains(&x);
+        if in_range(x) && in_range(y) {Some((x, y))} else {None}
+    }
+
+    /// the outline of this photo's visible (distortion corrected) image
+    ///
+    /// `points_per_side` is the number of points sampled along each image edge
+    /// (points a spherical projection can't show are skipped,
+    /// `lens_correction` as for `world_coords`)
+    pub fn border(&self, camera_model: &CameraModel, lens_correction: LensCorrection, points_per_side: u32) -> Vec<WorldCoords> {
+
+        match camera_model {
+            CameraModel::Planar =>
+                Self::border_texture_coords(self.image_width, self.image_height, self.image_orientation, &lens_correction.apply(&self.lens), points_per_side)
+                    .into_iter()
+                    .map(|(u, v)| self.orientation.world_coords(LocalCoords{x: u - 0.5, y: v - 0.5}))
+                    .collect(),
+            CameraModel::Spherical(projection) =>
+                Self::spherical_border_impl(projection, &self.pose, self.image_width, self.image_height, self.image_orientation, &self.lens, points_per_side),
+        }
+    }
+
+    fn spherical_border_impl(
+        projection: &SphereProjection,
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        points_per_side: u32,
+    ) -> Vec<WorldCoords> {
+
+        Self::border_texture_coords(image_width, image_height, image_orientation, lens, points_per_side)
+            .into_iter()
+            .filter_map(|uv| {
+                let camera_direction = spherical::camera_direction(image_width, image_height, lens.fov, uv);
+                projection.world_coords(pose.world_direction(camera_direction))
+            })
+            .collect()
+    }
+
+    /// upright (undistorted) texture coords of points along the stored image's edges
+    fn border_texture_coords(
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+        points_per_side: u32,
+    ) -> Vec<(f64, f64)> {
+
+        let (width, height) = image_orientation.stored_dimensions(image_width, image_height);
+        let (width, height) = (width as f64, height as f64);
+        let steps = points_per_side.max(1);
+
+        let edges = [
+            ((0.0, 0.0), (0.0, height)),
+            ((0.0, height), (width, height)),
+            ((width, height), (width, 0.0)),
+            ((width, 0.0), (0.0, 0.0)),
+        ];
+
+        edges.iter().flat_map(|&((x1, y1), (x2, y2))| {
+            (0..steps).map(move |step| {
+                let t = step as f64 / steps as f64;
+                PixelCoords{x: x1 + (x2 - x1) * t, y: y1 + (y2 - y1) * t}
+            })
+        }).map(|pixel_coords| {
+            let (u, v) = Self::corrected_texture_coords(image_width, image_height, image_orientation, lens, pixel_coords);
+
+            //the rendered mesh clips anything beyond its edges
+            (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+        }).collect()
+    }
+
+    /// WorldCoords (min, max) corners of a rectangle containing this photo in a spherical projection
+    pub fn spherical_bounds(&self, projection: &SphereProjection) -> (WorldCoords, WorldCoords) {
+
+        Self::spherical_bounds_impl(projection, &self.pose, self.image_width, self.image_height, self.image_orientation, &self.lens)
+    }
+
+    pub fn spherical_bounds_impl(
+        projection: &SphereProjection,
+        pose: &CameraPose,
+        image_width: u32,
+        image_height: u32,
+        image_orientation: ImageOrientation,
+        lens: &LensParameters,
+    ) -> (WorldCoords, WorldCoords) {
+
+        const POINTS_PER_SIDE: u32 = 16;
+
+        let border = Self::spherical_border_impl(projection, pose, image_width, image_height, image_orientation, lens, POINTS_PER_SIDE);
+        let (sphere_min, sphere_max) = projection.bounds();
+
+        //photos that are partly unprojectable, cross the wraparound edge, or contain a singular direction
+        //(a pole of a cylindrical projection, or the back of an azimuthal projection) can reach the projection's edges
+        let unbounded = border.len() < 4 * POINTS_PER_SIDE as usize
+            || border.iter().zip(border.iter().cycle().skip(1)).any(|(&a, &b)| !projection.is_continuous(a, b))
+            || projection.singular_directions().into_iter().any(|direction| {
+                Self::contains_direction_impl(pose, image_width, image_height, image_orientation, lens, direction)
+            });
+
+        if unbounded {
+            return (sphere_min, sphere_max);
+        }
+
+        let mut min = WorldCoords{x: f64::MAX, y: f64::MAX};
+        let mut max = WorldCoords{x: f64::MIN, y: f64::MIN};
+
+        for point in &border {
+            min.x = min.x.min(point.x);
+            min.y = min.y.min(point.y);
+            max.x = max.x.max(point.x);
+            max.y = max.y.max(point.y);
+        }
+
+        //edges are curved between border points
+        let margin_x = (max.x - min.x) * 0.05;
+        let margin_y = (max.y - min.y) * 0.05;
+        min = WorldCoords{x: min.x - margin_x, y: min.y - margin_y};
+        max = WorldCoords{x: max.x + margin_x, y: max.y + margin_y};
+
+        //the rendered projection's edges
+        min = WorldCoords{x: min.x.max(sphere_min.x), y: min.y.max(sphere_min.y)};
+        max = WorldCoords{x: max.x.min(sphere_max.x), y: max.y.min(sphere_max.y)};
+
+        (min, max)
+    }
+
+    /// (width, height) of the stored image in pixels, before orientation correction
+    pub fn stored_dimensions(&self) -> (u32, u32) {
+
+        self.image_orientation.stored_dimensions(self.image_width, self.image_height)
+    }
+
+    /// gets approximate Hugin (yaw, pitch, roll) angles in degrees for this photo's orientation
+    ///
+    /// `fov` is this photo's horizontal field of view in degrees
+    pub fn pto_angles(&self, fov: f64) -> Option<(f64, f64, f64)> {
+
+        Self::pto_angles_impl(&self.orientation, fov)
+    }
+
+    /// an approximate camera pose for this photo's (planar) orientation
+    pub fn pose_from_orientation(&self) -> Option<CameraPose> {
+
+        self.pto_angles(spherical::usable_fov(self.lens.fov)).map(|(yaw, pitch, roll)| CameraPose{yaw, pitch, roll})
+    }
+
+    fn pto_angles_impl(world_rectangle: &WorldRectangle, fov: f64) -> Option<(f64, f64, f64)> {
+
+        if fov <= 0.0 || fov >= 180.0 {
+            return None;
+        }
 
         let width = world_rectangle.scale.x.magnitude() as f64;
-        let height = world_rectangle.scale.y.magnitude() as f64;
 
+        //focal length in (unwarped) pixels = WorldCoords units
+        let focal_length = (width / 2.0) / (fov / 2.0).to_radians().tan();
 
-        let local_x =
-        if width == 0.0 {
-            //if width is somehow 0, center on origin
-            0.0
+        if focal_length == 0.0 {
+            return None;
         }
-        else {
-            //scale to width = 1
-            let x = pixel_coords.x / width;
-            //center on origin
-            let x = x - 0.5;
-            x
-        };
 
-        let local_y =
-        if height == 0.0 {
-            //if height is somehow 0, center on origin
-            0.0
-        }
-        else {
-            //scale to width = 1
-            let y = pixel_coords.y / height;
-            //center on origin
-            let y = y - 0.5;
-            //flip y-coords to positive = up
-            let y = -y;
-            y
-        };
+        //treat the world plane as tangent to the panorama sphere at the world origin
+        let center = world_rectangle.translation();
+        let yaw = (center.x / focal_length).atan().to_degrees();
+        let pitch = (center.y / focal_length).atan().to_degrees();
 
-        LocalCoords{x: local_x, y: local_y}
+        //WorldRectangle rotation is counterclockwise, Hugin roll is clockwise
+        let roll = -world_rectangle.rotation() as f64;
+
+        Some((yaw, pitch, roll))
     }
 }
 
@@ -145,6 +576,7 @@ impl Photo {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::spherical::ProjectionKind;
 
 
     #[test]
@@ -159,7 +591,7 @@ mod test {
             //top left corner
             {
                 let pixel_coords = PixelCoords { x: 0.0, y: 0.0 };
-                let world_coords = Photo::world_coords_impl(&orientation, pixel_coords);
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), pixel_coords);
 
                 assert_eq!(world_coords, WorldCoords { x: -100.0, y: 50.0 });
             }
@@ -167,7 +599,7 @@ mod test {
             //bottom right corner
             {
                 let pixel_coords = PixelCoords { x: 200.0, y: 100.0 };
-                let world_coords = Photo::world_coords_impl(&orientation, pixel_coords);
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), pixel_coords);
 
                 assert_eq!(world_coords, WorldCoords { x: 100.0, y: -50.0 });
             }
@@ -184,7 +616,7 @@ mod test {
             //top left corner
             {
                 let pixel_coords = PixelCoords { x: 0.0, y: 0.0 };
-                let world_coords = Photo::world_coords_impl(&orientation, pixel_coords);
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), pixel_coords);
 
                 assert_eq!(world_coords, WorldCoords { x: x - 50.0, y: y - 100.0 });
             }
@@ -192,11 +624,215 @@ mod test {
             //bottom right corner
             {
                 let pixel_coords = PixelCoords { x: 200.0, y: 100.0 };
-                let world_coords = Photo::world_coords_impl(&orientation, pixel_coords);
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), pixel_coords);
 
                 assert_eq!(world_coords, WorldCoords { x: x + 50.0, y: y + 100.0 });
             }
         }
 
+        //scaled to twice the image size
+        {
+            let orientation = WorldRectangle::new(400.0, 200.0);
+
+            //top left corner
+            {
+                let pixel_coords = PixelCoords { x: 0.0, y: 0.0 };
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), pixel_coords);
+
+                assert_eq!(world_coords, WorldCoords { x: -200.0, y: 100.0 });
+            }
+
+            //center
+            {
+                let pixel_coords = PixelCoords { x: 100.0, y: 50.0 };
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), pixel_coords);
+
+                assert_eq!(world_coords, WorldCoords { x: 0.0, y: 0.0 });
+            }
+        }
+
+        //stored rotated: displayed rotated 90 degrees clockwise
+        {
+            let orientation = WorldRectangle::new(200.0, 100.0);
+
+            //stored (100 x 200 pixels) top left corner: upright top right corner
+            {
+                let pixel_coords = PixelCoords { x: 0.0, y: 0.0 };
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Rotate90, &LensParameters::default(), pixel_coords);
+
+                assert_eq!(world_coords, WorldCoords { x: 100.0, y: 50.0 });
+            }
+
+            //stored bottom right corner: upright bottom left corner
+            {
+                let pixel_coords = PixelCoords { x: 100.0, y: 200.0 };
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Rotate90, &LensParameters::default(), pixel_coords);
+
+                assert_eq!(world_coords, WorldCoords { x: -100.0, y: -50.0 });
+            }
+
+            //stored top left corner, mirrored: upright top left corner
+            {
+                let pixel_coords = PixelCoords { x: 0.0, y: 0.0 };
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Transpose, &LensParameters::default(), pixel_coords);
+
+                assert_eq!(world_coords, WorldCoords { x: -100.0, y: 50.0 });
+            }
+        }
+
+        //lens distortion: the shader samples the source pixel at the returned location
+        {
+            use assert_approx_eq::assert_approx_eq;
+
+            let orientation = WorldRectangle::new(200.0, 100.0);
+            let lens = LensParameters{c: -0.05, d: 10.0, ..Default::default()};
+
+            for &(x, y) in &[(20.0, 10.0), (150.0, 80.0), (110.0, 50.0)] {
+
+                let world_coords = Photo::world_coords_impl(&orientation, 200, 100, ImageOrientation::Normal, &lens, PixelCoords{x, y});
+                let uv = (world_coords.x / 200.0 + 0.5, world_coords.y / 100.0 + 0.5);
+                let (u, v) = lens.distort(uv, 200, 100);
+
+                assert_approx_eq!(u * 200.0, x, 1e-3);
+                assert_approx_eq!((1.0 - v) * 100.0, y, 1e-3);
+            }
+        }
+
+    }
+
+    #[test]
+    fn contains_test() {
+
+        let mut orientation = WorldRectangle::new(200.0, 100.0);
+        orientation.set_translation(WorldCoords { x: 1000.0, y: 0.0 });
+
+        let no_lens = LensParameters::default();
+        //pincushion: corners sample beyond the source image
+        let pincushion = LensParameters{c: 0.1, ..Default::default()};
+
+        let contains = |lens: &LensParameters, x: f64, y: f64| {
+            Photo::contains_impl(&orientation, 200, 100, ImageOrientation::Normal, lens, WorldCoords { x: 1000.0 + x, y })
+        };
+
+        assert!(contains(&no_lens, 0.0, 0.0));
+        assert!(contains(&no_lens, 99.0, 49.0));
+        assert!(!contains(&no_lens, 101.0, 0.0));
+
+        assert!(contains(&pincushion, 0.0, 0.0));
+        assert!(contains(&pincushion, 50.0, 20.0));
+        assert!(!contains(&pincushion, 99.0, 49.0));
+        assert!(!contains(&pincushion, 0.0, 51.0));
+
+        assert!(!Photo::contains_impl(&WorldRectangle::new(0.0, 0.0), 200, 100, ImageOrientation::Normal, &no_lens, WorldCoords { x: 0.0, y: 0.0 }));
+    }
+
+    #[test]
+    fn spherical_test() {
+
+        use assert_approx_eq::assert_approx_eq;
+
+        let projection = SphereProjection{scale: 1000.0, kind: ProjectionKind::Equirectangular};
+        let pose = CameraPose{yaw: 30.0, pitch: 10.0, roll: 5.0};
+        let lens = LensParameters{fov: 60.0, c: -0.05, ..Default::default()};
+
+        let world_coords = |x: f64, y: f64| {
+            projection.world_coords(Photo::world_direction_impl(&pose, 200, 100, ImageOrientation::Normal, &lens, PixelCoords{x, y})).unwrap()
+        };
+
+        //the image center is at the pose's yaw and pitch
+        let center = world_coords(100.0, 50.0);
+        assert_approx_eq!(center.x, 30_f64.to_radians() * 1000.0);
+        assert_approx_eq!(center.y, 10_f64.to_radians() * 1000.0);
+
+        //rolled clockwise: the right edge is lower than the left edge
+        assert!(world_coords(200.0, 50.0).y < world_coords(0.0, 50.0).y);
+
+        let contains = |point: WorldCoords| {
+            Photo::contains_spherical_impl(&projection, &pose, 200, 100, ImageOrientation::Normal, &lens, point)
+        };
+
+        for &(x, y) in &[(1.0, 1.0), (150.0, 80.0), (199.0, 50.0), (100.0, 50.0)] {
+            assert!(contains(world_coords(x, y)));
+        }
+
+        //left of the image
+        assert!(!contains(WorldCoords{x: -300.0, y: 170.0}));
+        //beyond the projected sphere
+        assert!(!contains(WorldCoords{x: 0.0, y: 2000.0}));
+        //behind the camera
+        assert!(!contains(WorldCoords{x: -2600.0, y: 0.0}));
+
+        //every projection contains the image center
+        for &kind in &ProjectionKind::ALL {
+            let projection = SphereProjection{scale: 1000.0, kind};
+            let center = Photo::world_direction_impl(&pose, 200, 100, ImageOrientation::Normal, &lens, PixelCoords{x: 100.0, y: 50.0});
+            let point = projection.world_coords(center).unwrap();
+            assert!(Photo::contains_spherical_impl(&projection, &pose, 200, 100, ImageOrientation::Normal, &lens, point));
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pto_angles_test() {
+
+        use assert_approx_eq::assert_approx_eq;
+
+        //90 degree fov: focal length = half of width
+        {
+            let mut orientation = WorldRectangle::new(200.0, 100.0);
+            orientation.set_rotation(10.0);
+            orientation.set_translation(WorldCoords { x: 100.0, y: -100.0 });
+
+            let (yaw, pitch, roll) = Photo::pto_angles_impl(&orientation, 90.0).unwrap();
+
+            assert_approx_eq!(yaw, 45.0, 1e-4);
+            assert_approx_eq!(pitch, -45.0, 1e-4);
+            assert_approx_eq!(roll, -10.0, 1e-4);
+        }
+
+        //at origin
+        {
+            let orientation = WorldRectangle::new(200.0, 100.0);
+
+            let (yaw, pitch, roll) = Photo::pto_angles_impl(&orientation, 50.0).unwrap();
+
+            assert_approx_eq!(yaw, 0.0);
+            assert_approx_eq!(pitch, 0.0);
+            assert_approx_eq!(roll, 0.0);
+        }
+
+        //invalid field of view
+        {
+            let orientation = WorldRectangle::new(200.0, 100.0);
+
+            assert_eq!(Photo::pto_angles_impl(&orientation, 0.0), None);
+            assert_eq!(Photo::pto_angles_impl(&orientation, 180.0), None);
+        }
+    }
+
+    #[test]
+    fn photo_fields_serde_test() -> Result<(), Box<dyn std::error::Error>> {
+
+        let mut orientation = WorldRectangle::new(920.0, 614.0);
+        orientation.set_translation(WorldCoords { x: 50.0, y: -20.0 });
+
+        let fields = PhotoFields {
+            source_path: "DSC_9108.JPG".to_string(),
+            image_width: 920,
+            image_height: 614,
+            orientation,
+            lens: LensParameters{fov: 50.0, a: 0.0019, b: -0.0028, c: 0.0095, d: 0.0, e: 0.0},
+            lens_id: Some(1),
+            pose: CameraPose{yaw: 10.0, pitch: -5.0, roll: 1.0},
+            color_correction: ColorCorrection{exposure: 0.5, red: 1.1, blue: 0.9},
+            visible: false,
+            locked: true,
+        };
+
+        let value = serde_json::to_value(&fields)?;
+        assert_eq!(value["orientation"], serde_json::json!({"width": 920.0, "height": 614.0, "center": {"x": 50.0, "y": -20.0}, "angle": 0.0}));
+
+        assert_eq!(fields, serde_json::from_value(value)?);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use three_d::{Mat4, InnerSpace};
+
+use crate::read_pto::ControlPointPair;
+use crate::photo::PhotoFields;
+use crate::viewport_geometry::WorldCoords;
+use crate::world_rectangle::WorldRectangle;
+use crate::lens::{LensParameters, Vignetting};
+use crate::spherical::{CameraModel, CameraPose};
+use crate::seams::SeamMap;
+use crate::exposure::ColorCorrection;
+
+/// the project file format version written by this build
+pub const PROJECT_VERSION: u64 = 2;
+
+/// A saved panorama project: photo placement, lens parameters, control points, and view state
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Project {
+    pub version: u64,
+    /// (`source_path`s are relative to the project file)
+    pub photos: Vec<PhotoFields>,
+    /// each lens' vignetting, by lens id (see PhotoFields::lens_id)
+    #[serde(default)]
+    pub lens_vignetting: Vec<Vignetting>,
+    pub control_point_pairs: Vec<ControlPointPair>,
+    pub view: ProjectView,
+    /// (projects saved before the spherical model are planar)
+    #[serde(default)]
+    pub camera_model: CameraModel,
+    /// found or painted seams (see seams.rs)
+    #[serde(default)]
+    pub seam_map: Option<SeamMap>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct ProjectView {
+    pub camera_position: WorldCoords,
+    pub zoom_value: u32,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Json(serde_json::Error),
+    MissingVersion,
+    /// this file was written by a newer build
+    UnsupportedVersion(u64),
+}
+
+impl Display for ProjectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProjectError::Json(e) => write!(f, "invalid project JSON: {}", e),
+            ProjectError::MissingVersion => write!(f, "project has no version number"),
+            ProjectError::UnsupportedVersion(v) => write!(f, "unsupported project version: {} (newest supported: {})", v, PROJECT_VERSION),
+        }
+    }
+}
+
+impl Error for ProjectError {}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(e: serde_json::Error) -> Self {
+        ProjectError::Json(e)
+    }
+}
+
+impl Project {
+
+    pub fn to_json_string(&self) -> Result<String, ProjectError> {
+
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// reads a project file of any supported version
+    pub fn from_json_str(s: &str) -> Result<Self, ProjectError> {
+
+        let value: Value = serde_json::from_str(s)?;
+        let value = migrate(value)?;
+
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// upgrades a project document to the current version, one version at a time
+fn migrate(mut value: Value) -> Result<Value, ProjectError> {
+
+    loop {
+        let version = value.get("version").and_then(Value::as_u64).ok_or(ProjectError::MissingVersion)?;
+
+        value = match version {
+            1 => migrate_v1(value)?,
+            PROJECT_VERSION => return Ok(value),
+            v => return Err(ProjectError::UnsupportedVersion(v)),
+        };
+    }
+}
+
+/// version 1 -> 2:
+/// * photos are complete Photo fields (`image_path` is renamed to `source_path`)
+/// * orientations are stored as size, center, and angle instead of matrices
+fn migrate_v1(mut value: Value) -> Result<Value, ProjectError> {
+
+    #[derive(Deserialize)]
+    struct V1Orientation {
+        scale: Mat4,
+        translate: Mat4,
+        rotate: Mat4,
+    }
+
+    #[derive(Deserialize)]
+    struct V1Photo {
+        image_path: String,
+        orientation: V1Orientation,
+        lens: LensParameters,
+    }
+
+    let photos: Vec<V1Photo> = serde_json::from_value(value["photos"].take())?;
+
+    let photos: Vec<PhotoFields> =
+    photos.into_iter().map(|photo| {
+
+        let orientation = WorldRectangle {
+            scale: photo.orientation.scale,
+            translate: photo.orientation.translate,
+            rotate: photo.orientation.rotate,
+        };
+
+        PhotoFields {
+            source_path: photo.image_path,
+            image_width: photo.orientation.scale.x.magnitude().round() as u32,
+            image_height: photo.orientation.scale.y.magnitude().round() as u32,
+            orientation,
+            lens: photo.lens,
+            lens_id: None,
+            pose: CameraPose::default(),
+            color_correction: ColorCorrection::default(),
+            visible: true,
+            locked: false,
+        }
+    }).collect();
+
+    value["photos"] = serde_json::to_value(photos)?;
+    value["version"] = Value::from(2);
+
+    Ok(value)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_matches::*;
+    use cgmath::assert_abs_diff_eq;
+    use crate::read_pto::{ControlPoint, ControlPointType};
+    use crate::spherical::{ProjectionKind, SphereProjection};
+
+    fn test_project() -> Project {
+
+        let mut orientation = WorldRectangle::new(920.0, 614.0);
+        orientation.set_rotation(10.0);
+        orientation.set_translation(WorldCoords{x: 50.0, y: 65.0});
+
+        Project {
+            version: PROJECT_VERSION,
+            photos: vec![
+                PhotoFields {
+                    source_path: "photos/DSC_9108.JPG".to_string(),
+                    image_width: 920,
+                    image_height: 614,
+                    orientation,
+                    lens: LensParameters{fov: 50.0, a: 0.0019, b: -0.0028, c: 0.0095, d: 1.0, e: -2.0},
+                    lens_id: Some(0),
+                    pose: CameraPose{yaw: 12.0, pitch: -3.5, roll: 0.5},
+                    color_correction: ColorCorrection{exposure: -0.3, red: 1.05, blue: 0.95},
+                    visible: true,
+                    locked: false,
+                },
+            ],
+            lens_vignetting: vec![Vignetting{a: 1.0, b: -0.3, c: 0.1, d: -0.05}],
+            control_point_pairs: vec![
+                ControlPointPair {
+                    cp1: ControlPoint::new(0, 568.5, 117.6),
+                    cp2: ControlPoint::new(1, 54.4, 98.7),
+                    point_type: ControlPointType::Normal,
+                },
+            ],
+            view: ProjectView {
+                camera_position: WorldCoords{x: -10.0, y: 20.0},
+                zoom_value: 12,
+            },
+            camera_model: CameraModel::Spherical(SphereProjection{scale: 1000.0, kind: ProjectionKind::Mercator}),
+            seam_map: Some(SeamMap {
+                top_left: WorldCoords{x: -5.0, y: 4.0},
+                cell_size: 0.5,
+                width: 2,
+                height: 1,
+                labels: vec![Some(0), None],
+            }),
+        }
+    }
+
+    #[test]
+    fn serde_test() -> Result<(), Box<dyn Error>> {
+
+        let project = test_project();
+
+        let s = project.to_json_string()?;
+        let mut loaded = Project::from_json_str(&s)?;
+
+        //orientations are rebuilt from center and angle
+        assert_abs_diff_eq!(loaded.photos[0].orientation, project.photos[0].orientation, epsilon = 1e-4);
+        loaded.photos[0].orientation = project.photos[0].orientation.clone();
+
+        assert_eq!(project, loaded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_test() -> Result<(), Box<dyn Error>> {
+
+        let mut value = serde_json::to_value(test_project())?;
+
+        value["version"] = Value::from(PROJECT_VERSION + 1);
+        assert_matches!(migrate(value.clone()), Err(ProjectError::UnsupportedVersion(_)));
+
+        value.as_object_mut().unwrap().remove("version");
+        assert_matches!(migrate(value), Err(ProjectError::MissingVersion));
+
+        assert_matches!(Project::from_json_str("not json"), Err(ProjectError::Json(_)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn migrate_v1_test() -> Result<(), Box<dyn Error>> {
+
+        let project = test_project();
+        let orientation = &project.photos[0].orientation;
+
+        let v1 = serde_json::json!({
+            "version": 1,
+            "photos": [{
+                "image_path": "photos/DSC_9108.JPG",
+                "orientation": {
+                    "scale": orientation.scale,
+                    "translate": orientation.translate,
+                    "rotate": orientation.rotate,
+                },
+                "lens": project.photos[0].lens,
+            }],
+            "control_point_pairs": project.control_point_pairs,
+            "view": project.view,
+        });
+
+        let migrated = Project::from_json_str(&v1.to_string())?;
+
+        assert_eq!(migrated.version, PROJECT_VERSION);
+        assert_eq!(migrated.photos[0].source_path, project.photos[0].source_path);
+        assert_eq!(migrated.photos[0].image_width, 920);
+        assert_eq!(migrated.photos[0].image_height, 614);
+        assert_abs_diff_eq!(migrated.photos[0].orientation, project.photos[0].orientation, epsilon = 1e-4);
+        assert_eq!(migrated.photos[0].lens, project.photos[0].lens);
+        assert_eq!(migrated.control_point_pairs, project.control_point_pairs);
+        assert_eq!(migrated.view, project.view);
+        assert_eq!(migrated.camera_model, CameraModel::Planar);
+        assert_eq!(migrated.seam_map, None);
+
+        Ok(())
+    }
+}
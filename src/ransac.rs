@@ -0,0 +1,327 @@
+use crate::control_points::{ControlPointStore, ImagePair};
+use crate::read_pto::{ControlPointPair, ControlPointType};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct RansacOptions {
+    /// maximum inlier distance, in pixels of an image pair's second image
+    pub threshold: f64,
+    /// maximum number of sampled models per image pair
+    pub iterations: usize,
+}
+
+impl Default for RansacOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 10.0,
+            iterations: 500,
+        }
+    }
+}
+
+/// a 2D point as a complex number
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+
+    fn sub(self, other: Self) -> Self {
+        Self{re: self.re - other.re, im: self.im - other.im}
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self{re: self.re + other.re, im: self.im + other.im}
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self{re: self.re * other.re - self.im * other.im, im: self.re * other.im + self.im * other.re}
+    }
+
+    fn conj(self) -> Self {
+        Self{re: self.re, im: -self.im}
+    }
+
+    fn scale(self, factor: f64) -> Self {
+        Self{re: self.re * factor, im: self.im * factor}
+    }
+
+    fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+}
+
+/// A similarity transform (rotation, uniform scale, translation) between two images' pixel coords:
+/// `to = a * from + b`
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Similarity {
+    a: Complex,
+    b: Complex,
+}
+
+impl Similarity {
+
+    /// the exact transform mapping 2 points; None if the `from` points coincide
+    fn from_two(from: [Complex; 2], to: [Complex; 2]) -> Option<Self> {
+
+        let d_from = from[1].sub(from[0]);
+        let norm = d_from.norm_sqr();
+
+        if norm == 0.0 {
+            return None;
+        }
+
+        //a = d_to / d_from
+        let a = to[1].sub(to[0]).mul(d_from.conj()).scale(1.0 / norm);
+        let b = to[0].sub(a.mul(from[0]));
+
+        Some(Self{a, b})
+    }
+
+    /// the least-squares transform for matched points
+    fn fit(points: &[(Complex, Complex)]) -> Option<Self> {
+
+        if points.is_empty() {
+            return None;
+        }
+
+        let n = points.len() as f64;
+        let mean = |select: fn(&(Complex, Complex)) -> Complex| {
+            points.iter().map(select).fold(Complex{re: 0.0, im: 0.0}, Complex::add).scale(1.0 / n)
+        };
+        let from_mean = mean(|p| p.0);
+        let to_mean = mean(|p| p.1);
+
+        let mut numerator = Complex{re: 0.0, im: 0.0};
+        let mut denominator = 0.0;
+
+        for (from, to) in points {
+            let from = from.sub(from_mean);
+            let to = to.sub(to_mean);
+            numerator = numerator.add(to.mul(from.conj()));
+            denominator += from.norm_sqr();
+        }
+
+        if denominator == 0.0 {
+            return None;
+        }
+
+        let a = numerator.scale(1.0 / denominator);
+        let b = to_mean.sub(a.mul(from_mean));
+
+        Some(Self{a, b})
+    }
+
+    fn error(&self, (from, to): (Complex, Complex)) -> f64 {
+
+        self.a.mul(from).add(self.b).sub(to).norm_sqr().sqrt()
+    }
+}
+
+/// deterministic pseudorandom numbers (xorshift64)
+struct Random(u64);
+
+impl Random {
+
+    fn below(&mut self, n: usize) -> usize {
+
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Classifies matched points as inliers (true) or outliers (false) of the best similarity transform.
+///
+/// With fewer than 3 points, every point is an inlier.
+fn classify(points: &[(Complex, Complex)], options: RansacOptions) -> Vec<bool> {
+
+    let n = points.len();
+
+    if n < 3 {
+        return vec![true; n];
+    }
+
+    //every sample if there are few enough, otherwise random samples
+    let samples: Vec<(usize, usize)> =
+    if n * (n - 1) / 2 <= options.iterations {
+        (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).collect()
+    }
+    else {
+        let mut random = Random(0x9E37_79B9_7F4A_7C15);
+        (0..options.iterations).filter_map(|_| {
+            let (i, j) = (random.below(n), random.below(n));
+            if i == j {None} else {Some((i, j))}
+        }).collect()
+    };
+
+    let inliers = |model: &Similarity| -> Vec<bool> {
+        points.iter().map(|&p| model.error(p) <= options.threshold).collect()
+    };
+    let count = |flags: &Vec<bool>| flags.iter().filter(|&&f| f).count();
+
+    let mut best: Option<Vec<bool>> = None;
+
+    for (i, j) in samples {
+
+        let model = match Similarity::from_two([points[i].0, points[j].0], [points[i].1, points[j].1]) {
+            Some(model) => model,
+            None => continue,
+        };
+
+        let flags = inliers(&model);
+
+        if best.as_ref().map_or(true, |best| count(&flags) > count(best)) {
+            best = Some(flags);
+        }
+    }
+
+    let best = match best {
+        Some(best) => best,
+        None => return vec![true; n],
+    };
+
+    //refine with all inliers
+    let inlier_points: Vec<(Complex, Complex)> =
+        points.iter().zip(&best).filter(|(_, &inlier)| inlier).map(|(&p, _)| p).collect();
+
+    match Similarity::fit(&inlier_points) {
+        Some(model) => {
+            let refined = inliers(&model);
+            if count(&refined) >= count(&best) {refined} else {best}
+        },
+        None => best,
+    }
+}
+
+/// Finds outlier control point pairs: for each image pair, normal pairs which don't fit
+/// the similarity transform (between the two images' pixel coords) that most pairs agree on.
+pub fn find_outliers(control_points: &ControlPointStore, options: RansacOptions) -> Vec<ControlPointPair> {
+
+    let mut outliers = Vec::new();
+
+    for (image_pair, pairs) in control_points.iter() {
+
+        if image_pair.first == image_pair.second {
+            continue;
+        }
+
+        let normal_pairs: Vec<&ControlPointPair> =
+            pairs.iter().filter(|pair| pair.point_type == ControlPointType::Normal).collect();
+
+        let points: Vec<(Complex, Complex)> =
+            normal_pairs.iter().map(|pair| oriented_points(pair, *image_pair)).collect();
+
+        let flags = classify(&points, options);
+
+        outliers.extend(
+            normal_pairs.into_iter().zip(flags).filter(|(_, inlier)| !inlier).map(|(pair, _)| pair.clone())
+        );
+    }
+
+    outliers
+}
+
+/// (point in the image pair's first image, point in its second image)
+fn oriented_points(pair: &ControlPointPair, image_pair: ImagePair) -> (Complex, Complex) {
+
+    let p1 = Complex{re: pair.cp1.x_coord, im: pair.cp1.y_coord};
+    let p2 = Complex{re: pair.cp2.x_coord, im: pair.cp2.y_coord};
+
+    if pair.cp1.image_id == image_pair.first {(p1, p2)} else {(p2, p1)}
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+    use crate::read_pto::ControlPoint;
+
+    /// image 1 is image 0 rotated 90 degrees (counterclockwise in pixel coords), shifted by (500, 10)
+    fn transform((x, y): (f64, f64)) -> (f64, f64) {
+        (500.0 - y, 10.0 + x)
+    }
+
+    fn pair(image_a: u64, a: (f64, f64), image_b: u64, b: (f64, f64)) -> ControlPointPair {
+
+        ControlPointPair {
+            cp1: ControlPoint::new(image_a, a.0, a.1),
+            cp2: ControlPoint::new(image_b, b.0, b.1),
+            point_type: ControlPointType::Normal,
+        }
+    }
+
+    fn inlier_pairs() -> Vec<ControlPointPair> {
+
+        [(10.0, 20.0), (300.0, 40.0), (150.0, 400.0), (80.0, 250.0), (250.0, 300.0), (20.0, 500.0)].iter().enumerate().map(|(index, &p)| {
+            //1 pixel of noise
+            let (x, y) = transform(p);
+            let noise = if index % 2 == 0 {1.0} else {-1.0};
+            //alternate pair direction
+            if index % 3 == 0 {
+                pair(1, (x + noise, y), 0, p)
+            } else {
+                pair(0, p, 1, (x, y + noise))
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn similarity_test() {
+
+        let c = |x, y| Complex{re: x, im: y};
+        let points: Vec<(Complex, Complex)> = [(0.0, 0.0), (100.0, 0.0), (30.0, 70.0)].iter().map(|&(x, y)| {
+            let (tx, ty) = transform((x, y));
+            (c(x, y), c(tx, ty))
+        }).collect();
+
+        let model = Similarity::from_two([points[0].0, points[1].0], [points[0].1, points[1].1]).unwrap();
+        assert_approx_eq!(model.error(points[2]), 0.0);
+
+        let model = Similarity::fit(&points).unwrap();
+        assert_approx_eq!(model.a.re, 0.0);
+        assert_approx_eq!(model.a.im, 1.0);
+        assert_approx_eq!(model.b.re, 500.0);
+        assert_approx_eq!(model.b.im, 10.0);
+
+        assert_eq!(Similarity::from_two([c(1.0, 1.0), c(1.0, 1.0)], [c(0.0, 0.0), c(5.0, 0.0)]), None);
+        assert_eq!(Similarity::fit(&[]), None);
+    }
+
+    #[test]
+    fn find_outliers_test() {
+
+        let bad1 = pair(0, (100.0, 100.0), 1, (100.0, 100.0));
+        let bad2 = pair(1, (50.0, 50.0), 0, (200.0, 20.0));
+
+        let mut pairs = inlier_pairs();
+        pairs.push(bad1.clone());
+        pairs.push(bad2.clone());
+
+        //too few pairs to judge
+        pairs.push(pair(0, (0.0, 0.0), 2, (10.0, 10.0)));
+        pairs.push(pair(0, (50.0, 0.0), 2, (900.0, 10.0)));
+
+        //line-type pairs are not classified
+        pairs.push(ControlPointPair {
+            point_type: ControlPointType::VerticalLine,
+            ..pair(1, (0.0, 0.0), 1, (0.0, 500.0))
+        });
+
+        let store = ControlPointStore::from_pairs(pairs);
+
+        assert_eq!(find_outliers(&store, RansacOptions::default()), vec![bad1.clone(), bad2.clone()]);
+
+        //randomly sampled models find the same outliers
+        let options = RansacOptions{threshold: 10.0, iterations: 20};
+        assert_eq!(find_outliers(&store, options), vec![bad1, bad2]);
+
+        //a threshold below the noise makes everything an outlier
+        let options = RansacOptions{threshold: 0.1, iterations: 500};
+        assert!(find_outliers(&store, options).len() >= 6);
+    }
+}
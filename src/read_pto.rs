@@ -1,15 +1,445 @@
 use std::str::FromStr;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
 use nom::{
     IResult,
-    bytes::complete::{tag, take_while1, take_until},
-    combinator::map_res,
+    branch::alt,
+    bytes::complete::{tag, take_while, take_while1, take_until},
+    combinator::{map, map_res, all_consuming, rest, eof},
+    error::{context, ErrorKind, ParseError, ContextError, FromExternalError},
     number::complete::double,
-    character::complete::multispace0,
-    multi::fold_many1,
+    character::complete::{char, alpha1, space0, space1, multispace0},
+    multi::{fold_many1, many0, many1},
+    sequence::{delimited, preceded, pair},
 };
 
+use serde::{Serialize, Deserialize};
+
+
+/// A PTO file line which couldn't be parsed
+#[derive(Debug, PartialEq, Clone)]
+pub struct PtoParseError {
+    /// line number, starting at 1
+    pub line_number: usize,
+    /// character column of the error location, starting at 1
+    pub column: usize,
+    /// the entire line
+    pub line: String,
+    /// what was expected at the error location
+    pub expected: String,
+}
+
+impl Display for PtoParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "line {}, column {}: expected {}", self.line_number, self.column, self.expected)?;
+        writeln!(f, "{}", self.line)?;
+        write!(f, "{}^", " ".repeat(self.column.saturating_sub(1)))
+    }
+}
+
+impl Error for PtoParseError {}
+
+/// nom error type for PTO line parsers: records what was expected at the error location
+#[derive(Debug, PartialEq, Clone)]
+struct LineError<'a> {
+    /// the remaining input at the error location
+    input: &'a str,
+    expected: String,
+}
+
+impl<'a> ParseError<&'a str> for LineError<'a> {
+
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+
+        let expected = match kind {
+            ErrorKind::Float => "a number".to_string(),
+            ErrorKind::Alpha => "a name".to_string(),
+            ErrorKind::Eof => "end of line".to_string(),
+            kind => format!("valid input ({:?})", kind),
+        };
+
+        LineError{input, expected}
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        //keep the innermost error
+        other
+    }
+}
+
+impl<'a> ContextError<&'a str> for LineError<'a> {
+
+    fn add_context(_input: &'a str, ctx: &'static str, other: Self) -> Self {
+        //keep the innermost error location, with a better description
+        LineError{input: other.input, expected: ctx.to_string()}
+    }
+}
+
+/// used by map_res: the external error message describes what was expected
+impl<'a, E: Display> FromExternalError<&'a str, E> for LineError<'a> {
+
+    fn from_external_error(input: &'a str, _kind: ErrorKind, e: E) -> Self {
+        LineError{input, expected: e.to_string()}
+    }
+}
+
+type LineResult<'a, O> = IResult<&'a str, O, LineError<'a>>;
+
+
+/// A Hugin PTO project file, stored as its lines (in file order)
+#[derive(Debug, PartialEq, Clone)]
+pub struct PtoFile {
+    pub lines: Vec<PtoLine>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PtoLine {
+    /// 'p' line: output panorama settings
+    Panorama(Panorama),
+    /// 'm' line: global options
+    Mode(Mode),
+    /// 'i' line: one input image
+    Image(Image),
+    /// 'v' line: variables to optimize
+    OptimizeVariables(Vec<OptimizeVariable>),
+    /// 'k' line: one image mask
+    Mask(Mask),
+    /// 'c' line: one control point pair
+    ControlPointPair(ControlPointPair),
+    /// '#' line (contains the text after '#')
+    Comment(String),
+    Blank,
+    /// a line of an unrecognized type (contains the entire line)
+    Unknown(String),
+}
+
+/// A PTO line parameter: a name followed immediately by a value (e.g. "w920", "v=0", "n\"a.jpg\"")
+#[derive(Debug, PartialEq, Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub value: ParameterValue,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParameterValue {
+    Number(f64),
+    /// "=N": this value is shared with image N
+    Link(u64),
+    /// a quoted string (without quotes)
+    Text(String),
+    /// comma-separated numbers (e.g. the crop rectangle "S0,920,0,614")
+    List(Vec<f64>),
+}
+
+/// An image variable which may be shared with another image
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ImageVariable {
+    Value(f64),
+    /// use the value of the image with this index
+    Link(usize),
+}
 
 #[derive(Debug, PartialEq, Clone)]
+pub struct Panorama {
+    /// f: output projection
+    pub projection: u64,
+    /// w: width in pixels
+    pub width: u64,
+    /// h: height in pixels
+    pub height: u64,
+    /// v: horizontal field of view in degrees
+    pub fov: f64,
+    pub other_parameters: Vec<Parameter>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mode {
+    pub parameters: Vec<Parameter>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Image {
+    /// w: width in pixels
+    pub width: u64,
+    /// h: height in pixels
+    pub height: u64,
+    /// f: lens projection
+    pub projection: u64,
+    /// v: horizontal field of view in degrees
+    pub fov: ImageVariable,
+    /// y: yaw in degrees
+    pub yaw: ImageVariable,
+    /// p: pitch in degrees
+    pub pitch: ImageVariable,
+    /// r: roll in degrees
+    pub roll: ImageVariable,
+    /// a, b, c: ptlens-style radial distortion coefficients
+    pub a: ImageVariable,
+    pub b: ImageVariable,
+    pub c: ImageVariable,
+    /// d, e: lens center shift in pixels
+    pub d: ImageVariable,
+    pub e: ImageVariable,
+    /// n: image filename
+    pub file_name: String,
+    pub other_parameters: Vec<Parameter>,
+}
+
+/// A variable name + the index of the image it applies to (e.g. "y1": yaw of image 1)
+#[derive(Debug, PartialEq, Clone)]
+pub struct OptimizeVariable {
+    pub name: String,
+    pub image_id: u64,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Mask {
+    /// i: index of the masked image
+    pub image_id: u64,
+    /// t: mask type
+    pub mask_type: u64,
+    /// p: polygon vertices in image pixel coords
+    pub polygon: Vec<(f64, f64)>,
+}
+
+impl PtoFile {
+
+    pub fn images(&self) -> Vec<&Image> {
+
+        self.lines.iter().filter_map(|line| {
+            match line {
+                PtoLine::Image(image) => Some(image),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    pub fn images_mut(&mut self) -> Vec<&mut Image> {
+
+        self.lines.iter_mut().filter_map(|line| {
+            match line {
+                PtoLine::Image(image) => Some(image),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// the (first) 'p' line
+    pub fn panorama(&self) -> Option<&Panorama> {
+
+        self.lines.iter().find_map(|line| {
+            match line {
+                PtoLine::Panorama(panorama) => Some(panorama),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn panorama_mut(&mut self) -> Option<&mut Panorama> {
+
+        self.lines.iter_mut().find_map(|line| {
+            match line {
+                PtoLine::Panorama(panorama) => Some(panorama),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn control_point_pairs(&self) -> Vec<ControlPointPair> {
+
+        self.lines.iter().filter_map(|line| {
+            match line {
+                PtoLine::ControlPointPair(pair) => Some(pair.clone()),
+                _ => None,
+            }
+        }).collect()
+    }
+
+    /// replaces all control point pair lines with `pairs`:
+    /// lines of pairs which are still present are kept in place (in their original order),
+    /// and new pairs are added after the last kept line (or at the location of the first removed one)
+    pub fn set_control_point_pairs(&mut self, pairs: Vec<ControlPointPair>) {
+
+        let is_pair = |line: &PtoLine| matches!(line, PtoLine::ControlPointPair(_));
+
+        let first_index = self.lines.iter().position(is_pair).unwrap_or(self.lines.len());
+
+        let mut new_pairs = pairs;
+
+        self.lines.retain(|line| match line {
+            PtoLine::ControlPointPair(pair) => match new_pairs.iter().position(|new_pair| new_pair == pair) {
+                Some(index) => {
+                    new_pairs.remove(index);
+                    true
+                },
+                None => false,
+            },
+            _ => true,
+        });
+
+        let index = self.lines.iter().rposition(is_pair).map_or(first_index, |last_index| last_index + 1);
+        self.lines.splice(index..index, new_pairs.into_iter().map(PtoLine::ControlPointPair));
+    }
+
+    /// gets the value of an image variable, following links to other images
+    pub fn resolve_image_variable(&self, image_index: usize, variable: impl Fn(&Image) -> ImageVariable) -> Option<f64> {
+
+        let index = self.image_variable_source(image_index, &variable)?;
+
+        match variable(self.images()[index]) {
+            ImageVariable::Value(value) => Some(value),
+            ImageVariable::Link(_) => None,
+        }
+    }
+
+    /// gets the index of the image whose value an image variable resolves to, following links to other images
+    pub fn image_variable_source(&self, image_index: usize, variable: impl Fn(&Image) -> ImageVariable) -> Option<usize> {
+
+        let images = self.images();
+        let mut index = image_index;
+
+        //a valid link chain can't be longer than the image count
+        for _ in 0..images.len() {
+
+            match variable(images.get(index)?) {
+                ImageVariable::Value(_) => return Some(index),
+                ImageVariable::Link(link) => index = link,
+            }
+        }
+        None
+    }
+}
+
+/// removes the first parameter with this name and returns its value
+fn take_parameter(parameters: &mut Vec<Parameter>, name: &str) -> Option<ParameterValue> {
+
+    let index = parameters.iter().position(|p| p.name == name)?;
+    Some(parameters.remove(index).value)
+}
+
+fn take_u64(parameters: &mut Vec<Parameter>, name: &str) -> Result<u64, String> {
+
+    match take_parameter(parameters, name) {
+        Some(ParameterValue::Number(n)) if n >= 0.0 && n.fract() == 0.0 => Ok(n as u64),
+        Some(_) => Err(format!("an unsigned integer value for parameter '{}'", name)),
+        None => Err(format!("parameter '{}'", name)),
+    }
+}
+
+fn take_f64(parameters: &mut Vec<Parameter>, name: &str) -> Result<f64, String> {
+
+    match take_parameter(parameters, name) {
+        Some(ParameterValue::Number(n)) => Ok(n),
+        Some(_) => Err(format!("a number value for parameter '{}'", name)),
+        None => Err(format!("parameter '{}'", name)),
+    }
+}
+
+fn take_text(parameters: &mut Vec<Parameter>, name: &str) -> Result<String, String> {
+
+    match take_parameter(parameters, name) {
+        Some(ParameterValue::Text(s)) => Ok(s),
+        Some(_) => Err(format!("a quoted text value for parameter '{}'", name)),
+        None => Err(format!("parameter '{}'", name)),
+    }
+}
+
+/// missing image variables default to 0 (as in Hugin)
+fn take_image_variable(parameters: &mut Vec<Parameter>, name: &str) -> Result<ImageVariable, String> {
+
+    match take_parameter(parameters, name) {
+        Some(ParameterValue::Number(n)) => Ok(ImageVariable::Value(n)),
+        Some(ParameterValue::Link(id)) => Ok(ImageVariable::Link(id as usize)),
+        Some(_) => Err(format!("a number or link value for parameter '{}'", name)),
+        None => Ok(ImageVariable::Value(0.0)),
+    }
+}
+
+impl Panorama {
+
+    fn try_from_parameters(mut parameters: Vec<Parameter>) -> Result<Self, String> {
+
+        Ok(Self {
+            projection: take_u64(&mut parameters, "f")?,
+            width: take_u64(&mut parameters, "w")?,
+            height: take_u64(&mut parameters, "h")?,
+            fov: take_f64(&mut parameters, "v")?,
+            other_parameters: parameters,
+        })
+    }
+}
+
+impl Image {
+
+    fn try_from_parameters(mut parameters: Vec<Parameter>) -> Result<Self, String> {
+
+        Ok(Self {
+            width: take_u64(&mut parameters, "w")?,
+            height: take_u64(&mut parameters, "h")?,
+            projection: take_u64(&mut parameters, "f")?,
+            fov: take_image_variable(&mut parameters, "v")?,
+            yaw: take_image_variable(&mut parameters, "y")?,
+            pitch: take_image_variable(&mut parameters, "p")?,
+            roll: take_image_variable(&mut parameters, "r")?,
+            a: take_image_variable(&mut parameters, "a")?,
+            b: take_image_variable(&mut parameters, "b")?,
+            c: take_image_variable(&mut parameters, "c")?,
+            d: take_image_variable(&mut parameters, "d")?,
+            e: take_image_variable(&mut parameters, "e")?,
+            file_name: take_text(&mut parameters, "n")?,
+            other_parameters: parameters,
+        })
+    }
+
+    /// an image variable kept in `other_parameters` (e.g. "Va"), or `default` if it's missing or not a number or link
+    pub fn other_variable(&self, name: &str, default: f64) -> ImageVariable {
+
+        match self.other_parameters.iter().find(|p| p.name == name).map(|p| &p.value) {
+            Some(&ParameterValue::Number(n)) => ImageVariable::Value(n),
+            Some(&ParameterValue::Link(id)) => ImageVariable::Link(id as usize),
+            _ => ImageVariable::Value(default),
+        }
+    }
+
+    /// sets an image variable kept in `other_parameters`, in place (or appended, if it's missing)
+    pub fn set_other_variable(&mut self, name: &str, variable: ImageVariable) {
+
+        let value = match variable {
+            ImageVariable::Value(n) => ParameterValue::Number(n),
+            ImageVariable::Link(id) => ParameterValue::Link(id as u64),
+        };
+
+        match self.other_parameters.iter_mut().find(|p| p.name == name) {
+            Some(parameter) => parameter.value = value,
+            None => self.other_parameters.push(Parameter{name: name.to_string(), value}),
+        }
+    }
+}
+
+impl Mask {
+
+    fn try_from_parameters(mut parameters: Vec<Parameter>) -> Result<Self, String> {
+
+        let image_id = take_u64(&mut parameters, "i")?;
+        let mask_type = take_u64(&mut parameters, "t")?;
+        let polygon_text = take_text(&mut parameters, "p")?;
+
+        let coords = polygon_text.split_whitespace().map(|s| {
+            f64::from_str(s).map_err(|_| "numbers in mask polygon parameter 'p'".to_string())
+        }).collect::<Result<Vec<f64>, String>>()?;
+
+        if coords.len() % 2 != 0 {
+            return Err("an even number of coordinates in mask polygon parameter 'p'".to_string());
+        }
+
+        let polygon = coords.chunks(2).map(|xy| (xy[0], xy[1])).collect();
+
+        Ok(Self {image_id, mask_type, polygon})
+    }
+}
+
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ControlPoint {
     pub image_id: u64,
     pub x_coord: f64,
@@ -24,49 +454,194 @@ impl ControlPoint {
     }
 }
 
-fn uinteger64(input: &str) -> IResult<&str, u64> {
+/// The type of a control point pair ('t' parameter)
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum ControlPointType {
+    /// t0: the points are the same location
+    Normal,
+    /// t1: the points are on a vertical line (only horizontal distance is optimized)
+    VerticalLine,
+    /// t2: the points are on a horizontal line (only vertical distance is optimized)
+    HorizontalLine,
+    /// t3+: the points are on a straight line; pairs with the same number are on the same line
+    StraightLine(u64),
+}
+
+impl From<u64> for ControlPointType {
+    fn from(t: u64) -> Self {
+        match t {
+            0 => ControlPointType::Normal,
+            1 => ControlPointType::VerticalLine,
+            2 => ControlPointType::HorizontalLine,
+            n => ControlPointType::StraightLine(n),
+        }
+    }
+}
+
+impl From<ControlPointType> for u64 {
+    fn from(point_type: ControlPointType) -> Self {
+        match point_type {
+            ControlPointType::Normal => 0,
+            ControlPointType::VerticalLine => 1,
+            ControlPointType::HorizontalLine => 2,
+            ControlPointType::StraightLine(n) => n,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ControlPointPair {
+    pub cp1: ControlPoint,
+    pub cp2: ControlPoint,
+    pub point_type: ControlPointType,
+}
+
+impl ControlPointPair {
+
+    /// this pair with its image ids replaced by `remap`: None if either image is removed
+    pub fn remap_images(&self, remap: impl Fn(usize) -> Option<usize>) -> Option<ControlPointPair> {
+
+        let remap_point = |cp: &ControlPoint| remap(cp.image_id as usize).map(|image_id| ControlPoint{image_id: image_id as u64, ..cp.clone()});
+
+        Some(ControlPointPair {
+            cp1: remap_point(&self.cp1)?,
+            cp2: remap_point(&self.cp2)?,
+            point_type: self.point_type,
+        })
+    }
+}
+
+fn uinteger64(input: &str) -> LineResult<'_, u64> {
 
-    map_res(
-        take_while1(|c: char| c.is_digit(10)),
-        |s| u64::from_str(s)
+    context("an unsigned integer",
+        map_res(
+            take_while1(|c: char| c.is_digit(10)),
+            |s| u64::from_str(s)
+        )
     )(input)
 }
 
-fn control_point_pair(input: &str) -> IResult<&str, (ControlPoint, ControlPoint)> {
+fn parameter(input: &str) -> LineResult<'_, Parameter> {
+
+    let (i, name) = alpha1(input)?;
+
+    let (i, value) = alt((
+        map(preceded(char('='), uinteger64), ParameterValue::Link),
+        map(delimited(char('"'), take_while(|c| c != '"'), char('"')), |s: &str| ParameterValue::Text(s.to_string())),
+        map(pair(double, many1(preceded(char(','), double))), |(first, rest)| {
+            ParameterValue::List(std::iter::once(first).chain(rest).collect())
+        }),
+        map(double, ParameterValue::Number),
+    ))(i)?;
+
+    Ok((i, Parameter{name: name.to_string(), value}))
+}
+
+/// space-separated parameters, to the end of the line
+fn parameters(input: &str) -> LineResult<'_, Vec<Parameter>> {
+
+    let (i, parameters) = many0(preceded(space1, parameter))(input)?;
+    let (i, _) = space0(i)?;
+    let (i, _) = context("a parameter (a name followed by a value)", eof)(i)?;
+
+    Ok((i, parameters))
+}
+
+fn panorama_line(input: &str) -> LineResult<'_, Panorama> {
+
+    let (i, _) = tag("p")(input)?;
+    map_res(parameters, Panorama::try_from_parameters)(i)
+}
+
+fn mode_line(input: &str) -> LineResult<'_, Mode> {
+
+    let (i, _) = tag("m")(input)?;
+    map(parameters, |parameters| Mode{parameters})(i)
+}
+
+fn image_line(input: &str) -> LineResult<'_, Image> {
+
+    let (i, _) = tag("i")(input)?;
+    map_res(parameters, Image::try_from_parameters)(i)
+}
+
+fn optimize_variable(input: &str) -> LineResult<'_, OptimizeVariable> {
+
+    let (i, name) = alpha1(input)?;
+    let (i, image_id) = uinteger64(i)?;
+
+    Ok((i, OptimizeVariable{name: name.to_string(), image_id}))
+}
+
+fn optimize_variables_line(input: &str) -> LineResult<'_, Vec<OptimizeVariable>> {
+
+    let (i, _) = tag("v")(input)?;
+    let (i, variables) = many0(preceded(space1, optimize_variable))(i)?;
+    let (i, _) = space0(i)?;
+    let (i, _) = context("a variable name followed by an image index", eof)(i)?;
+
+    Ok((i, variables))
+}
+
+fn mask_line(input: &str) -> LineResult<'_, Mask> {
+
+    let (i, _) = tag("k")(input)?;
+    map_res(parameters, Mask::try_from_parameters)(i)
+}
+
+fn pto_line(input: &str) -> LineResult<'_, PtoLine> {
+
+    //the first character determines the line type
+    match input.chars().next() {
+        Some('p') => map(panorama_line, PtoLine::Panorama)(input),
+        Some('m') => map(mode_line, PtoLine::Mode)(input),
+        Some('i') => map(image_line, PtoLine::Image)(input),
+        Some('v') => map(optimize_variables_line, PtoLine::OptimizeVariables)(input),
+        Some('k') => map(mask_line, PtoLine::Mask)(input),
+        Some('c') => map(control_point_pair, PtoLine::ControlPointPair)(input),
+        Some('#') => map(preceded(char('#'), rest), |s: &str| PtoLine::Comment(s.to_string()))(input),
+        _ if input.trim().is_empty() => Ok(("", PtoLine::Blank)),
+        _ => map(rest, |s: &str| PtoLine::Unknown(s.to_string()))(input),
+    }
+}
+
+fn control_point_pair(input: &str) -> LineResult<'_, ControlPointPair> {
 
     let (i, _) = tag("c")(input)?;
 
-    let (i, _) = tag(" n")(i)?;
+    let (i, _) = context("' n'", tag(" n"))(i)?;
     let (i, id1) = uinteger64(i)?;
 
-    let (i, _) = tag(" N")(i)?;
+    let (i, _) = context("' N'", tag(" N"))(i)?;
     let (i, id2) = uinteger64(i)?;
 
-    let (i, _) = tag(" x")(i)?;
+    let (i, _) = context("' x'", tag(" x"))(i)?;
     let (i, x1) = double(i)?;
 
-    let (i, _) = tag(" y")(i)?;
+    let (i, _) = context("' y'", tag(" y"))(i)?;
     let (i, y1) = double(i)?;
 
-    let (i, _) = tag(" X")(i)?;
+    let (i, _) = context("' X'", tag(" X"))(i)?;
     let (i, x2) = double(i)?;
 
-    let (i, _) = tag(" Y")(i)?;
+    let (i, _) = context("' Y'", tag(" Y"))(i)?;
     let (i, y2) = double(i)?;
 
-    let (i, _) = tag(" t0")(i)?;
+    let (i, _) = context("' t'", tag(" t"))(i)?;
+    let (i, point_type) = uinteger64(i)?;
 
     let (i, _) = multispace0(i)?;
 
     Ok((i,
-        (
-            ControlPoint{image_id: id1, x_coord: x1, y_coord: y1},
-            ControlPoint{image_id: id2, x_coord: x2, y_coord: y2}
-        )
+        ControlPointPair {
+            cp1: ControlPoint{image_id: id1, x_coord: x1, y_coord: y1},
+            cp2: ControlPoint{image_id: id2, x_coord: x2, y_coord: y2},
+            point_type: ControlPointType::from(point_type),
+        }
     ))
 }
 
-fn read_control_point_pairs_impl(pto_file_contents: &str) -> IResult<&str, Vec<(ControlPoint, ControlPoint)>> {
+fn read_control_point_pairs_impl(pto_file_contents: &str) -> LineResult<'_, Vec<ControlPointPair>> {
 
     let (i, _) = take_until("# control points")(pto_file_contents)?;
     let (i, _) = tag("# control points")(i)?;
@@ -86,7 +661,8 @@ fn read_control_point_pairs_impl(pto_file_contents: &str) -> IResult<&str, Vec<(
 }
 
 
-pub fn read_control_point_pairs(pto_file_contents: &str) -> std::result::Result<Vec<(ControlPoint, ControlPoint)>, String> {
+#[allow(dead_code)]
+pub fn read_control_point_pairs(pto_file_contents: &str) -> std::result::Result<Vec<ControlPointPair>, String> {
 
     match read_control_point_pairs_impl(pto_file_contents) {
         Ok((_, v)) => Ok(v),
@@ -96,6 +672,65 @@ pub fn read_control_point_pairs(pto_file_contents: &str) -> std::result::Result<
     }
 }
 
+fn read_pto_line(line_number: usize, line: &str) -> std::result::Result<PtoLine, PtoParseError> {
+
+    let error = |input: &str, expected: String| {
+
+        //parsers only return subslices of the line: get the error location's offset from the remaining input
+        let offset = line.len().saturating_sub(input.len());
+
+        PtoParseError {
+            line_number,
+            column: line[..offset].chars().count() + 1,
+            line: line.to_string(),
+            expected,
+        }
+    };
+
+    match all_consuming(pto_line)(line) {
+        Ok((_, pto_line)) => Ok(pto_line),
+        Err(nom::Err::Error(e)) => Err(error(e.input, e.expected)),
+        Err(nom::Err::Failure(e)) => Err(error(e.input, e.expected)),
+        Err(nom::Err::Incomplete(_)) => Err(error("", "more input".to_string())),
+    }
+}
+
+/// Reads a PTO file, failing at the first line which can't be parsed
+#[allow(dead_code)]
+pub fn read_pto_file(pto_file_contents: &str) -> std::result::Result<PtoFile, PtoParseError> {
+
+    let lines =
+    pto_file_contents.lines().enumerate().map(|(index, line)| {
+        read_pto_line(index + 1, line)
+    }).collect::<Result<Vec<PtoLine>, PtoParseError>>()?;
+
+    Ok(PtoFile{lines})
+}
+
+/// Reads a PTO file, storing lines which can't be parsed as `PtoLine::Unknown`
+/// (so they are preserved when the file is written) and returning their errors as warnings
+///
+/// Fails at an 'i' line which can't be parsed: skipping it would renumber the following images
+pub fn read_pto_file_lenient(pto_file_contents: &str) -> std::result::Result<(PtoFile, Vec<PtoParseError>), PtoParseError> {
+
+    let mut lines = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (index, line) in pto_file_contents.lines().enumerate() {
+
+        match read_pto_line(index + 1, line) {
+            Ok(pto_line) => lines.push(pto_line),
+            Err(e) if line.starts_with('i') => return Err(e),
+            Err(e) => {
+                lines.push(PtoLine::Unknown(line.to_string()));
+                warnings.push(e);
+            },
+        }
+    }
+
+    Ok((PtoFile{lines}, warnings))
+}
+
 
 #[cfg(test)]
 mod test {
@@ -118,21 +753,38 @@ mod test {
     #[test]
     fn control_point_pair_test() {
         {
-            let (s, (cp1, cp2)) =
+            let (s, ControlPointPair{cp1, cp2, point_type}) =
                 control_point_pair("c n123 N456 x789 y876 X543 Y210 t0").unwrap();
 
             assert_eq!(s, "");
             assert_eq!(cp1, ControlPoint::new(123, 789 as f64, 876 as f64));
             assert_eq!(cp2, ControlPoint::new(456, 543 as f64, 210 as f64));
+            assert_eq!(point_type, ControlPointType::Normal);
         }
 
         {
-            let (s, (cp1, cp2)) =
+            let (s, ControlPointPair{cp1, cp2, point_type}) =
                 control_point_pair("c n0 N1 x568.542826048136 y117.691966641595 X54.4570607766205 Y98.7300002744364 t0").unwrap();
 
             assert_eq!(s, "");
             assert_eq!(cp1, ControlPoint::new(0, 568.542826048136, 117.691966641595));
             assert_eq!(cp2, ControlPoint::new(1, 54.4570607766205, 98.7300002744364));
+            assert_eq!(point_type, ControlPointType::Normal);
+        }
+
+        //line control point types
+        {
+            let (_, pair) = control_point_pair("c n0 N0 x1 y2 X3 Y4 t1").unwrap();
+            assert_eq!(pair.point_type, ControlPointType::VerticalLine);
+
+            let (_, pair) = control_point_pair("c n0 N0 x1 y2 X3 Y4 t2").unwrap();
+            assert_eq!(pair.point_type, ControlPointType::HorizontalLine);
+
+            let (_, pair) = control_point_pair("c n0 N1 x1 y2 X3 Y4 t3").unwrap();
+            assert_eq!(pair.point_type, ControlPointType::StraightLine(3));
+
+            let (_, pair) = control_point_pair("c n0 N1 x1 y2 X3 Y4 t12").unwrap();
+            assert_eq!(pair.point_type, ControlPointType::StraightLine(12));
         }
 
         assert_matches!(control_point_pair("c n0 N1 x568.542826048136 y117.691966641595 X54.4570607766205 Y98.7300002744364 t0"), Ok(_));
@@ -160,8 +812,8 @@ more file contents
 ";
             let v = read_control_point_pairs(pto_file_contents).unwrap();
             assert_eq!(1, v.len());
-            assert_eq!(v[0].0, ControlPoint::new(0, 568.542826048136, 117.691966641595));
-            assert_eq!(v[0].1, ControlPoint::new(1, 54.4570607766205, 98.7300002744364));
+            assert_eq!(v[0].cp1, ControlPoint::new(0, 568.542826048136, 117.691966641595));
+            assert_eq!(v[0].cp2, ControlPoint::new(1, 54.4570607766205, 98.7300002744364));
         }
 
         //3 control point pairs
@@ -178,12 +830,12 @@ more file contents
 ";
             let v = read_control_point_pairs(pto_file_contents).unwrap();
             assert_eq!(3, v.len());
-            assert_eq!(v[0].0, ControlPoint::new(0, 568.542826048136, 117.691966641595));
-            assert_eq!(v[0].1, ControlPoint::new(1, 54.4570607766205, 98.7300002744364));
-            assert_eq!(v[1].0, ControlPoint::new(1, 111.1, 222.2));
-            assert_eq!(v[1].1, ControlPoint::new(123, 333.3, 444.4));
-            assert_eq!(v[2].0, ControlPoint::new(2, 555.5, 222.2));
-            assert_eq!(v[2].1, ControlPoint::new(123, 333.3, 444.4));
+            assert_eq!(v[0].cp1, ControlPoint::new(0, 568.542826048136, 117.691966641595));
+            assert_eq!(v[0].cp2, ControlPoint::new(1, 54.4570607766205, 98.7300002744364));
+            assert_eq!(v[1].cp1, ControlPoint::new(1, 111.1, 222.2));
+            assert_eq!(v[1].cp2, ControlPoint::new(123, 333.3, 444.4));
+            assert_eq!(v[2].cp1, ControlPoint::new(2, 555.5, 222.2));
+            assert_eq!(v[2].cp2, ControlPoint::new(123, 333.3, 444.4));
         }
 
         //no control point pairs
@@ -223,4 +875,351 @@ more file contents
             assert_matches!(read_control_point_pairs(pto_file_contents), Err(_));
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parameter_test() {
+
+        assert_eq!(parameter("w920"), Ok(("", Parameter{name: "w".to_string(), value: ParameterValue::Number(920.0)})));
+        assert_eq!(parameter("Eev-1.5 x"), Ok((" x", Parameter{name: "Eev".to_string(), value: ParameterValue::Number(-1.5)})));
+        assert_eq!(parameter("v=0"), Ok(("", Parameter{name: "v".to_string(), value: ParameterValue::Link(0)})));
+        assert_eq!(parameter("n\"a b.jpg\""), Ok(("", Parameter{name: "n".to_string(), value: ParameterValue::Text("a b.jpg".to_string())})));
+        assert_eq!(parameter("n\"\""), Ok(("", Parameter{name: "n".to_string(), value: ParameterValue::Text("".to_string())})));
+        assert_eq!(parameter("S0,920,12.5,614 x"), Ok((" x", Parameter{name: "S".to_string(), value: ParameterValue::List(vec![0.0, 920.0, 12.5, 614.0])})));
+
+        assert_matches!(parameter("920"), Err(_));
+        assert_matches!(parameter("w"), Err(_));
+        assert_matches!(parameter("n\"unterminated"), Err(_));
+    }
+
+    #[test]
+    fn image_line_test() {
+
+        let (s, image) =
+            image_line("i w920 h614 f0 v=0 Ra0 Eev0 r-1.5 p2 y30.25 TrX0 a=0 b0.01 c-0.02 d1 e-1 g0 t0 Va1 n\"DSC_9108.JPG\"").unwrap();
+
+        assert_eq!(s, "");
+        assert_eq!(image.width, 920);
+        assert_eq!(image.height, 614);
+        assert_eq!(image.projection, 0);
+        assert_eq!(image.fov, ImageVariable::Link(0));
+        assert_eq!(image.yaw, ImageVariable::Value(30.25));
+        assert_eq!(image.pitch, ImageVariable::Value(2.0));
+        assert_eq!(image.roll, ImageVariable::Value(-1.5));
+        assert_eq!(image.a, ImageVariable::Link(0));
+        assert_eq!(image.b, ImageVariable::Value(0.01));
+        assert_eq!(image.c, ImageVariable::Value(-0.02));
+        assert_eq!(image.d, ImageVariable::Value(1.0));
+        assert_eq!(image.e, ImageVariable::Value(-1.0));
+        assert_eq!(image.file_name, "DSC_9108.JPG");
+
+        let other_names: Vec<&str> = image.other_parameters.iter().map(|p| p.name.as_ref()).collect();
+        assert_eq!(other_names, vec!["Ra", "Eev", "TrX", "g", "t", "Va"]);
+
+        //missing variables default to 0
+        let (_, mut image) = image_line("i w920 h614 f0 n\"a.jpg\"").unwrap();
+        assert_eq!(image.yaw, ImageVariable::Value(0.0));
+
+        //other variables
+        let (_, image_with_others) = image_line("i w920 h614 f0 Va1.1 Vb=0 n\"a.jpg\"").unwrap();
+        assert_eq!(image_with_others.other_variable("Va", 1.0), ImageVariable::Value(1.1));
+        assert_eq!(image_with_others.other_variable("Vb", 0.0), ImageVariable::Link(0));
+        assert_eq!(image.other_variable("Va", 1.0), ImageVariable::Value(1.0));
+
+        image.set_other_variable("Va", ImageVariable::Value(0.9));
+        image.set_other_variable("Va", ImageVariable::Link(2));
+        assert_eq!(image.other_parameters, vec![Parameter{name: "Va".to_string(), value: ParameterValue::Link(2)}]);
+
+        //missing required parameters
+        assert_matches!(image_line("i h614 f0 n\"a.jpg\""), Err(_));
+        assert_matches!(image_line("i w920 h614 f0"), Err(_));
+
+        //invalid values
+        assert_matches!(image_line("i w920.5 h614 f0 n\"a.jpg\""), Err(_));
+        assert_matches!(image_line("i w920 h614 f0 y\"text\" n\"a.jpg\""), Err(_));
+
+        //a cropped image (Hugin's "S<left>,<right>,<top>,<bottom>")
+        let (s, image) = image_line("i w920 h614 f0 v50.2734 Ra0 Rb0 Rc0 Rd0 Re0 Eev11.9 Er1 Eb1 r0.117 p-0.554 y-10.02 TrX0 TrY0 TrZ0 Tpy0 Tpp0 j0 a0.00190984684 b-0.00282668791 c0.00953214827 d0 e0 g0 t0 Va1 Vb0 Vc0 Vd0 Vx0 Vy0 Vm5 S24,896,12,600 n\"DSC_9108_12_5.JPG\"").unwrap();
+        assert_eq!(s, "");
+        assert_eq!(image.other_parameters.last(), Some(&Parameter{name: "S".to_string(), value: ParameterValue::List(vec![24.0, 896.0, 12.0, 600.0])}));
+    }
+
+    #[test]
+    fn panorama_line_test() {
+
+        let (s, panorama) =
+            panorama_line("p f2 w3000 h1500 v360 E0 R0 n\"TIFF_m c:LZW r:CROP\"").unwrap();
+
+        assert_eq!(s, "");
+        assert_eq!(panorama.projection, 2);
+        assert_eq!(panorama.width, 3000);
+        assert_eq!(panorama.height, 1500);
+        assert_eq!(panorama.fov, 360.0);
+        assert_eq!(panorama.other_parameters.len(), 3);
+        assert_eq!(panorama.other_parameters[2], Parameter{name: "n".to_string(), value: ParameterValue::Text("TIFF_m c:LZW r:CROP".to_string())});
+
+        assert_matches!(panorama_line("p w3000 h1500 v360"), Err(_));
+
+        //a cropped panorama
+        let (_, panorama) = panorama_line("p f2 w3000 h1500 v360  k0 E11.9 R0 S150,2850,300,1200 n\"TIFF_m c:LZW r:CROP\"").unwrap();
+        assert_eq!(panorama.other_parameters[3], Parameter{name: "S".to_string(), value: ParameterValue::List(vec![150.0, 2850.0, 300.0, 1200.0])});
+    }
+
+    #[test]
+    fn optimize_variables_line_test() {
+
+        assert_eq!(optimize_variables_line("v"), Ok(("", vec![])));
+        assert_eq!(optimize_variables_line("v y1 TrX2"), Ok(("",
+            vec![
+                OptimizeVariable{name: "y".to_string(), image_id: 1},
+                OptimizeVariable{name: "TrX".to_string(), image_id: 2},
+            ]
+        )));
+
+        assert_matches!(all_consuming(optimize_variables_line)("v y"), Err(_));
+    }
+
+    #[test]
+    fn mask_line_test() {
+
+        let (s, mask) = mask_line("k i1 t0 p\"10 20 30.5 40 50 60\"").unwrap();
+
+        assert_eq!(s, "");
+        assert_eq!(mask, Mask{image_id: 1, mask_type: 0, polygon: vec![(10.0, 20.0), (30.5, 40.0), (50.0, 60.0)]});
+
+        assert_matches!(mask_line("k i1 t0 p\"10 20 30\""), Err(_));
+        assert_matches!(mask_line("k i1 t0 p\"10 x 30 40\""), Err(_));
+    }
+
+    #[test]
+    fn read_pto_file_test() {
+
+let pto_file_contents =
+"# hugin project file
+#hugin_ptoversion 2
+p f2 w3000 h1500 v360 E0 R0 n\"TIFF_m c:LZW\"
+m g1 i0 f0 m2 p0.00784314
+
+# image lines
+i w920 h614 f0 v50 r0 p0 y0 a0 b0 c0 d0 e0 n\"DSC_9108_12_5.JPG\"
+i w920 h614 f0 v=0 r1 p-1 y20 a=0 b=0 c=0 d0 e0 n\"DSC_9109_12_5.JPG\"
+
+# specify variables that should be optimized
+v y1 p1 r1
+v
+
+k i0 t0 p\"0 0 10 0 10 10\"
+
+# control points
+c n0 N1 x568.542826048136 y117.691966641595 X54.4570607766205 Y98.7300002744364 t0
+c n0 N1 x111.1 y222.2 X333.3 Y444.4 t0
+
+*
+";
+        let pto_file = read_pto_file(pto_file_contents).unwrap();
+
+        assert_eq!(pto_file.lines.len(), 20);
+        assert_eq!(pto_file.lines[0], PtoLine::Comment(" hugin project file".to_string()));
+        assert_matches!(pto_file.lines[2], PtoLine::Panorama(_));
+        assert_matches!(pto_file.lines[3], PtoLine::Mode(_));
+        assert_eq!(pto_file.lines[4], PtoLine::Blank);
+        assert_matches!(pto_file.lines[10], PtoLine::OptimizeVariables(_));
+        assert_matches!(pto_file.lines[13], PtoLine::Mask(_));
+        assert_eq!(pto_file.lines[19], PtoLine::Unknown("*".to_string()));
+
+        let images = pto_file.images();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].file_name, "DSC_9108_12_5.JPG");
+        assert_eq!(images[1].file_name, "DSC_9109_12_5.JPG");
+
+        assert_eq!(pto_file.resolve_image_variable(1, |image| image.fov), Some(50.0));
+        assert_eq!(pto_file.resolve_image_variable(1, |image| image.yaw), Some(20.0));
+        assert_eq!(pto_file.resolve_image_variable(2, |image| image.yaw), None);
+        assert_eq!(pto_file.image_variable_source(1, |image| image.fov), Some(0));
+        assert_eq!(pto_file.image_variable_source(1, |image| image.yaw), Some(1));
+
+        let pairs = pto_file.control_point_pairs();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[1].cp1, ControlPoint::new(0, 111.1, 222.2));
+        assert_eq!(pairs[1].cp2, ControlPoint::new(1, 333.3, 444.4));
+
+        //malformed image line
+        assert_matches!(read_pto_file("i w920 h614 f0 v50 [invalid] n\"a.jpg\""), Err(_));
+
+        //circular links don't resolve
+        let pto_file = read_pto_file("i w1 h1 f0 v=1 n\"a.jpg\"\ni w1 h1 f0 v=0 n\"b.jpg\"").unwrap();
+        assert_eq!(pto_file.resolve_image_variable(0, |image| image.fov), None);
+        assert_eq!(pto_file.image_variable_source(0, |image| image.fov), None);
+    }
+
+    #[test]
+    fn read_pto_file_error_test() {
+
+        //invalid parameter
+        {
+            let e = read_pto_file("# comment\ni w920 h614 f0 v50 [invalid] n\"a.jpg\"").unwrap_err();
+
+            assert_eq!(e.line_number, 2);
+            assert_eq!(e.column, 20);
+            assert_eq!(e.line, "i w920 h614 f0 v50 [invalid] n\"a.jpg\"");
+            assert_eq!(e.expected, "a parameter (a name followed by a value)");
+        }
+
+        //missing parameter
+        {
+            let e = read_pto_file("i w920 h614 f0 v50").unwrap_err();
+
+            assert_eq!(e.line_number, 1);
+            assert_eq!(e.expected, "parameter 'n'");
+        }
+
+        //invalid control point field
+        {
+            let e = read_pto_file("\n\nc n0 N1 x568.5 y117.6 X54.4 Z98.7 t0").unwrap_err();
+
+            assert_eq!(e.line_number, 3);
+            assert_eq!(e.column, 28);
+            assert_eq!(e.expected, "' Y'");
+        }
+
+        //invalid control point value
+        {
+            let e = read_pto_file("c n0 N1 x568.5 y117.6 X54.4 Y98.7 t-1").unwrap_err();
+
+            assert_eq!(e.column, 36);
+            assert_eq!(e.expected, "an unsigned integer");
+        }
+
+        //unexpected trailing text
+        {
+            let e = read_pto_file("c n0 N1 x568.5 y117.6 X54.4 Y98.7 t0 trailing").unwrap_err();
+
+            assert_eq!(e.column, 38);
+            assert_eq!(e.expected, "end of line");
+        }
+
+        //column counts characters, not bytes
+        {
+            let e = read_pto_file("i w920 h614 f0 n\"ä.jpg\" [invalid]").unwrap_err();
+
+            assert_eq!(e.column, 25);
+        }
+    }
+
+    #[test]
+    fn pto_parse_error_display_test() {
+
+        let e = PtoParseError {
+            line_number: 3,
+            column: 5,
+            line: "c n0 N1".to_string(),
+            expected: "' n'".to_string(),
+        };
+
+        assert_eq!(format!("{}", e), "line 3, column 5: expected ' n'\nc n0 N1\n    ^");
+    }
+
+    #[test]
+    fn read_pto_file_lenient_test() {
+
+let pto_file_contents =
+"p f2 w3000 h1500 v360
+i w920 h614 f0 v50 n\"a.jpg\"
+i w920 h614 f0 v50 n\"b.jpg\"
+k i0 t0 p\"[invalid]\"
+c n0 N1 x1 y2 X3 Y4 t0
+c n0 N1 x1 y2 X3
+";
+        assert_matches!(read_pto_file(pto_file_contents), Err(_));
+
+        let (pto_file, warnings) = read_pto_file_lenient(pto_file_contents).unwrap();
+
+        assert_eq!(pto_file.lines.len(), 6);
+        assert_eq!(pto_file.images().len(), 2);
+        assert_eq!(pto_file.control_point_pairs().len(), 1);
+
+        //invalid lines are kept as unknown lines
+        assert_eq!(pto_file.lines[3], PtoLine::Unknown("k i0 t0 p\"[invalid]\"".to_string()));
+        assert_eq!(pto_file.lines[5], PtoLine::Unknown("c n0 N1 x1 y2 X3".to_string()));
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].line_number, 4);
+        assert_eq!(warnings[1].line_number, 6);
+
+        //an invalid image line would renumber the images after it
+        let pto_file_contents = pto_file_contents.replace("v50 n\"b.jpg\"", "v[invalid] n\"b.jpg\"");
+        assert_matches!(read_pto_file_lenient(&pto_file_contents), Err(PtoParseError{line_number: 3, ..}));
+
+        //cropped Hugin image lines are valid: only the invalid line is a warning
+        let pto_file_contents =
+"p f2 w3000 h1500 v360  k0 E11.9 R0 S150,2850,300,1200 n\"TIFF_m c:LZW r:CROP\"
+i w920 h614 f0 v50.2734 Ra0 Rb0 Rc0 Rd0 Re0 Eev11.9 Er1 Eb1 r0.117 p-0.554 y-10.02 TrX0 TrY0 TrZ0 Tpy0 Tpp0 j0 a0.00190984684 b-0.00282668791 c0.00953214827 d0 e0 g0 t0 Va1 Vb0 Vc0 Vd0 Vx0 Vy0 Vm5 S24,896,12,600 n\"DSC_9108_12_5.JPG\"
+i w920 h614 f0 v=0 Ra=0 Rb=0 Rc=0 Rd=0 Re=0 Eev11.9 Er1 Eb1 r-0.2 p0.3 y22.5 TrX0 TrY0 TrZ0 Tpy0 Tpp0 j0 a=0 b=0 c=0 d=0 e=0 g=0 t=0 Va=0 Vb=0 Vc=0 Vd=0 Vx=0 Vy=0 Vm5 S24,896,12,600 n\"DSC_9109_12_5.JPG\"
+c n0 N1 x1 y2 X3
+";
+        let (pto_file, warnings) = read_pto_file_lenient(pto_file_contents).unwrap();
+
+        assert_eq!(pto_file.images().len(), 2);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line_number, 4);
+    }
+
+    #[test]
+    fn remap_images_test() {
+
+        let pair = ControlPointPair {
+            cp1: ControlPoint::new(0, 1.0, 2.0),
+            cp2: ControlPoint::new(2, 3.0, 4.0),
+            point_type: ControlPointType::Normal,
+        };
+
+        let swapped = pair.remap_images(|index| Some(2 - index)).unwrap();
+        assert_eq!(swapped.cp1, ControlPoint::new(2, 1.0, 2.0));
+        assert_eq!(swapped.cp2, ControlPoint::new(0, 3.0, 4.0));
+
+        assert_eq!(pair.remap_images(|index| if index == 2 {None} else {Some(index)}), None);
+    }
+
+    #[test]
+    fn set_control_point_pairs_test() {
+
+        let pair = |id: u64| ControlPointPair {
+            cp1: ControlPoint::new(id, 1.0, 2.0),
+            cp2: ControlPoint::new(id + 1, 3.0, 4.0),
+            point_type: ControlPointType::Normal,
+        };
+
+        let mut pto_file = read_pto_file("# start\nc n0 N1 x1 y2 X3 Y4 t0\n# middle\nc n1 N2 x1 y2 X3 Y4 t0\n# end").unwrap();
+
+        pto_file.set_control_point_pairs(vec![pair(5), pair(6)]);
+
+        assert_eq!(pto_file.lines, vec![
+            PtoLine::Comment(" start".to_string()),
+            PtoLine::ControlPointPair(pair(5)),
+            PtoLine::ControlPointPair(pair(6)),
+            PtoLine::Comment(" middle".to_string()),
+            PtoLine::Comment(" end".to_string()),
+        ]);
+
+        //unchanged pairs keep their order, new pairs follow them
+        pto_file.set_control_point_pairs(vec![pair(7), pair(6), pair(5)]);
+
+        assert_eq!(pto_file.lines, vec![
+            PtoLine::Comment(" start".to_string()),
+            PtoLine::ControlPointPair(pair(5)),
+            PtoLine::ControlPointPair(pair(6)),
+            PtoLine::ControlPointPair(pair(7)),
+            PtoLine::Comment(" middle".to_string()),
+            PtoLine::Comment(" end".to_string()),
+        ]);
+
+        //no existing pairs: add at the end
+        let mut pto_file = read_pto_file("# start").unwrap();
+
+        pto_file.set_control_point_pairs(vec![pair(0)]);
+
+        assert_eq!(pto_file.lines, vec![
+            PtoLine::Comment(" start".to_string()),
+            PtoLine::ControlPointPair(pair(0)),
+        ]);
+    }
+}
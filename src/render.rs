@@ -4,6 +4,7 @@ use three_d::gui::GUI;
 use three_d::{Context, CameraControl, FrameInput};
 
 use crate::control_state::{ControlState, UiMode};
+use crate::blend::BlendMode;
 use crate::entities::Entities;
 use crate::ViewportGeometry;
 
@@ -14,6 +15,8 @@ mod render_states;
 mod map_overlay;
 mod colors;
 
+use photo::PhotoAlpha;
+
 
 /// Stores immutable references used in rendering
 #[derive(Copy, Clone)]
@@ -27,6 +30,7 @@ pub struct Renderer<'a> {
     texture_program: &'a MeshProgram,
     texture_dewarp_program: &'a MeshProgram,
     texture_dewarp2_program: &'a MeshProgram,
+    texture_spherical_program: &'a MeshProgram,
     color_program: &'a MeshProgram,
 
 
@@ -47,6 +51,7 @@ impl Renderer<'_> {
         texture_program: &'a MeshProgram,
         texture_dewarp_program: &'a MeshProgram,
         texture_dewarp2_program: &'a MeshProgram,
+        texture_spherical_program: &'a MeshProgram,
         color_program: &'a MeshProgram,
 
         viewport_geometry: &'a ViewportGeometry,
@@ -63,6 +68,7 @@ impl Renderer<'_> {
             texture_program,
             texture_dewarp_program,
             texture_dewarp2_program,
+            texture_spherical_program,
             color_program,
 
             viewport_geometry,
@@ -82,29 +88,31 @@ impl Renderer<'_> {
             //depth testing is not used: draw order determines visibility (last = most visible)
 
             //render photos
-            match self.control_state.ui_mode {
-                UiMode::Browse => self.render_photos_with_pixel_averaging()?,
-                UiMode::Edit => {
-
-                    if self.control_state.alignment_mode {
+            if self.control_state.ui_mode == UiMode::Edit && self.control_state.alignment_mode {
 
-                        //in alignment mode, use standard transparency
-                        self.render_photos(0.5, render_states::render_states_transparency())?;
-                    }
-                    else {
+                //in alignment mode, use standard transparency
+                self.render_photos(PhotoAlpha::Constant(0.5), render_states::render_states_transparency())?;
+            }
+            else {
 
-                        //otherwise, use pixel averaging
-                        self.render_photos_with_pixel_averaging()?;
-                    }
-                },
+                //otherwise, use the selected blend mode
+                match self.control_state.blend_mode {
+                    BlendMode::Average => self.render_photos_with_pixel_averaging(PhotoAlpha::Constant(1.0))?,
+                    BlendMode::Feather => self.render_photos_with_pixel_averaging(PhotoAlpha::EdgeDistance)?,
+                    BlendMode::MultiBand => self.render_photos_with_multiband_blending()?,
+                }
             }
 
             if self.control_state.ui_mode == UiMode::Edit {
 
                 if self.control_state.control_points_visible {
 
-                    //(temporary) point renderer hardcoded to the first 2 images
-                    self.render_control_points_temp()?;
+                    self.render_control_points()?;
+                }
+
+                if self.control_state.residuals_visible {
+
+                    self.render_residuals()?;
                 }
 
                 if let Some(ref rp) = self.control_state.active_rotation_point {
@@ -120,6 +128,13 @@ impl Renderer<'_> {
                 }
             }
 
+            if self.control_state.ui_mode == UiMode::Edit && self.control_state.seams_visible {
+
+                if let Some(ref seam_map) = self.entities.seam_map {
+                    self.draw_seams(seam_map)?;
+                }
+            }
+
             if self.control_state.photo_borders_visible {
 
                 self.draw_photo_border_rectangles(&self.entities.photos)?;
@@ -9,12 +9,49 @@ pub fn map_overlay_clear() -> ClearState {
     ClearState::color(0.0, 0.5, 0.0, 0.0)
 }
 
-pub fn photo1_control_points_temp() -> Vec4 {
-    Vec4::new(0.8, 0.5, 0.2, 0.5)
+/// control point color for the image pair at `index` (repeats after 8)
+pub fn image_pair_control_points(index: usize) -> Vec4 {
+
+    let colors = [
+        (0.8, 0.5, 0.2),
+        (0.2, 0.8, 0.2),
+        (0.2, 0.5, 0.9),
+        (0.9, 0.3, 0.3),
+        (0.9, 0.9, 0.2),
+        (0.6, 0.3, 0.9),
+        (0.2, 0.8, 0.8),
+        (0.9, 0.4, 0.7),
+    ];
+
+    let (r, g, b) = colors[index % colors.len()];
+    Vec4::new(r, g, b, 0.5)
+}
+
+pub fn seams() -> Vec4 {
+    Vec4::new(1.0, 0.9, 0.1, 0.9)
+}
+
+pub fn outlier_control_points() -> Vec4 {
+    Vec4::new(1.0, 0.1, 0.1, 1.0)
+}
+
+/// green (0) to yellow to red (1 and above)
+pub fn residual(magnitude: f64) -> Vec4 {
+
+    let t = magnitude.clamp(0.0, 1.0) as f32;
+    Vec4::new((2.0 * t).min(1.0) * 0.9, (2.0 - 2.0 * t).min(1.0) * 0.8, 0.1, 1.0)
+}
+
+pub fn vertical_line_control_points() -> Vec4 {
+    Vec4::new(0.2, 0.8, 0.8, 1.0)
+}
+
+pub fn horizontal_line_control_points() -> Vec4 {
+    Vec4::new(0.8, 0.2, 0.8, 1.0)
 }
 
-pub fn photo2_control_points_temp() -> Vec4 {
-    Vec4::new(0.2, 0.8, 0.2, 0.5)
+pub fn straight_line_control_points() -> Vec4 {
+    Vec4::new(0.8, 0.8, 0.8, 1.0)
 }
 
 pub fn rotation_point() -> Vec4 {
@@ -9,6 +9,11 @@ impl Renderer<'_> {
     #[allow(dead_code)]
     pub(in super) fn render_map_overlay(&self) {
 
+        let overlay_mesh = match &self.entities.overlay_mesh {
+            Some(overlay_mesh) => overlay_mesh,
+            None => return,
+        };
+
         //create texture for overlay contents
         use three_d::definition::{Interpolation, Wrapping, Format};
         use three_d::vec3;
@@ -31,7 +36,7 @@ impl Renderer<'_> {
 
         overlay_texture.write(colors::map_overlay_clear(), || {
 
-            self.texture_program.use_texture(&self.entities.overlay_mesh.texture_2d, "tex").unwrap();
+            self.texture_program.use_texture(&overlay_mesh.texture_2d, "tex").unwrap();
             self.texture_program.use_uniform_float("out_alpha", &1.0).unwrap();
 
             let viewport = Viewport::new_at_origo(overlay_width_px,overlay_height_px);
@@ -43,7 +48,7 @@ impl Renderer<'_> {
                                          1.0,
                                          10.0).unwrap();
 
-            let mut mesh = self.entities.overlay_mesh.mesh.clone();
+            let mut mesh = overlay_mesh.mesh.clone();
             mesh.transformation = Mat4::identity()
                 //flip y-coords
                 .concat(&Mat4::from_nonuniform_scale(1.0, -1.0, 1.0)
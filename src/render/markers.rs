@@ -7,31 +7,76 @@ use crate::control_state::{RotationPoint,RotateDrag};
 
 use super::{Renderer,colors,render_states};
 use crate::photo::Photo;
-use crate::world_rectangle::Corner;
 use crate::viewport_geometry::PixelCoords;
+use crate::read_pto::{ControlPoint, ControlPointType};
+use crate::spherical::CameraModel;
+use crate::seams::SeamMap;
 
 impl Renderer<'_> {
 
-    pub(in super) fn render_control_points_temp(&self) -> Result<(), Error> {
+    /// draws every control point pair in its photos:
+    /// * normal pairs as points, colored by image pair
+    /// * outlier pairs as red lines between their points
+    /// * line-type pairs as guide lines between their points
+    pub(in super) fn render_control_points(&self) -> Result<(), Error> {
 
-        let points = &self.entities.image0_control_points;
+        let lens_correction = self.control_state.dewarp_shader.lens_correction();
 
-        for &v in points {
-
-            let pixel_coords = PixelCoords{ x: v.x as f64, y: v.y as f64};
-            let world_coords = self.entities.photos[0].world_coords(pixel_coords);
+        let world_coords = |cp: &ControlPoint| {
+            self.entities.photos.get(cp.image_id as usize).and_then(|photo| {
+                photo.world_coords(&self.entities.camera_model, lens_correction, PixelCoords{ x: cp.x_coord, y: cp.y_coord })
+            })
+        };
 
-            self.draw_point(world_coords, 0.0, colors::photo1_control_points_temp())?;
+        for (index, (_, pairs)) in self.entities.control_points.iter().enumerate() {
+
+            let pair_color = colors::image_pair_control_points(index);
+
+            for pair in pairs {
+
+                let (point1, point2) = match (world_coords(&pair.cp1), world_coords(&pair.cp2)) {
+                    (Some(point1), Some(point2)) => (point1, point2),
+                    _ => continue,
+                };
+
+                let line_color = match pair.point_type {
+                    ControlPointType::Normal if self.entities.outliers.contains(pair) => colors::outlier_control_points(),
+                    ControlPointType::Normal => {
+                        self.draw_point(point1, 0.0, pair_color)?;
+                        self.draw_point(point2, 45.0, pair_color)?;
+                        continue;
+                    },
+                    ControlPointType::VerticalLine => colors::vertical_line_control_points(),
+                    ControlPointType::HorizontalLine => colors::horizontal_line_control_points(),
+                    ControlPointType::StraightLine(_) => colors::straight_line_control_points(),
+                };
+
+                self.draw_line(point1, point2, 1.0, line_color)?;
+                self.draw_point(point1, 45.0, line_color)?;
+                self.draw_point(point2, 45.0, line_color)?;
+            }
         }
 
-        let points = &self.entities.image1_control_points;
+        Ok(())
+    }
+
+    /// draws each normal control point pair's residual vector:
+    /// colored from green (0) to red (3x the overall RMS residual or more)
+    pub(in super) fn render_residuals(&self) -> Result<(), Error> {
 
-        for &v in points {
+        let report = self.entities.residual_report(self.control_state.dewarp_shader.lens_correction());
 
-            let pixel_coords = PixelCoords{ x: v.x as f64, y: v.y as f64};
-            let world_coords = self.entities.photos[1].world_coords(pixel_coords);
+        let color_scale = match report.overall {
+            Some(stats) if stats.rms > 0.0 => 3.0 * stats.rms,
+            _ => return Ok(()),
+        };
 
-            self.draw_point(world_coords, 45.0, colors::photo2_control_points_temp())?;
+        for residual in &report.residuals {
+
+            let color = colors::residual(residual.distance() / color_scale);
+
+            self.draw_line(residual.point1, residual.point2, 2.0, color)?;
+            self.draw_point(residual.point2, 45.0, color)?;
         }
 
         Ok(())
@@ -85,20 +130,24 @@ impl Renderer<'_> {
 
     fn draw_photo_border_rectangle(&self, photo: &Photo, color: Vec4) -> Result<(), Error> {
 
-        let draw_corner_line = |corner1: Corner, corner2: Corner| {
+        //follows lens distortion correction along each edge
+        let border = photo.border(&self.entities.camera_model, self.control_state.dewarp_shader.lens_correction(), 16);
 
-            self.draw_line(
-                photo.orientation().corner(corner1),
-                photo.orientation().corner(corner2),
-                1.0,
-                color,
-            )
-        };
+        for (index, &point) in border.iter().enumerate() {
+
+            let next = border[(index + 1) % border.len()];
 
-        draw_corner_line(Corner::BottomLeft, Corner::BottomRight)?;
-        draw_corner_line(Corner::BottomRight, Corner::TopRight)?;
-        draw_corner_line(Corner::TopRight, Corner::TopLeft)?;
-        draw_corner_line(Corner::TopLeft, Corner::BottomLeft)
+            //don't draw across a spherical projection's wraparound edge
+            if let CameraModel::Spherical(projection) = &self.entities.camera_model {
+                if !projection.is_continuous(point, next) {
+                    continue;
+                }
+            }
+
+            self.draw_line(point, next, 1.0, color)?;
+        }
+
+        Ok(())
     }
 
     pub(in super) fn draw_selected_photo_border_rectangle(&self, photo: &Photo) -> Result<(), Error> {
@@ -113,6 +162,16 @@ impl Renderer<'_> {
             self.draw_photo_border_rectangle(photo, colors::photo_border_rectangle())?;
         }
 
+        Ok(())
+    }
+    /// draws the boundaries between photos in `seam_map`
+    pub(in super) fn draw_seams(&self, seam_map: &SeamMap) -> Result<(), Error> {
+
+        for (point1, point2) in seam_map.boundaries() {
+
+            self.draw_line(point1, point2, 2.0, colors::seams())?;
+        }
+
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,33 +1,145 @@
-use three_d::{Screen,ClearState,RenderStates,ColorTargetTexture2D};
+use three_d::{Screen,ClearState,RenderStates,ColorTargetTexture2D,MeshProgram,ImageEffect,Vec2,Vec3,Vec4,Mat3,Mat4,Viewport,Texture};
 use three_d::Error;
 
 use crate::control_state::DewarpShader;
+use crate::photo::Photo;
+use crate::lens::Vignetting;
+use crate::spherical;
+use crate::spherical::{CameraModel, SphereProjection};
 use super::{Renderer,render_states};
 
+/// The output alpha of rendered photos
+#[derive(Copy, Clone)]
+pub(in super) enum PhotoAlpha {
+    Constant(f32),
+    /// distance to the nearest image edge, in texture coords (see the photo shaders' `edge_weight` uniform)
+    ///
+    /// (texture_dewarp.frag always uses 0.5)
+    EdgeDistance,
+}
+
 impl Renderer<'_> {
 
-    pub(in super) fn render_photos(&self, photo_alpha: f32, render_states: RenderStates) -> Result<(), Error> {
+    pub(in super) fn render_photos(&self, photo_alpha: PhotoAlpha, render_states: RenderStates) -> Result<(), Error> {
+
+        for m in self.entities.photos.iter().filter(|m| m.visible) {
+            self.render_photo(m, photo_alpha, render_states)?;
+        }
 
-        for m in &self.entities.photos {
-            let program = match self.control_state.dewarp_shader
-            {
-                DewarpShader::NoMorph => &self.texture_program,
-                DewarpShader::Dewarp1 => &self.texture_dewarp_program,
-                DewarpShader::Dewarp2 => &self.texture_dewarp2_program,
-            };
+        Ok(())
+    }
 
-            program.use_texture(&m.loaded_image_mesh.texture_2d, "tex").unwrap();
-            program.use_uniform_float("out_alpha", &photo_alpha).unwrap();
+    fn render_photo(&self, m: &Photo, photo_alpha: PhotoAlpha, render_states: RenderStates) -> Result<(), Error> {
 
-            let mut mesh = m.loaded_image_mesh.mesh.clone();
-            mesh.transformation = m.orientation().to_world();
-            mesh.render(program, render_states, self.frame_input.viewport, &self.camera)?;
+        if let CameraModel::Spherical(projection) = &self.entities.camera_model {
+            return self.render_spherical_photo(m, projection, photo_alpha, render_states);
         }
 
-        Ok(())
+        let program = match self.control_state.dewarp_shader
+        {
+            DewarpShader::NoMorph => &self.texture_program,
+            DewarpShader::Dewarp1 => &self.texture_dewarp_program,
+            DewarpShader::Dewarp2 => &self.texture_dewarp2_program,
+        };
+
+        program.use_texture(&m.loaded_image_mesh.texture_2d, "tex").unwrap();
+
+        if self.control_state.dewarp_shader != DewarpShader::Dewarp1 {
+            Self::use_alpha_uniforms(program, photo_alpha)?;
+        }
+
+        Self::use_color_uniforms(program, m, self.entities.vignetting(m.lens_id))?;
+
+        if self.control_state.dewarp_shader == DewarpShader::Dewarp2 {
+            Self::use_lens_uniforms(program, m)?;
+        }
+
+        let mut mesh = m.loaded_image_mesh.mesh.clone();
+        mesh.transformation = m.orientation().to_world();
+        mesh.render(program, render_states, self.frame_input.viewport, &self.camera)
     }
 
-    pub(in super) fn render_photos_with_pixel_averaging(&self) -> Result<(), Error> {
+    /// renders `photo` with texture_spherical.frag, on a rectangle containing it in `projection`
+    ///
+    /// (lens distortion is always corrected)
+    fn render_spherical_photo(&self, photo: &Photo, projection: &SphereProjection, photo_alpha: PhotoAlpha, render_states: RenderStates) -> Result<(), Error> {
+
+        let program = self.texture_spherical_program;
+
+        program.use_texture(&photo.loaded_image_mesh.texture_2d, "tex")?;
+        Self::use_alpha_uniforms(program, photo_alpha)?;
+        Self::use_color_uniforms(program, photo, self.entities.vignetting(photo.lens_id))?;
+        program.use_uniform_float("projection_scale", &(projection.scale as f32))?;
+        program.use_uniform_int("projection", &(projection.kind.pto_projection() as i32))?;
+
+        //the transpose of camera to world
+        let rotation = photo.pose.rotation();
+        let row = |i: usize| Vec3::new(rotation[i][0] as f32, rotation[i][1] as f32, rotation[i][2] as f32);
+        program.use_uniform_mat3("world_to_camera", &Mat3::from_cols(row(0), row(1), row(2)))?;
+
+        program.use_uniform_float("focal_length", &(spherical::focal_length(photo.image_width, photo.lens.fov) as f32))?;
+        program.use_uniform_vec2("image_size", &Vec2::new(photo.image_width as f32, photo.image_height as f32))?;
+
+        //image orientations are affine in texture coords
+        let stored = |uv| photo.image_orientation.stored_texture_coords(uv);
+        let (origin, x, y) = (stored((0.0, 0.0)), stored((1.0, 0.0)), stored((0.0, 1.0)));
+        let upright_to_stored = Mat3::from_cols(
+            Vec3::new((x.0 - origin.0) as f32, (x.1 - origin.1) as f32, 0.0),
+            Vec3::new((y.0 - origin.0) as f32, (y.1 - origin.1) as f32, 0.0),
+            Vec3::new(origin.0 as f32, origin.1 as f32, 1.0),
+        );
+        program.use_uniform_mat3("upright_to_stored", &upright_to_stored)?;
+
+        Self::use_lens_uniforms(program, photo)?;
+
+        let (min, max) = photo.spherical_bounds(projection);
+
+        let mut mesh = photo.loaded_image_mesh.mesh.clone();
+        mesh.transformation =
+            Mat4::from_translation(Vec3::new(((min.x + max.x) / 2.0) as f32, ((min.y + max.y) / 2.0) as f32, 0.0)) *
+            Mat4::from_nonuniform_scale((max.x - min.x) as f32, (max.y - min.y) as f32, 1.0);
+        mesh.render(program, render_states, self.frame_input.viewport, self.camera)
+    }
+
+    /// sets the photo shaders' `out_alpha` and `edge_weight` uniforms
+    fn use_alpha_uniforms(program: &MeshProgram, photo_alpha: PhotoAlpha) -> Result<(), Error> {
+
+        let (out_alpha, edge_weight) = match photo_alpha {
+            PhotoAlpha::Constant(alpha) => (alpha, 0),
+            PhotoAlpha::EdgeDistance => (1.0, 1),
+        };
+
+        program.use_uniform_float("out_alpha", &out_alpha)?;
+        program.use_uniform_int("edge_weight", &edge_weight)
+    }
+
+    /// sets photo_common.glsl's `color_gain` and `vignetting` uniforms for `photo`, taken with a lens with `vignetting`
+    fn use_color_uniforms(program: &MeshProgram, photo: &Photo, vignetting: Vignetting) -> Result<(), Error> {
+
+        let [red, green, blue] = photo.color_correction.encoded_gains();
+        program.use_uniform_vec3("color_gain", &Vec3::new(red as f32, green as f32, blue as f32))?;
+
+        program.use_uniform_vec4("vignetting", &Vec4::new(vignetting.a as f32, vignetting.b as f32, vignetting.c as f32, vignetting.d as f32))
+    }
+
+    /// sets photo_common.glsl's lens uniforms for `photo`
+    ///
+    /// (the mesh's uvs are stored image texture coords, so the lens model uses stored image dimensions)
+    fn use_lens_uniforms(program: &MeshProgram, photo: &Photo) -> Result<(), Error> {
+
+        let lens = &photo.lens;
+        let (width, height) = photo.stored_dimensions();
+        let (shift_x, shift_y) = lens.center_shift(width, height);
+
+        program.use_uniform_float("aspect_x_to_y", &(width as f32 / height as f32))?;
+        program.use_uniform_float("lens_a", &(lens.a as f32))?;
+        program.use_uniform_float("lens_b", &(lens.b as f32))?;
+        program.use_uniform_float("lens_c", &(lens.c as f32))?;
+        program.use_uniform_vec2("lens_center_shift", &Vec2::new(shift_x as f32, shift_y as f32))
+    }
+
+    /// averages overlapping photos, weighted by `photo_alpha`
+    pub(in super) fn render_photos_with_pixel_averaging(&self, photo_alpha: PhotoAlpha) -> Result<(), Error> {
 
         use three_d::definition::{Interpolation, Wrapping, Format};
 
@@ -58,7 +170,7 @@ impl Renderer<'_> {
         {
             tmp_texture.write(ClearState::color(0.0, 0.0, 0.0, 0.0), || {
 
-                self.render_photos(1.0, render_states::render_states_accumulate())
+                self.render_photos(photo_alpha, render_states::render_states_weighted_accumulate())
 
             }).unwrap();
 
@@ -76,4 +188,102 @@ impl Renderer<'_> {
 
         })
     }
+    /// renders photos with multi-band blending (see blend::MultiBandBlender, its CPU equivalent):
+    /// each pixel's high frequencies come from the photo where it's farthest from the image edges,
+    /// and lower frequencies are blended over progressively wider transitions
+    pub(in super) fn render_photos_with_multiband_blending(&self) -> Result<(), Error> {
+
+        let effects = &self.entities.multiband_effects;
+        let viewport = self.frame_input.viewport;
+        let clear = ClearState::color(0.0, 0.0, 0.0, 0.0);
+
+        let textures = effects.textures(self.context, viewport.width, viewport.height)?;
+        let coarsest = textures.color.len() - 1;
+
+        textures.edge.write(clear, || {
+            self.render_photos(PhotoAlpha::EdgeDistance, render_states::render_states_max())
+        })?;
+
+        //the other textures are overwritten, except the sums
+        for texture in &textures.accumulated {
+            texture.write(clear, || Ok(()))?;
+        }
+
+        for photo in self.entities.photos.iter().filter(|m| m.visible) {
+
+            textures.photo.write(clear, || {
+                self.render_photo(photo, PhotoAlpha::EdgeDistance, render_states::render_states_no_blend())
+            })?;
+
+            for &(pyramid, output_weight) in &[(&textures.color, 0), (&textures.weight, 1)] {
+
+                effects.prepare.use_uniform_int("output_weight", &output_weight)?;
+                Self::apply_effect(&effects.prepare, &[(&textures.photo, "photoMap"), (&textures.edge, "edgeMap")], &pyramid[0], render_states::render_states_no_blend())?;
+
+                for k in 0..coarsest {
+                    Self::apply_effect(&effects.reduce, &[(&pyramid[k], "colorMap")], &pyramid[k + 1], render_states::render_states_no_blend())?;
+                }
+            }
+
+            for k in (0..=coarsest).rev() {
+
+                if k < coarsest {
+                    Self::apply_effect(&effects.expand, &[(&textures.filled[k + 1], "colorMap")], &textures.expanded[k], render_states::render_states_no_blend())?;
+                }
+
+                Self::apply_effect(
+                    &effects.fill,
+                    &[(&textures.color[k], "levelMap"), (&textures.expanded[k], "expandedMap")],
+                    &textures.filled[k],
+                    render_states::render_states_no_blend()
+                )?;
+
+                Self::apply_effect(
+                    &effects.accumulate,
+                    &[(&textures.filled[k], "filledMap"), (&textures.expanded[k], "expandedMap"), (&textures.weight[k], "weightMap")],
+                    &textures.accumulated[k],
+                    render_states::render_states_accumulate()
+                )?;
+            }
+        }
+
+        //collapse the blended pyramid
+        for k in (0..=coarsest).rev() {
+
+            if k < coarsest {
+                Self::apply_effect(&effects.expand, &[(&textures.collapsed[k + 1], "colorMap")], &textures.expanded[k], render_states::render_states_no_blend())?;
+            }
+
+            Self::apply_effect(
+                &effects.collapse,
+                &[(&textures.accumulated[k], "accumulatedMap"), (&textures.expanded[k], "expandedMap")],
+                &textures.collapsed[k],
+                render_states::render_states_no_blend()
+            )?;
+        }
+
+        Screen::write(self.context, ClearState::none(), || {
+
+            self.entities.copy_photos_effect.use_texture(&textures.collapsed[0], "colorMap")?;
+            self.entities.copy_photos_effect.apply(render_states::render_states_transparency(), viewport)
+        })
+    }
+
+    /// applies `effect` to all of `target`, with `textures` bound to their sampler names
+    fn apply_effect(
+        effect: &ImageEffect,
+        textures: &[(&ColorTargetTexture2D<f32>, &str)],
+        target: &ColorTargetTexture2D<f32>,
+        render_states: RenderStates
+    ) -> Result<(), Error> {
+
+        target.write(ClearState::none(), || {
+
+            for &(texture, name) in textures {
+                effect.use_texture(texture, name)?;
+            }
+
+            effect.apply(render_states, Viewport::new_at_origo(target.width(), target.height()))
+        })
+    }
 }
\ No newline at end of file
@@ -32,6 +32,23 @@ pub fn render_states_accumulate() -> RenderStates {
     }
 }
 
+/// like render_states_accumulate, with source colors multiplied by source alpha
+pub fn render_states_weighted_accumulate() -> RenderStates {
+    RenderStates {
+        blend: Some(BlendParameters {
+            source_rgb_multiplier: BlendMultiplierType::SrcAlpha,
+            source_alpha_multiplier: BlendMultiplierType::One,
+            destination_rgb_multiplier: BlendMultiplierType::One,
+            destination_alpha_multiplier: BlendMultiplierType::One,
+            rgb_equation: BlendEquationType::Add,
+            alpha_equation: BlendEquationType::Add,
+        }),
+
+        write_mask: WriteMask::COLOR,
+        depth_test: DepthTestType::Always,
+    }
+}
+
 pub fn render_states_no_blend() -> RenderStates {
     RenderStates {
         blend: None,
@@ -39,3 +56,19 @@ pub fn render_states_no_blend() -> RenderStates {
         depth_test: DepthTestType::Always,
     }
 }
+
+pub fn render_states_max() -> RenderStates {
+    RenderStates {
+        blend: Some(BlendParameters {
+            source_rgb_multiplier: BlendMultiplierType::One,
+            source_alpha_multiplier: BlendMultiplierType::One,
+            destination_rgb_multiplier: BlendMultiplierType::One,
+            destination_alpha_multiplier: BlendMultiplierType::One,
+            rgb_equation: BlendEquationType::Max,
+            alpha_equation: BlendEquationType::Max,
+        }),
+
+        write_mask: WriteMask::COLOR,
+        depth_test: DepthTestType::Always,
+    }
+}
@@ -0,0 +1,170 @@
+use crate::control_points::{ControlPointStore, ImagePair};
+use crate::read_pto::{ControlPoint, ControlPointPair, ControlPointType};
+use crate::viewport_geometry::WorldCoords;
+
+/// The alignment error of one control point pair
+#[derive(Debug, PartialEq, Clone)]
+pub struct Residual {
+    pub pair: ControlPointPair,
+    /// each control point's location in its photo, in WorldCoords
+    pub point1: WorldCoords,
+    pub point2: WorldCoords,
+}
+
+impl Residual {
+
+    /// distance in WorldCoords units
+    pub fn distance(&self) -> f64 {
+
+        ((self.point2.x - self.point1.x).powi(2) + (self.point2.y - self.point1.y).powi(2)).sqrt()
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct ResidualStats {
+    pub count: usize,
+    pub rms: f64,
+    pub max: f64,
+}
+
+impl ResidualStats {
+
+    /// returns None if there are no residuals
+    fn from_residuals<'a>(residuals: impl Iterator<Item = &'a Residual>) -> Option<Self> {
+
+        let distances: Vec<f64> = residuals.map(Residual::distance).collect();
+
+        if distances.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            count: distances.len(),
+            rms: (distances.iter().map(|d| d * d).sum::<f64>() / distances.len() as f64).sqrt(),
+            max: distances.iter().cloned().fold(0.0, f64::max),
+        })
+    }
+}
+
+/// Residuals of all normal control point pairs, with per image pair and overall statistics
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResidualReport {
+    pub residuals: Vec<Residual>,
+    pub image_pairs: Vec<(ImagePair, ResidualStats)>,
+    pub overall: Option<ResidualStats>,
+}
+
+impl ResidualReport {
+
+    /// `world_coords` maps a control point to WorldCoords (None if its photo doesn't exist)
+    pub fn new(control_points: &ControlPointStore, world_coords: impl Fn(&ControlPoint) -> Option<WorldCoords>) -> Self {
+
+        let mut residuals = Vec::new();
+        let mut image_pairs = Vec::new();
+
+        for (image_pair, pairs) in control_points.iter() {
+
+            let pair_residuals: Vec<Residual> =
+            pairs.iter().filter(|pair| pair.point_type == ControlPointType::Normal).filter_map(|pair| {
+                Some(Residual {
+                    pair: pair.clone(),
+                    point1: world_coords(&pair.cp1)?,
+                    point2: world_coords(&pair.cp2)?,
+                })
+            }).collect();
+
+            if let Some(stats) = ResidualStats::from_residuals(pair_residuals.iter()) {
+                image_pairs.push((*image_pair, stats));
+            }
+
+            residuals.extend(pair_residuals);
+        }
+
+        let overall = ResidualStats::from_residuals(residuals.iter());
+
+        Self {
+            residuals,
+            image_pairs,
+            overall,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+    use crate::image_orientation::ImageOrientation;
+    use crate::lens::LensParameters;
+    use crate::photo::Photo;
+    use crate::viewport_geometry::PixelCoords;
+    use crate::world_rectangle::WorldRectangle;
+
+    fn pair(image_a: u64, (x1, y1): (f64, f64), image_b: u64, (x2, y2): (f64, f64), point_type: ControlPointType) -> ControlPointPair {
+
+        ControlPointPair {
+            cp1: ControlPoint::new(image_a, x1, y1),
+            cp2: ControlPoint::new(image_b, x2, y2),
+            point_type,
+        }
+    }
+
+    #[test]
+    fn residual_report_test() {
+
+        //photo 1 is 100 units right of photo 0
+        let mut orientations = [WorldRectangle::new(200.0, 100.0), WorldRectangle::new(200.0, 100.0)];
+        orientations[1].set_translation(WorldCoords{x: 100.0, y: 0.0});
+
+        let world_coords = |cp: &ControlPoint| {
+            orientations.get(cp.image_id as usize).map(|orientation| {
+                Photo::world_coords_impl(orientation, 200, 100, ImageOrientation::Normal, &LensParameters::default(), PixelCoords{x: cp.x_coord, y: cp.y_coord})
+            })
+        };
+
+        let store = ControlPointStore::from_pairs(vec![
+            //exact
+            pair(0, (150.0, 50.0), 1, (50.0, 50.0), ControlPointType::Normal),
+            //3 units right, 4 units down
+            pair(0, (150.0, 50.0), 1, (53.0, 54.0), ControlPointType::Normal),
+            //ignored: line type
+            pair(0, (0.0, 0.0), 0, (0.0, 100.0), ControlPointType::VerticalLine),
+            //ignored: photo 2 doesn't exist
+            pair(1, (0.0, 0.0), 2, (0.0, 0.0), ControlPointType::Normal),
+            //1 unit
+            pair(1, (10.0, 10.0), 1, (11.0, 10.0), ControlPointType::Normal),
+        ]);
+
+        let report = ResidualReport::new(&store, world_coords);
+
+        assert_eq!(report.residuals.len(), 3);
+        assert_approx_eq!(report.residuals[0].distance(), 0.0);
+        assert_approx_eq!(report.residuals[1].distance(), 5.0);
+        assert_eq!(report.residuals[1].point1, WorldCoords{x: 50.0, y: 0.0});
+        assert_eq!(report.residuals[1].point2, WorldCoords{x: 53.0, y: -4.0});
+
+        assert_eq!(report.image_pairs.len(), 2);
+
+        let (image_pair, stats) = report.image_pairs[0];
+        assert_eq!(image_pair, ImagePair::new(0, 1));
+        assert_eq!(stats.count, 2);
+        assert_approx_eq!(stats.rms, (25.0_f64 / 2.0).sqrt());
+        assert_approx_eq!(stats.max, 5.0);
+
+        let (image_pair, stats) = report.image_pairs[1];
+        assert_eq!(image_pair, ImagePair::new(1, 1));
+        assert_eq!(stats.count, 1);
+
+        let overall = report.overall.unwrap();
+        assert_eq!(overall.count, 3);
+        assert_approx_eq!(overall.rms, (26.0_f64 / 3.0).sqrt());
+        assert_approx_eq!(overall.max, 5.0);
+
+        let empty = ResidualReport::new(&ControlPointStore::default(), world_coords);
+        assert!(empty.residuals.is_empty());
+        assert!(empty.image_pairs.is_empty());
+        assert_eq!(empty.overall, None);
+    }
+}
@@ -0,0 +1,426 @@
+use std::cmp::Ordering;
+
+use serde::{Serialize, Deserialize};
+
+use crate::viewport_geometry::WorldCoords;
+
+/// the most cells on a side of a seam grid (see SeamMap::with_max_cells)
+pub const MAX_SEAM_GRID_SIDE: usize = 1024;
+
+/// seam cost of one unit of distance difference from the photos' centroids (see refine_seam):
+/// with no color differences, seams stay near the Voronoi boundary
+const DISTANCE_COST: f32 = 1e-3;
+
+/// Assigns each world location where photos overlap to a single photo
+///
+/// seams are the boundaries between cells of different photos
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SeamMap {
+    /// the top left corner of the top left cell
+    pub top_left: WorldCoords,
+    /// WorldCoords units per cell side
+    pub cell_size: f64,
+    pub width: usize,
+    pub height: usize,
+    /// per cell, by rows (row 0 at the top): the index of the photo shown there
+    pub labels: Vec<Option<usize>>,
+}
+
+impl SeamMap {
+
+    /// an unlabeled grid covering the rectangle from `min` to `max`, at most MAX_SEAM_GRID_SIDE cells on a side
+    /// (and cells are at least 1 WorldCoords unit: about a source image pixel)
+    pub fn with_max_cells(min: WorldCoords, max: WorldCoords) -> Self {
+
+        let longest_side = (max.x - min.x).max(max.y - min.y);
+        let cell_size = (longest_side / MAX_SEAM_GRID_SIDE as f64).max(1.0);
+
+        let width = (((max.x - min.x) / cell_size).ceil() as usize).clamp(1, MAX_SEAM_GRID_SIDE);
+        let height = (((max.y - min.y) / cell_size).ceil() as usize).clamp(1, MAX_SEAM_GRID_SIDE);
+
+        Self {
+            top_left: WorldCoords{x: min.x, y: max.y},
+            cell_size,
+            width,
+            height,
+            labels: vec![None; width * height],
+        }
+    }
+
+    /// the center of cell (x, y)
+    pub fn cell_center(&self, x: usize, y: usize) -> WorldCoords {
+
+        WorldCoords {
+            x: self.top_left.x + (x as f64 + 0.5) * self.cell_size,
+            y: self.top_left.y - (y as f64 + 0.5) * self.cell_size,
+        }
+    }
+
+    /// the cell containing `point`
+    pub fn cell(&self, point: WorldCoords) -> Option<(usize, usize)> {
+
+        let x = ((point.x - self.top_left.x) / self.cell_size).floor();
+        let y = ((self.top_left.y - point.y) / self.cell_size).floor();
+
+        if x < 0.0 || y < 0.0 || x >= self.width as f64 || y >= self.height as f64 {
+            return None;
+        }
+
+        Some((x as usize, y as usize))
+    }
+
+    /// the photo shown at `point`
+    pub fn label(&self, point: WorldCoords) -> Option<usize> {
+
+        self.cell(point).and_then(|(x, y)| self.labels[y * self.width + x])
+    }
+
+    /// assigns cells within `radius` of `center` to `photo_index`, where `covers` (the photo) contains their centers;
+    /// returns true if any cells changed
+    pub fn paint(&mut self, center: WorldCoords, radius: f64, photo_index: usize, covers: impl Fn(WorldCoords) -> bool) -> bool {
+
+        let cells = |min: f64, max: f64, limit: usize| {
+            let min = (min / self.cell_size).floor().max(0.0) as usize;
+            let max = ((max / self.cell_size).ceil().max(0.0) as usize).min(limit);
+            min..max
+        };
+
+        let x_range = cells(center.x - radius - self.top_left.x, center.x + radius - self.top_left.x, self.width);
+        let y_range = cells(self.top_left.y - center.y - radius, self.top_left.y - center.y + radius, self.height);
+
+        let mut changed = false;
+
+        for y in y_range {
+            for x in x_range.clone() {
+                let point = self.cell_center(x, y);
+                let label = &mut self.labels[y * self.width + x];
+
+                if *label != Some(photo_index) && label.is_some() &&
+                   (point.x - center.x).hypot(point.y - center.y) <= radius && covers(point) {
+                    *label = Some(photo_index);
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// replaces each cell's photo index with `remap` of it (None: the cell is unlabeled)
+    pub fn remap_labels(&mut self, remap: impl Fn(usize) -> Option<usize>) {
+
+        for label in &mut self.labels {
+            *label = label.and_then(&remap);
+        }
+    }
+
+    /// the seams: line segments between neighboring cells of different photos
+    pub fn boundaries(&self) -> Vec<(WorldCoords, WorldCoords)> {
+
+        let label = |x: usize, y: usize| self.labels[y * self.width + x];
+        let is_seam = |a: Option<usize>, b: Option<usize>| a.is_some() && b.is_some() && a != b;
+
+        //cell corner (x, y) in WorldCoords
+        let corner = |x: usize, y: usize| WorldCoords {
+            x: self.top_left.x + x as f64 * self.cell_size,
+            y: self.top_left.y - y as f64 * self.cell_size,
+        };
+
+        let mut segments = Vec::new();
+
+        //vertical segments, joined down each column of cell edges
+        for x in 1..self.width {
+            let mut start = None;
+            for y in 0..=self.height {
+                let seam = y < self.height && is_seam(label(x - 1, y), label(x, y));
+                match (seam, start) {
+                    (true, None) => start = Some(y),
+                    (false, Some(y0)) => {
+                        segments.push((corner(x, y0), corner(x, y)));
+                        start = None;
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        //horizontal segments, joined along each row of cell edges
+        for y in 1..self.height {
+            let mut start = None;
+            for x in 0..=self.width {
+                let seam = x < self.width && is_seam(label(x, y - 1), label(x, y));
+                match (seam, start) {
+                    (true, None) => start = Some(x),
+                    (false, Some(x0)) => {
+                        segments.push((corner(x0, y), corner(x, y)));
+                        start = None;
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        segments
+    }
+
+    /// labels every cell from photo colors at the cell centers (see find_seams)
+    pub fn find_seams(&mut self, colors: &[Vec<Option<[f32; 3]>>]) {
+
+        self.labels = find_seams(self.width, self.height, colors);
+    }
+}
+
+/// assigns each cell of a `width` x `height` grid to one of the photos covering it:
+/// * initially, to the photo with the nearest centroid (a Voronoi diagram)
+/// * then each seam between two photos is moved to where they differ least (see refine_seam)
+///
+/// `colors`: per photo, per cell (by rows): RGB, if the photo covers the cell
+pub fn find_seams(width: usize, height: usize, colors: &[Vec<Option<[f32; 3]>>]) -> Vec<Option<usize>> {
+
+    let centroids: Vec<Option<(f32, f32)>> = colors.iter().map(|photo| {
+        let (mut sum_x, mut sum_y, mut count) = (0.0, 0.0, 0);
+
+        for (index, _) in photo.iter().enumerate().filter(|(_, color)| color.is_some()) {
+            sum_x += (index % width) as f32;
+            sum_y += (index / width) as f32;
+            count += 1;
+        }
+
+        (count > 0).then(|| (sum_x / count as f32, sum_y / count as f32))
+    }).collect();
+
+    let distance = |photo: usize, index: usize| {
+        let (cx, cy) = centroids[photo].unwrap();
+        let (dx, dy) = ((index % width) as f32 - cx, (index / width) as f32 - cy);
+        (dx * dx + dy * dy).sqrt()
+    };
+
+    let mut labels: Vec<Option<usize>> = (0..width * height).map(|index| {
+        (0..colors.len())
+            .filter(|&photo| colors[photo][index].is_some())
+            .min_by(|&a, &b| distance(a, index).partial_cmp(&distance(b, index)).unwrap_or(Ordering::Equal))
+    }).collect();
+
+    //refine each pair of photos with a shared seam
+    for a in 0..colors.len() {
+        for b in a + 1..colors.len() {
+
+            let pair = [Some(a), Some(b)];
+            let adjacent = (0..labels.len()).any(|index| {
+                let (x, y) = (index % width, index / width);
+                let neighbor = |other: usize| pair.contains(&labels[index]) && pair.contains(&labels[other]) && labels[index] != labels[other];
+
+                (x + 1 < width && neighbor(index + 1)) || (y + 1 < height && neighbor(index + width))
+            });
+
+            if adjacent {
+                refine_seam(width, height, colors, &centroids, (a, b), &mut labels);
+            }
+        }
+    }
+
+    labels
+}
+
+/// per seam grid cell in an overlap: (the minimum total cost of a seam ending there, its previous row's j)
+type SeamPath = Option<(f32, Option<usize>)>;
+
+/// moves the seam between photos `a` and `b` to a minimum cost path across their overlap (by dynamic programming):
+/// a cell's cost is the color difference between the photos there,
+/// plus DISTANCE_COST times the difference of its distances to their centroids
+///
+/// the seam runs perpendicular to the line between the photos' centroids,
+/// and moves by at most one cell sideways per cell along it
+fn refine_seam(
+    width: usize,
+    height: usize,
+    colors: &[Vec<Option<[f32; 3]>>],
+    centroids: &[Option<(f32, f32)>],
+    (a, b): (usize, usize),
+    labels: &mut [Option<usize>],
+) {
+    let ((ax, ay), (bx, by)) = match (centroids[a], centroids[b]) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return,
+    };
+
+    //seam grid coords (i along the seam, j across it) -> cell index
+    let vertical = (bx - ax).abs() >= (by - ay).abs();
+    let (rows, columns) = if vertical {(height, width)} else {(width, height)};
+    let cell = |i: usize, j: usize| if vertical {i * width + j} else {j * width + i};
+
+    //the photo on the low j side
+    let (first, second) = match vertical {
+        true if ax <= bx => (a, b),
+        false if ay <= by => (a, b),
+        _ => (b, a),
+    };
+
+    let in_overlap: Vec<bool> = (0..labels.len()).map(|index| {
+        colors[a][index].is_some() && colors[b][index].is_some() &&
+        (labels[index] == Some(a) || labels[index] == Some(b))
+    }).collect();
+
+    let cost = |index: usize| {
+        let (color_a, color_b) = (colors[a][index].unwrap(), colors[b][index].unwrap());
+        let difference: f32 = color_a.iter().zip(color_b.iter()).map(|(p, q)| (p - q).abs()).sum();
+
+        let (x, y) = ((index % width) as f32, (index / width) as f32);
+        let distance = |(cx, cy): (f32, f32)| ((x - cx).powi(2) + (y - cy).powi(2)).sqrt();
+
+        difference + DISTANCE_COST * (distance((ax, ay)) - distance((bx, by))).abs()
+    };
+
+    let mut paths: Vec<Vec<SeamPath>> = Vec::with_capacity(rows);
+
+    for i in 0..rows {
+        let row: Vec<SeamPath> = (0..columns).map(|j| {
+
+            let index = cell(i, j);
+            if !in_overlap[index] {
+                return None;
+            }
+
+            let previous = i.checked_sub(1).and_then(|previous_i| {
+                (j.saturating_sub(1)..=(j + 1).min(columns - 1))
+                    .filter_map(|previous_j| paths[previous_i][previous_j].map(|(total, _)| (total, previous_j)))
+                    .min_by(|p, q| p.0.partial_cmp(&q.0).unwrap_or(Ordering::Equal))
+            });
+
+            Some(match previous {
+                Some((total, previous_j)) => (total + cost(index), Some(previous_j)),
+                None => (cost(index), None),
+            })
+        }).collect();
+
+        paths.push(row);
+    }
+
+    let row_minimum = |row: &[SeamPath]| {
+        row.iter().enumerate()
+            .filter_map(|(j, path)| path.map(|(total, _)| (total, j)))
+            .min_by(|p, q| p.0.partial_cmp(&q.0).unwrap_or(Ordering::Equal))
+            .map(|(_, j)| j)
+    };
+
+    //trace back from the end of each disconnected part of the overlap
+    let mut seam: Vec<Option<usize>> = vec![None; rows];
+    let mut next: Option<usize> = None;
+
+    for i in (0..rows).rev() {
+        let j = next.or_else(|| row_minimum(&paths[i]));
+        seam[i] = j;
+        next = j.and_then(|j| paths[i][j].and_then(|(_, previous_j)| previous_j));
+    }
+
+    for (i, seam_j) in seam.into_iter().enumerate() {
+        if let Some(seam_j) = seam_j {
+            for j in 0..columns {
+                let index = cell(i, j);
+                if in_overlap[index] {
+                    labels[index] = Some(if j <= seam_j {first} else {second});
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    /// two photos on a `width` x `height` grid: left covers columns [0, 24), right covers [8, 32)
+    fn overlapping_photos(width: usize, height: usize, left: [f32; 3], right: [f32; 3]) -> Vec<Vec<Option<[f32; 3]>>> {
+
+        let photo = |columns: std::ops::Range<usize>, color: [f32; 3]| {
+            (0..width * height).map(|index| columns.contains(&(index % width)).then(|| color)).collect()
+        };
+
+        vec![photo(0..24, left), photo(8..32, right)]
+    }
+
+    #[test]
+    fn seam_map_test() {
+
+        let mut map = SeamMap::with_max_cells(WorldCoords{x: -10.0, y: 0.0}, WorldCoords{x: 2038.0, y: 512.0});
+        assert_eq!((map.width, map.height), (1024, 256));
+        assert_approx_eq!(map.cell_size, 2.0);
+
+        assert_eq!(map.cell(WorldCoords{x: -9.0, y: 511.0}), Some((0, 0)));
+        assert_eq!(map.cell(WorldCoords{x: 2037.0, y: 1.0}), Some((1023, 255)));
+        assert_eq!(map.cell(WorldCoords{x: -11.0, y: 1.0}), None);
+        assert_eq!(map.cell(WorldCoords{x: 0.0, y: 513.0}), None);
+
+        let center = map.cell_center(3, 4);
+        assert_eq!(map.cell(center), Some((3, 4)));
+
+        //painting: only labeled cells the photo covers
+        map.labels[4 * map.width + 3] = Some(0);
+        map.labels[4 * map.width + 4] = Some(0);
+        map.labels[4 * map.width + 5] = Some(0);
+        assert!(map.paint(center, 2.5, 1, |point| point.x < center.x + 1.0));
+        assert_eq!(map.label(center), Some(1));
+        assert_eq!(&map.labels[4 * map.width + 2..4 * map.width + 6], &[None, Some(1), Some(0), Some(0)]);
+
+        //out of range
+        assert!(!map.paint(WorldCoords{x: -100.0, y: -100.0}, 2.5, 1, |_| true));
+
+        //remapping: photo 0 removed, photo 1 moved to index 0
+        map.remap_labels(|index| if index == 1 {Some(0)} else {None});
+        assert_eq!(&map.labels[4 * map.width + 2..4 * map.width + 6], &[None, Some(0), None, None]);
+    }
+
+    #[test]
+    fn boundaries_test() {
+
+        let mut map = SeamMap::with_max_cells(WorldCoords{x: 0.0, y: 0.0}, WorldCoords{x: 4.0, y: 4.0});
+        assert_eq!((map.width, map.height, map.cell_size), (4, 4, 1.0));
+
+        //left half: photo 0, right half: photo 1, bottom row: none
+        map.labels = (0..16).map(|index| match (index % 4, index / 4) {
+            (_, 3) => None,
+            (x, _) if x < 2 => Some(0),
+            _ => Some(1),
+        }).collect();
+
+        assert_eq!(map.boundaries(), vec![(WorldCoords{x: 2.0, y: 4.0}, WorldCoords{x: 2.0, y: 1.0})]);
+    }
+
+    #[test]
+    fn find_seams_test() {
+
+        let (width, height) = (32, 16);
+
+        //matching colors: the Voronoi seam, halfway between the centroids
+        let colors = overlapping_photos(width, height, [0.5; 3], [0.5; 3]);
+        let labels = find_seams(width, height, &colors);
+
+        for y in 0..height {
+            assert_eq!(labels[y * width], Some(0));
+            assert_eq!(labels[y * width + 15], Some(0));
+            assert_eq!(labels[y * width + 16], Some(1));
+            assert_eq!(labels[y * width + 31], Some(1));
+        }
+
+        //a moving object in the left photo, across the Voronoi seam: the seam goes around it
+        let mut colors = overlapping_photos(width, height, [0.5; 3], [0.5; 3]);
+        for y in 0..height {
+            for x in 13..19 {
+                colors[0][y * width + x] = Some([1.0, 0.0, 0.0]);
+            }
+        }
+
+        let labels = find_seams(width, height, &colors);
+
+        let object_labels: Vec<Option<usize>> = (0..height).flat_map(|y| (13..19).map(move |x| y * width + x)).map(|index| labels[index]).collect();
+        assert!(object_labels.iter().all(|&label| label == object_labels[0]));
+
+        //each cell is labeled with a photo covering it
+        for (index, label) in labels.iter().enumerate() {
+            assert!(colors[label.unwrap()][index].is_some());
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//multi-band blending: one photo's weighted Laplacian band, for additive blending
+//(see MultiBandBlender::add in blend.rs)
+
+//the filled level
+uniform sampler2D filledMap;
+
+//the coarser filled level, expanded (0 at the coarsest level)
+uniform sampler2D expandedMap;
+
+//the blend mask's Gaussian pyramid level
+uniform sampler2D weightMap;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+    ivec2 p = ivec2(gl_FragCoord.xy);
+
+    //this level's detail beyond the coarser level
+    vec3 band = texelFetch(filledMap, p, 0).rgb - texelFetch(expandedMap, p, 0).rgb;
+    float weight = texelFetch(weightMap, p, 0).a;
+
+    color = vec4(weight * band, weight);
+}
@@ -0,0 +1,28 @@
+//multi-band blending: normalizes a level of the blended pyramid and adds the coarser collapsed level
+//(see MultiBandBlender::result in blend.rs)
+
+//(weighted band sums, weight sums)
+uniform sampler2D accumulatedMap;
+
+//the coarser collapsed level, expanded (0 at the coarsest level)
+uniform sampler2D expandedMap;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+    const float MIN_PYRAMID_WEIGHT = 1e-4;
+
+    ivec2 p = ivec2(gl_FragCoord.xy);
+
+    vec4 accumulated = texelFetch(accumulatedMap, p, 0);
+    vec3 coarser = texelFetch(expandedMap, p, 0).rgb;
+
+    if (accumulated.a > MIN_PYRAMID_WEIGHT) {
+        color = vec4(accumulated.rgb / accumulated.a + coarser, 1.0);
+    }
+    else {
+        //not on any photo
+        color = vec4(coarser, 0.0);
+    }
+}
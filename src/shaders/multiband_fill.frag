@@ -0,0 +1,27 @@
+//multi-band blending: unpremultiplies a pyramid level of one photo;
+//beyond the photo's edges, colors are filled in from the coarser level
+//so its bands don't fade to black there (see MultiBandBlender::add in blend.rs)
+
+//(premultiplied color, coverage)
+uniform sampler2D levelMap;
+
+//the coarser filled level, expanded (0 at the coarsest level)
+uniform sampler2D expandedMap;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+    const float MIN_PYRAMID_WEIGHT = 1e-4;
+
+    ivec2 p = ivec2(gl_FragCoord.xy);
+
+    vec4 level = texelFetch(levelMap, p, 0);
+
+    if (level.a > MIN_PYRAMID_WEIGHT) {
+        color = vec4(level.rgb / level.a, 1.0);
+    }
+    else {
+        color = vec4(texelFetch(expandedMap, p, 0).rgb, 1.0);
+    }
+}
@@ -0,0 +1,29 @@
+//multi-band blending, level 0 of one photo's pyramids (see render_photos_with_multiband_blending)
+
+//the photo, rendered with edge distance alpha
+uniform sampler2D photoMap;
+
+//the largest edge distance of all photos
+uniform sampler2D edgeMap;
+
+//0: output (premultiplied color, coverage)
+//1: output the photo's blend mask: 1 where it's farthest from its image edges
+uniform int output_weight;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+    ivec2 p = ivec2(gl_FragCoord.xy);
+
+    vec4 photo = texelFetch(photoMap, p, 0);
+    float coverage = photo.a > 0.0 ? 1.0 : 0.0;
+
+    if (output_weight != 0) {
+        float weight = (coverage > 0.0 && photo.a >= texelFetch(edgeMap, p, 0).a) ? 1.0 : 0.0;
+        color = vec4(weight);
+    }
+    else {
+        color = vec4(photo.rgb * coverage, coverage);
+    }
+}
@@ -0,0 +1,82 @@
+//shared by the photo shaders: prepended to them when their programs are created (see main.rs)
+
+//RGB multipliers of this photo's colors (see ColorCorrection::encoded_gains)
+uniform vec3 color_gain;
+
+//the lens' vignetting polynomial (Va, Vb, Vc, Vd: see lens::Vignetting)
+uniform vec4 vignetting;
+
+//stored image width / height
+uniform float aspect_x_to_y;
+
+//ptlens/panotools-style polynomial distortion parameters
+uniform float lens_a;
+uniform float lens_b;
+uniform float lens_c;
+
+//lens center offset from the image center, in texture coordinates (y up)
+uniform vec2 lens_center_shift;
+
+//the distorted (source) texture coords sampled at undistorted stored image texture coords `uvs` (y up)
+vec2 distorted_texture_coords(vec2 uvs)
+{
+    vec2 image_center = vec2(0.5, 0.5) + lens_center_shift;
+
+    //image coordinates, relative to image center
+    vec2 image_coords = uvs - image_center;
+
+    //aspect ratio correction for radius calculation (in units of image height)
+    vec2 asp_coords = image_coords;
+         asp_coords.x *= aspect_x_to_y;
+
+    //radius (from image center), Undistorted
+    float rU = distance(vec2(0.0), asp_coords);
+
+    float a = lens_a;
+    float b = lens_b;
+    float c = lens_c;
+
+    //double the radius:
+    // ptlens/panotools-style algorithm expects texture range: |[-1,1]| = 2
+    // glsl uses |[0,1]| = 1
+    rU *= 2.0;
+
+    //radius 1.0 is half of the shorter image side
+    rU /= min(aspect_x_to_y, 1.0);
+
+
+    //radius (from image center), Distorted
+    float rD =
+    //ptlens/panotools-style polynomial distortion algorithm:
+    a * pow(rU,4.0) + b * pow(rU,3.0) + c * pow(rU,2.0) + (1.0 - a - b - c) * rU;
+
+
+    float ratio;
+    if (rU != 0.0) { ratio = rD / rU; }
+    else           { ratio = 0.0;     }
+
+    //distorted coordinates: apply new radius from center
+    return image_center + image_coords * ratio;
+}
+
+//`color` with this photo's color correction, and its vignetting corrected at stored image texture coords `uvs`
+//of a `tex_size` image: as Vignetting::radius and Vignetting::gain, in linear light (gamma 2.2, see exposure.rs)
+vec3 corrected_color(vec3 color, vec2 uvs, vec2 tex_size)
+{
+    vec2 centered = (uvs - 0.5) * tex_size;
+    float r2 = dot(centered, centered) / dot(0.5 * tex_size, 0.5 * tex_size);
+    float brightness = vignetting.x + r2 * (vignetting.y + r2 * (vignetting.z + r2 * vignetting.w));
+
+    return color * color_gain * pow(1.0 / max(brightness, 0.05), 1.0 / 2.2);
+}
+
+/*
+
+lens parameters are set per photo (see Photo::lens), e.g. imported from a lensfun database (see lensfun.rs):
+<distortion model="ptlens" focal="200" a="0.0019098468424889991" b="-0.0028266879132016103" c="0.009532148272374459"/>
+
+Rd = a * Ru^4 + b * Ru^3 + c * Ru^2 + (1 - a - b - c) * Ru
+"Ru is the radius of the undistorted pixel, Rd is the radius of the distorted pixel"
+
+"the largest circle that completely fits into an image is said to have radius=1.0"
+*/
@@ -0,0 +1,28 @@
+//doubles the size of a pyramid level, interpolating with the binomial kernel: the inverse of pyramid_reduce.frag
+//(see Plane::expand in blend.rs)
+
+uniform sampler2D colorMap;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+    const float kernel[5] = float[5](1.0/16.0, 4.0/16.0, 6.0/16.0, 4.0/16.0, 1.0/16.0);
+
+    ivec2 source_size = textureSize(colorMap, 0);
+    ivec2 p = ivec2(gl_FragCoord.xy);
+
+    color = vec4(0.0);
+
+    //each output pixel gets the kernel taps that land on source pixels
+    for (int j = -2; j <= 2; j++) {
+        if (((p.y - j) & 1) != 0) { continue; }
+
+        for (int i = -2; i <= 2; i++) {
+            if (((p.x - i) & 1) != 0) { continue; }
+
+            ivec2 source = clamp((p - ivec2(i, j)) / 2, ivec2(0), source_size - 1);
+            color += 4.0 * kernel[i + 2] * kernel[j + 2] * texelFetch(colorMap, source, 0);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//one level of a Gaussian pyramid: blurs with the binomial kernel, then halves the size
+//(see Plane::reduce in blend.rs)
+
+uniform sampler2D colorMap;
+
+layout (location = 0) out vec4 color;
+
+void main()
+{
+    const float kernel[5] = float[5](1.0/16.0, 4.0/16.0, 6.0/16.0, 4.0/16.0, 1.0/16.0);
+
+    ivec2 source_size = textureSize(colorMap, 0);
+    ivec2 center = 2 * ivec2(gl_FragCoord.xy);
+
+    color = vec4(0.0);
+
+    for (int j = -2; j <= 2; j++) {
+        for (int i = -2; i <= 2; i++) {
+            ivec2 source = clamp(center + ivec2(i, j), ivec2(0), source_size - 1);
+            color += kernel[i + 2] * kernel[j + 2] * texelFetch(colorMap, source, 0);
+        }
+    }
+}
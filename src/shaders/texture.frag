@@ -2,6 +2,9 @@
 uniform sampler2D tex;
 uniform float out_alpha;
 
+//if nonzero, output alpha is the distance to the nearest image edge, in texture coords (see blend::edge_distance)
+uniform int edge_weight;
+
 in vec3 pos;
 in vec2 uvs;
 
@@ -10,5 +13,12 @@ layout (location = 0) out vec4 outColor;
 void main()
 {
     outColor = texture(tex, vec2(uvs.x, 1.0 - uvs.y));
+
+    outColor.rgb = corrected_color(outColor.rgb, uvs, vec2(textureSize(tex, 0)));
     outColor.a = out_alpha;
-}
\ No newline at end of file
+
+    if (edge_weight != 0) {
+        vec2 edge = min(uvs, 1.0 - uvs);
+        outColor.a = max(min(edge.x, edge.y), 1e-6);
+    }
+}
@@ -12,7 +12,7 @@ void main()
     //float strength = 0.5;
     float zoom = 1.0;
 
-    float aspect_x_to_y = 3.0/2.0;
+    float aspect = 3.0/2.0;
 
     float image_center_x = 0.5;
     float image_center_y = 0.5;
@@ -21,7 +21,7 @@ void main()
     float newY = uvs.y - image_center_y;
 
     float X_asp = newX;
-    float Y_asp = newY / aspect_x_to_y;
+    float Y_asp = newY / aspect;
     float distance = sqrt(X_asp*X_asp + Y_asp*Y_asp);
 
     float r = distance * strength;
@@ -35,6 +35,8 @@ void main()
 
     outColor = texture(tex, vec2(sourceX, 1.0 - sourceY));
 
+    outColor.rgb = corrected_color(outColor.rgb, vec2(sourceX, sourceY), vec2(textureSize(tex, 0)));
+
     //debug rings
     if (distance > 0.2 && distance < 0.21) { outColor.xyz = vec3(0,0,0); }
     if (distance > 0.49 && distance < 0.5) { outColor.xyz = vec3(0,1,1); }
@@ -0,0 +1,103 @@
+uniform sampler2D tex;
+uniform float out_alpha;
+
+//if nonzero, output alpha is the distance to the nearest image edge, in texture coords (see blend::edge_distance)
+uniform int edge_weight;
+
+//spherical camera model (see spherical.rs)
+
+//WorldCoords units per radian at the projection's center
+uniform float projection_scale;
+
+//the projection's PTO 'p' line 'f' value (see ProjectionKind)
+uniform int projection;
+
+//world direction -> this photo's camera direction
+uniform mat3 world_to_camera;
+
+//focal length, in upright image pixels
+uniform float focal_length;
+
+//upright image width, height in pixels
+uniform vec2 image_size;
+
+//upright image texture coords -> stored image texture coords (y up)
+uniform mat3 upright_to_stored;
+
+in vec3 pos;
+
+layout (location = 0) out vec4 outColor;
+
+void main()
+{
+    const float PI = 3.14159265358979;
+
+    outColor = vec4(0.0, 0.0, 0.0, 0.0);
+
+    //WorldCoords -> world direction: as SphereProjection::direction
+    vec2 p = pos.xy / projection_scale;
+
+    vec3 world_direction;
+
+    if (projection == 0) {
+        //rectilinear
+        world_direction = normalize(vec3(p, 1.0));
+    }
+    else if (projection == 3) {
+        //fisheye (equidistant)
+        float angle = length(p);
+        if (angle > PI) { return; }
+
+        vec2 radial = angle > 0.0 ? p / angle : vec2(0.0);
+        world_direction = vec3(sin(angle) * radial, cos(angle));
+    }
+    else if (projection == 4) {
+        //stereographic
+        float r2 = dot(p, p);
+        world_direction = normalize(vec3(4.0 * p, 4.0 - r2));
+    }
+    else {
+        //cylindrical projections: longitude, latitude
+        float lon = p.x;
+        float lat;
+
+        if      (projection == 1) { lat = atan(p.y); }
+        else if (projection == 5) { lat = atan(sinh(p.y)); }
+        else                      { lat = p.y; }
+
+        if (abs(lon) > PI || abs(lat) > PI / 2.0) { return; }
+
+        world_direction = vec3(
+            cos(lat) * sin(lon),
+            sin(lat),
+            cos(lat) * cos(lon)
+        );
+    }
+
+    vec3 camera_direction = world_to_camera * world_direction;
+
+    //behind the camera
+    if (camera_direction.z <= 0.0) { return; }
+
+    //rectilinear image plane: upright (undistorted) texture coords
+    vec2 upright = camera_direction.xy / camera_direction.z * focal_length / image_size + vec2(0.5, 0.5);
+
+    vec2 uvs = (upright_to_stored * vec3(upright, 1.0)).xy;
+
+    vec2 distorted = distorted_texture_coords(uvs);
+
+    //don't render texture samples from outside the image borders
+    if (distorted.x < 0.0 || distorted.x > 1.0) { return; }
+    if (distorted.y < 0.0 || distorted.y > 1.0) { return; }
+
+    //sample texture (flip y-coord)
+    outColor = texture(tex, vec2(distorted.x, 1.0 - distorted.y));
+
+    outColor.rgb = corrected_color(outColor.rgb, distorted, vec2(textureSize(tex, 0)));
+    outColor.a = out_alpha;
+
+    if (edge_weight != 0) {
+        vec2 edge = min(distorted, 1.0 - distorted);
+        outColor.a = max(min(edge.x, edge.y), 1e-6);
+    }
+}
@@ -0,0 +1,493 @@
+use std::f64::consts::{PI, FRAC_PI_2};
+
+use serde::{Serialize, Deserialize};
+
+use crate::viewport_geometry::WorldCoords;
+
+/// horizontal field of view used for photos without a valid one, in degrees
+pub const DEFAULT_FOV: f64 = 50.0;
+
+/// A unit direction from the panorama center: x right, y up, z forward (yaw 0, pitch 0)
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Direction {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Direction {
+
+    /// normalizes (x, y, z): returns None for the zero vector
+    pub fn new(x: f64, y: f64, z: f64) -> Option<Self> {
+
+        let length = (x * x + y * y + z * z).sqrt();
+
+        if length == 0.0 || !length.is_finite() {
+            return None;
+        }
+
+        Some(Self{x: x / length, y: y / length, z: z / length})
+    }
+
+    /// the angle between two directions, in radians
+    pub fn angle_to(&self, other: &Direction) -> f64 {
+
+        //chord length is more accurate than acos for small angles
+        let chord = ((self.x - other.x).powi(2) + (self.y - other.y).powi(2) + (self.z - other.z).powi(2)).sqrt();
+        2.0 * (chord / 2.0).min(1.0).asin()
+    }
+
+    /// (yaw, pitch) in degrees: as CameraPose, for a camera pointing in this direction
+    pub fn yaw_pitch(&self) -> (f64, f64) {
+
+        (self.x.atan2(self.z).to_degrees(), self.y.clamp(-1.0, 1.0).asin().to_degrees())
+    }
+}
+
+/// A photo's camera orientation on the panorama sphere, as in PTO `i` lines (degrees):
+/// * yaw: positive = right
+/// * pitch: positive = up
+/// * roll: positive = clockwise
+#[derive(Debug, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub yaw: f64,
+    pub pitch: f64,
+    pub roll: f64,
+}
+
+impl CameraPose {
+
+    /// the camera to world rotation matrix (row major): yaw * pitch * roll
+    pub fn rotation(&self) -> [[f64; 3]; 3] {
+
+        let (sin_y, cos_y) = self.yaw.to_radians().sin_cos();
+        let (sin_p, cos_p) = self.pitch.to_radians().sin_cos();
+        let (sin_r, cos_r) = self.roll.to_radians().sin_cos();
+
+        let yaw = [[cos_y, 0.0, sin_y], [0.0, 1.0, 0.0], [-sin_y, 0.0, cos_y]];
+        let pitch = [[1.0, 0.0, 0.0], [0.0, cos_p, sin_p], [0.0, -sin_p, cos_p]];
+        let roll = [[cos_r, sin_r, 0.0], [-sin_r, cos_r, 0.0], [0.0, 0.0, 1.0]];
+
+        multiply(&multiply(&yaw, &pitch), &roll)
+    }
+
+    /// maps a direction in this camera's frame to the world frame
+    pub fn world_direction(&self, camera: Direction) -> Direction {
+
+        let m = self.rotation();
+        let Direction{x, y, z} = camera;
+
+        Direction {
+            x: m[0][0] * x + m[0][1] * y + m[0][2] * z,
+            y: m[1][0] * x + m[1][1] * y + m[1][2] * z,
+            z: m[2][0] * x + m[2][1] * y + m[2][2] * z,
+        }
+    }
+
+    /// maps a world direction to this camera's frame
+    pub fn camera_direction(&self, world: Direction) -> Direction {
+
+        //the inverse of a rotation is its transpose
+        let m = self.rotation();
+        let Direction{x, y, z} = world;
+
+        Direction {
+            x: m[0][0] * x + m[1][0] * y + m[2][0] * z,
+            y: m[0][1] * x + m[1][1] * y + m[2][1] * z,
+            z: m[0][2] * x + m[1][2] * y + m[2][2] * z,
+        }
+    }
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+
+    let mut product = [[0.0; 3]; 3];
+
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+
+    product
+}
+
+/// `fov` (a horizontal field of view in degrees) if a rectilinear image can have it, otherwise DEFAULT_FOV
+pub fn usable_fov(fov: f64) -> f64 {
+
+    if fov > 0.0 && fov < 180.0 {fov} else {DEFAULT_FOV}
+}
+
+/// focal length in pixels of a rectilinear image `image_width` pixels wide
+///
+/// (`fov` is the horizontal field of view in degrees, see `usable_fov`)
+pub fn focal_length(image_width: u32, fov: f64) -> f64 {
+
+    (image_width as f64 / 2.0) / (usable_fov(fov) / 2.0).to_radians().tan()
+}
+
+/// the camera frame direction of (upright, undistorted) image texture coords (range [0,1], y up)
+pub fn camera_direction(image_width: u32, image_height: u32, fov: f64, (u, v): (f64, f64)) -> Direction {
+
+    let f = focal_length(image_width, fov);
+
+    Direction::new((u - 0.5) * image_width as f64, (v - 0.5) * image_height as f64, f)
+        .unwrap_or(Direction{x: 0.0, y: 0.0, z: 1.0})
+}
+
+/// the (upright, undistorted) image texture coords of a camera frame direction:
+/// None if it points away from the image plane
+pub fn camera_texture_coords(image_width: u32, image_height: u32, fov: f64, direction: Direction) -> Option<(f64, f64)> {
+
+    if direction.z <= 0.0 || image_width == 0 || image_height == 0 {
+        return None;
+    }
+
+    let f = focal_length(image_width, fov);
+
+    Some((
+        direction.x / direction.z * f / image_width as f64 + 0.5,
+        direction.y / direction.z * f / image_height as f64 + 0.5,
+    ))
+}
+
+/// A projection of the panorama sphere to a plane, identified in PTO `p` lines by its `f` value
+///
+/// (azimuthal projections are centered on yaw 0, pitch 0)
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum ProjectionKind {
+    /// f0: gnomonic, straight lines stay straight (less than 90° from center)
+    Rectilinear,
+    /// f1: longitude = x, tan(latitude) = y
+    Cylindrical,
+    /// f2: longitude = x, latitude = y
+    Equirectangular,
+    /// f3: equidistant: angle from center = radius
+    Fisheye,
+    /// f4: conformal azimuthal
+    Stereographic,
+    /// f5: conformal cylindrical
+    Mercator,
+}
+
+impl Default for ProjectionKind {
+    fn default() -> Self {
+        ProjectionKind::Equirectangular
+    }
+}
+
+impl ProjectionKind {
+
+    pub const ALL: [ProjectionKind; 6] = [
+        ProjectionKind::Rectilinear,
+        ProjectionKind::Cylindrical,
+        ProjectionKind::Equirectangular,
+        ProjectionKind::Fisheye,
+        ProjectionKind::Stereographic,
+        ProjectionKind::Mercator,
+    ];
+
+    /// from a PTO `p` line `f` value: None if unsupported
+    pub fn from_pto(f: u64) -> Option<Self> {
+
+        Self::ALL.iter().copied().find(|kind| kind.pto_projection() == f)
+    }
+
+    /// the PTO `p` line `f` value
+    pub fn pto_projection(&self) -> u64 {
+
+        match self {
+            ProjectionKind::Rectilinear => 0,
+            ProjectionKind::Cylindrical => 1,
+            ProjectionKind::Equirectangular => 2,
+            ProjectionKind::Fisheye => 3,
+            ProjectionKind::Stereographic => 4,
+            ProjectionKind::Mercator => 5,
+        }
+    }
+
+    /// true IFF x is longitude
+    fn is_cylindrical(&self) -> bool {
+
+        matches!(self, ProjectionKind::Cylindrical | ProjectionKind::Equirectangular | ProjectionKind::Mercator)
+    }
+}
+
+/// how far from the center rendered projections extend, where they would otherwise be unbounded
+const MAX_LATITUDE: f64 = 85.0;
+const MAX_RECTILINEAR_ANGLE: f64 = 80.0;
+const MAX_STEREOGRAPHIC_ANGLE: f64 = 160.0;
+
+/// Maps sphere directions to the WorldCoords plane (see texture_spherical.frag)
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct SphereProjection {
+    /// WorldCoords units per radian at the projection's center
+    pub scale: f64,
+    #[serde(default)]
+    pub kind: ProjectionKind,
+}
+
+impl SphereProjection {
+
+    /// the projected location of `direction`: None if it can't be projected
+    pub fn world_coords(&self, direction: Direction) -> Option<WorldCoords> {
+
+        let Direction{x, y, z} = direction;
+
+        let longitude = x.atan2(z);
+        let latitude = y.clamp(-1.0, 1.0).asin();
+
+        //angle from the center, and the direction of the projected point from the projected center
+        let angle = z.clamp(-1.0, 1.0).acos();
+        let radial = |radius: f64| {
+            let length = x.hypot(y);
+            if length == 0.0 {(0.0, 0.0)} else {(radius * x / length, radius * y / length)}
+        };
+
+        let (px, py) = match self.kind {
+            ProjectionKind::Equirectangular => (longitude, latitude),
+            ProjectionKind::Cylindrical if latitude.abs() < FRAC_PI_2 => (longitude, latitude.tan()),
+            ProjectionKind::Mercator if latitude.abs() < FRAC_PI_2 => (longitude, latitude.tan().asinh()),
+            ProjectionKind::Rectilinear if z > 0.0 => (x / z, y / z),
+            ProjectionKind::Stereographic if z > -1.0 => (2.0 * x / (1.0 + z), 2.0 * y / (1.0 + z)),
+            ProjectionKind::Fisheye => radial(angle),
+            _ => return None,
+        };
+
+        Some(WorldCoords{x: px * self.scale, y: py * self.scale})
+    }
+
+    /// the direction projected to `world_coords`: None outside of the projected sphere
+    pub fn direction(&self, world_coords: WorldCoords) -> Option<Direction> {
+
+        if self.scale <= 0.0 {
+            return None;
+        }
+
+        let x = world_coords.x / self.scale;
+        let y = world_coords.y / self.scale;
+
+        let from_longitude_latitude = |longitude: f64, latitude: f64| {
+            if longitude.abs() > PI || latitude.abs() > FRAC_PI_2 {
+                return None;
+            }
+
+            let (sin_lon, cos_lon) = longitude.sin_cos();
+            let (sin_lat, cos_lat) = latitude.sin_cos();
+
+            Some(Direction{x: cos_lat * sin_lon, y: sin_lat, z: cos_lat * cos_lon})
+        };
+
+        match self.kind {
+            ProjectionKind::Equirectangular => from_longitude_latitude(x, y),
+            ProjectionKind::Cylindrical => from_longitude_latitude(x, y.atan()),
+            ProjectionKind::Mercator => from_longitude_latitude(x, y.sinh().atan()),
+            ProjectionKind::Rectilinear => Direction::new(x, y, 1.0),
+            ProjectionKind::Stereographic => {
+                let r2 = x * x + y * y;
+                Direction::new(4.0 * x, 4.0 * y, 4.0 - r2)
+            },
+            ProjectionKind::Fisheye => {
+                let angle = x.hypot(y);
+                if angle > PI {
+                    return None;
+                }
+                if angle == 0.0 {
+                    return Some(Direction{x: 0.0, y: 0.0, z: 1.0});
+                }
+                let sin = angle.sin();
+                Some(Direction{x: sin * x / angle, y: sin * y / angle, z: angle.cos()})
+            },
+        }
+    }
+
+    /// the WorldCoords (min, max) corners of the rendered projection
+    pub fn bounds(&self) -> (WorldCoords, WorldCoords) {
+
+        let (x, y) = match self.kind {
+            ProjectionKind::Equirectangular => (PI, FRAC_PI_2),
+            ProjectionKind::Cylindrical => (PI, MAX_LATITUDE.to_radians().tan()),
+            ProjectionKind::Mercator => (PI, MAX_LATITUDE.to_radians().tan().asinh()),
+            ProjectionKind::Rectilinear => {
+                let r = MAX_RECTILINEAR_ANGLE.to_radians().tan();
+                (r, r)
+            },
+            ProjectionKind::Stereographic => {
+                let r = 2.0 * (MAX_STEREOGRAPHIC_ANGLE.to_radians() / 2.0).tan();
+                (r, r)
+            },
+            ProjectionKind::Fisheye => (PI, PI),
+        };
+
+        (
+            WorldCoords{x: -x * self.scale, y: -y * self.scale},
+            WorldCoords{x: x * self.scale, y: y * self.scale},
+        )
+    }
+
+    /// directions where this projection is discontinuous or unbounded
+    pub fn singular_directions(&self) -> Vec<Direction> {
+
+        if self.kind.is_cylindrical() {
+            vec![Direction{x: 0.0, y: 1.0, z: 0.0}, Direction{x: 0.0, y: -1.0, z: 0.0}]
+        }
+        else {
+            vec![Direction{x: 0.0, y: 0.0, z: -1.0}]
+        }
+    }
+
+    /// false IFF a straight line between these projected points would cross the projection's wraparound edge
+    /// (or for azimuthal projections, is too long to be a plausible photo edge)
+    pub fn is_continuous(&self, a: WorldCoords, b: WorldCoords) -> bool {
+
+        if self.kind.is_cylindrical() {
+            (a.x - b.x).abs() < PI * self.scale
+        }
+        else {
+            (a.x - b.x).hypot(a.y - b.y) < PI * self.scale
+        }
+    }
+}
+
+/// How photos are placed in the world
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum CameraModel {
+    /// each photo is a WorldRectangle on a flat plane
+    Planar,
+    /// each photo is a camera pose on a sphere, projected to the plane
+    Spherical(SphereProjection),
+}
+
+impl Default for CameraModel {
+    fn default() -> Self {
+        CameraModel::Planar
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_approx_eq::assert_approx_eq;
+
+    fn assert_direction_eq(a: Direction, b: Direction) {
+        assert_approx_eq!(a.x, b.x);
+        assert_approx_eq!(a.y, b.y);
+        assert_approx_eq!(a.z, b.z);
+    }
+
+    #[test]
+    fn camera_pose_test() {
+
+        let forward = Direction{x: 0.0, y: 0.0, z: 1.0};
+        let right = Direction{x: 1.0, y: 0.0, z: 0.0};
+        let up = Direction{x: 0.0, y: 1.0, z: 0.0};
+
+        //yaw right
+        let pose = CameraPose{yaw: 90.0, pitch: 0.0, roll: 0.0};
+        assert_direction_eq(pose.world_direction(forward), right);
+
+        //pitch up
+        let pose = CameraPose{yaw: 0.0, pitch: 90.0, roll: 0.0};
+        assert_direction_eq(pose.world_direction(forward), up);
+
+        //roll clockwise: the image's right edge points down
+        let pose = CameraPose{yaw: 0.0, pitch: 0.0, roll: 90.0};
+        assert_direction_eq(pose.world_direction(right), Direction{x: 0.0, y: -1.0, z: 0.0});
+
+        //round trips
+        let pose = CameraPose{yaw: 30.0, pitch: -20.0, roll: 10.0};
+        for &d in &[forward, right, up, Direction::new(1.0, 2.0, 3.0).unwrap()] {
+            assert_direction_eq(pose.camera_direction(pose.world_direction(d)), d);
+        }
+
+        assert_approx_eq!(right.angle_to(&up), FRAC_PI_2);
+        assert_approx_eq!(forward.angle_to(&forward), 0.0);
+    }
+
+    #[test]
+    fn camera_texture_coords_test() {
+
+        //90° fov: the image's left and right edges are 45° from center
+        let direction = camera_direction(200, 100, 90.0, (1.0, 0.5));
+        assert_approx_eq!(direction.x.atan2(direction.z).to_degrees(), 45.0);
+        assert_approx_eq!(direction.y, 0.0);
+
+        for &uv in &[(0.5, 0.5), (0.0, 1.0), (0.25, 0.75)] {
+            let (u, v) = camera_texture_coords(200, 100, 90.0, camera_direction(200, 100, 90.0, uv)).unwrap();
+            assert_approx_eq!(u, uv.0);
+            assert_approx_eq!(v, uv.1);
+        }
+
+        //behind the camera
+        assert_eq!(camera_texture_coords(200, 100, 90.0, Direction{x: 0.0, y: 0.0, z: -1.0}), None);
+
+        //invalid fovs use the default
+        assert_approx_eq!(focal_length(200, 0.0), focal_length(200, DEFAULT_FOV));
+    }
+
+    #[test]
+    fn sphere_projection_test() {
+
+        let projection = SphereProjection{scale: 100.0, kind: ProjectionKind::Equirectangular};
+
+        let world_coords = projection.world_coords(Direction{x: 1.0, y: 0.0, z: 0.0}).unwrap();
+        assert_approx_eq!(world_coords.x, FRAC_PI_2 * 100.0);
+        assert_approx_eq!(world_coords.y, 0.0);
+
+        let world_coords = projection.world_coords(Direction{x: 0.0, y: 1.0, z: 0.0}).unwrap();
+        assert_approx_eq!(world_coords.y, FRAC_PI_2 * 100.0);
+
+        assert_eq!(projection.direction(WorldCoords{x: 400.0, y: 0.0}), None);
+        assert_eq!(projection.direction(WorldCoords{x: 0.0, y: -200.0}), None);
+
+        assert!(projection.is_continuous(WorldCoords{x: -10.0, y: 0.0}, WorldCoords{x: 10.0, y: 0.0}));
+        assert!(!projection.is_continuous(WorldCoords{x: -310.0, y: 0.0}, WorldCoords{x: 310.0, y: 0.0}));
+
+        //every projection: round trips, and 1 WorldCoords unit per scale unit at the center
+        let directions = [(0.0, 0.0, 1.0), (1.0, 2.0, 3.0), (-1.0, -0.5, 2.0), (0.5, 0.1, -0.2)];
+
+        for &kind in &ProjectionKind::ALL {
+
+            let projection = SphereProjection{scale: 100.0, kind};
+
+            let small = Direction::new(0.001, 0.002, 1.0).unwrap();
+            let world_coords = projection.world_coords(small).unwrap();
+            assert_approx_eq!(world_coords.x, 0.1, 1e-6);
+            assert_approx_eq!(world_coords.y, 0.2, 1e-6);
+
+            for &(x, y, z) in &directions {
+                let d = Direction::new(x, y, z).unwrap();
+                if let Some(world_coords) = projection.world_coords(d) {
+                    assert_direction_eq(projection.direction(world_coords).unwrap(), d);
+                }
+            }
+
+            assert_eq!(ProjectionKind::from_pto(kind.pto_projection()), Some(kind));
+        }
+
+        //only rectilinear can't show the back hemisphere
+        let behind = Direction::new(0.5, 0.1, -0.2).unwrap();
+        assert_eq!(SphereProjection{scale: 100.0, kind: ProjectionKind::Rectilinear}.world_coords(behind), None);
+        assert!(SphereProjection{scale: 100.0, kind: ProjectionKind::Stereographic}.world_coords(behind).is_some());
+
+        //known values
+        let right = Direction{x: 1.0, y: 0.0, z: 0.0};
+        let world_x = |kind: ProjectionKind| SphereProjection{scale: 1.0, kind}.world_coords(right).map(|w| w.x);
+        assert_approx_eq!(world_x(ProjectionKind::Fisheye).unwrap(), FRAC_PI_2);
+        assert_approx_eq!(world_x(ProjectionKind::Stereographic).unwrap(), 2.0);
+        assert_eq!(world_x(ProjectionKind::Rectilinear), None);
+
+        let up45 = Direction::new(0.0, 1.0, 1.0).unwrap();
+        let world_y = |kind: ProjectionKind| SphereProjection{scale: 1.0, kind}.world_coords(up45).unwrap().y;
+        assert_approx_eq!(world_y(ProjectionKind::Cylindrical), 1.0);
+        assert_approx_eq!(world_y(ProjectionKind::Mercator), 1_f64.asinh());
+
+        let (yaw, pitch) = Direction::new(1.0, 1.0, 0.0).unwrap().yaw_pitch();
+        assert_approx_eq!(yaw, 90.0);
+        assert_approx_eq!(pitch, 45.0);
+
+        assert_eq!(ProjectionKind::from_pto(9), None);
+    }
+}
@@ -0,0 +1,1104 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use image::{RgbaImage, Rgba, DynamicImage, ImageError, ImageFormat};
+use log::{info, warn};
+
+use crate::read_pto;
+use crate::read_pto::PtoParseError;
+use crate::media_paths;
+use crate::entities;
+use crate::project::{Project, ProjectError};
+use crate::photo::{Photo, PhotoFields};
+use crate::exif_metadata::ExifMetadata;
+use crate::image_orientation::ImageOrientation;
+use crate::spherical::{CameraModel, CameraPose};
+use crate::viewport_geometry::WorldCoords;
+use crate::world_rectangle::{WorldRectangle, Corner};
+use crate::blend;
+use crate::blend::{BlendMode, MultiBandBlender, Plane};
+use crate::seams::SeamMap;
+use crate::exposure;
+use crate::exposure::{ColorCorrection, OverlapSample};
+use crate::lens::{LensParameters, Vignetting};
+use crate::optimize;
+
+pub const USAGE: &str = "usage: panorama_tool --stitch OUTPUT_FILE (PTO_FILE | PROJECT_FILE) [--bilinear] [--feather | --multiband] [--seams] [--match-colors] [--scale SCALE]";
+
+/// the largest output image width or height, in pixels
+const MAX_DIMENSION: f64 = 32768.0;
+
+/// the most output pixels: stitching buffers tens of bytes per pixel (more with multi-band blending)
+const MAX_PIXELS: f64 = 64_000_000.0;
+
+/// the most overlap sample locations along each side of the photos' bounds, for estimating color corrections
+const MAX_OVERLAP_GRID_SIDE: usize = 128;
+
+/// How source images are resampled
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Sampling {
+    Bilinear,
+    /// Catmull-Rom
+    Bicubic,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct StitchOptions {
+    pub sampling: Sampling,
+    pub blend_mode: BlendMode,
+    /// cut overlaps at seams: the project's painted seams, or found by `find_seams`
+    pub seams: bool,
+    /// replace the photos' color corrections with estimated ones (see `estimate_color_corrections`)
+    pub match_colors: bool,
+    /// output pixels per WorldCoords unit
+    pub scale: f64,
+}
+
+impl Default for StitchOptions {
+
+    fn default() -> Self {
+        Self {
+            sampling: Sampling::Bicubic,
+            blend_mode: BlendMode::Average,
+            seams: false,
+            match_colors: false,
+            scale: 1.0,
+        }
+    }
+}
+
+/// The command line arguments of a headless stitch
+#[derive(Debug, PartialEq, Clone)]
+pub struct StitchArgs {
+    /// a .png, .jpg, or .jpeg file
+    pub output_file: String,
+    /// a PTO or project file
+    pub input_file: String,
+    pub options: StitchOptions,
+}
+
+#[derive(Debug)]
+pub enum StitchError {
+    Usage,
+    /// not a PTO or project file
+    UnknownInput(String),
+    /// not a PNG or JPEG file name
+    UnknownOutput(String),
+    Io(String, std::io::Error),
+    Image(String, ImageError),
+    Project(String, ProjectError),
+    Pto(String, PtoParseError),
+    NoPhotos,
+    /// (width, height) in pixels
+    TooLarge(f64, f64),
+}
+
+impl Display for StitchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StitchError::Usage => write!(f, "invalid arguments"),
+            StitchError::UnknownInput(path) => write!(f, "{} is not a PTO or project file", path),
+            StitchError::UnknownOutput(path) => write!(f, "{} is not a PNG or JPEG file name", path),
+            StitchError::Io(path, e) => write!(f, "could not read {}: {}", path, e),
+            StitchError::Image(path, e) => write!(f, "image error in {}: {}", path, e),
+            StitchError::Project(path, e) => write!(f, "could not read {}: {}", path, e),
+            StitchError::Pto(path, e) => write!(f, "could not read {}: {}", path, e),
+            StitchError::NoPhotos => write!(f, "no visible photos"),
+            StitchError::TooLarge(width, height) =>
+                write!(f, "output would be {:.0}x{:.0} pixels (max {} a side, {} in total)", width, height, MAX_DIMENSION, MAX_PIXELS),
+        }
+    }
+}
+
+impl Error for StitchError {}
+
+impl StitchArgs {
+
+    /// gets a headless stitch from command line arguments (without the program name):
+    /// None unless the first argument is `--stitch`
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, StitchError> {
+
+        match args.first() {
+            Some(arg) if arg == "--stitch" => {},
+            _ => return Ok(None),
+        }
+
+        let mut options = StitchOptions::default();
+        let mut paths = Vec::new();
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bilinear" => options.sampling = Sampling::Bilinear,
+                "--feather" => options.blend_mode = BlendMode::Feather,
+                "--multiband" => options.blend_mode = BlendMode::MultiBand,
+                "--seams" => options.seams = true,
+                "--match-colors" => options.match_colors = true,
+                "--scale" => {
+                    options.scale = args.next()
+                        .and_then(|s| s.parse().ok())
+                        .filter(|&scale: &f64| scale > 0.0)
+                        .ok_or(StitchError::Usage)?;
+                },
+                option if option.starts_with("--") => return Err(StitchError::Usage),
+                _ => paths.push(arg.clone()),
+            }
+        }
+
+        match paths.as_slice() {
+            [output_file, input_file] => {
+                //fail before loading and stitching
+                output_format(output_file)?;
+
+                Ok(Some(Self {
+                    output_file: output_file.clone(),
+                    input_file: input_file.clone(),
+                    options,
+                }))
+            },
+            _ => Err(StitchError::Usage),
+        }
+    }
+}
+
+/// A photo to stitch: its placement and its decoded (stored, not reoriented) image
+pub struct SourcePhoto {
+    pub fields: PhotoFields,
+    pub image_orientation: ImageOrientation,
+    /// the vignetting of the photo's lens
+    pub vignetting: Vignetting,
+    pub image: RgbaImage,
+}
+
+impl SourcePhoto {
+
+    /// decodes `fields.source_path`: the image dimensions and orientation are read from the file
+    /// (without vignetting)
+    pub fn load(mut fields: PhotoFields) -> Result<Self, StitchError> {
+
+        let path = fields.source_path.clone();
+        let bytes = std::fs::read(&path).map_err(|e| StitchError::Io(path.clone(), e))?;
+        let image = image::load_from_memory(&bytes).map_err(|e| StitchError::Image(path.clone(), e))?.to_rgba8();
+
+        let image_orientation = ExifMetadata::from_bytes(&bytes).unwrap_or_default().image_orientation();
+        let (image_width, image_height) = image_orientation.upright_dimensions(image.width(), image.height());
+
+        fields.image_width = image_width;
+        fields.image_height = image_height;
+
+        Ok(Self{fields, image_orientation, vignetting: Vignetting::default(), image})
+    }
+
+    /// samples the image (see `sample`) with its lens' vignetting corrected: RGB in the range [0,1]
+    fn devignetted_sample(&self, uv: (f64, f64), sampling: Sampling) -> [f64; 3] {
+
+        let [red, green, blue, _] = sample(&self.image, uv, sampling);
+        let gain = exposure::encoded_gain(self.vignetting.gain(self.radius(uv)));
+
+        [red / 255.0 * gain, green / 255.0 * gain, blue / 255.0 * gain]
+    }
+
+    /// samples the image (see `sample`) with its vignetting and color corrections applied
+    fn corrected_sample(&self, uv: (f64, f64), sampling: Sampling) -> [f64; 4] {
+
+        let alpha = sample(&self.image, uv, sampling)[3];
+        let [red, green, blue] = self.fields.color_correction.apply(self.devignetted_sample(uv, sampling));
+
+        let channel = |value: f64| (value * 255.0).clamp(0.0, 255.0);
+        [channel(red), channel(green), channel(blue), alpha]
+    }
+
+    /// the vignetting radius (see Vignetting::radius) of source image texture coords
+    fn radius(&self, uv: (f64, f64)) -> f64 {
+
+        Vignetting::radius(uv, self.image.width(), self.image.height())
+    }
+
+    /// the source image texture coords rendered at `point`, as `Photo::contains`
+    fn source_texture_coords(&self, camera_model: &CameraModel, point: WorldCoords) -> Option<(f64, f64)> {
+
+        let f = &self.fields;
+
+        match camera_model {
+            CameraModel::Planar =>
+                Photo::source_texture_coords_impl(&f.orientation, f.image_width, f.image_height, self.image_orientation, &f.lens, point),
+            CameraModel::Spherical(projection) =>
+                Photo::spherical_source_texture_coords_impl(projection, &f.pose, f.image_width, f.image_height, self.image_orientation, &f.lens, point),
+        }
+    }
+
+    /// WorldCoords (min, max) corners of a rectangle containing this photo
+    fn bounds(&self, camera_model: &CameraModel) -> (WorldCoords, WorldCoords) {
+
+        let f = &self.fields;
+
+        match camera_model {
+            CameraModel::Planar => {
+                let corners = vec![Corner::TopLeft, Corner::TopRight, Corner::BottomLeft, Corner::BottomRight];
+                let corners: Vec<WorldCoords> = corners.into_iter().map(|corner| f.orientation.corner(corner)).collect();
+
+                let min = WorldCoords{
+                    x: corners.iter().map(|p| p.x).fold(f64::MAX, f64::min),
+                    y: corners.iter().map(|p| p.y).fold(f64::MAX, f64::min),
+                };
+                let max = WorldCoords{
+                    x: corners.iter().map(|p| p.x).fold(f64::MIN, f64::max),
+                    y: corners.iter().map(|p| p.y).fold(f64::MIN, f64::max),
+                };
+
+                (min, max)
+            },
+            CameraModel::Spherical(projection) =>
+                Photo::spherical_bounds_impl(projection, &f.pose, f.image_width, f.image_height, self.image_orientation, &f.lens),
+        }
+    }
+}
+
+/// The photos to stitch, and how to place them
+pub struct StitchInput {
+    pub photos: Vec<SourcePhoto>,
+    pub camera_model: CameraModel,
+    /// a project's saved seams
+    pub seam_map: Option<SeamMap>,
+}
+
+/// loads the photos and camera model of a project file, or of a PTO file (in its spherical output projection)
+pub fn load_input(path: &str) -> Result<StitchInput, StitchError> {
+
+    let lowercase_path = path.to_lowercase();
+    let is_project = lowercase_path.ends_with(".json");
+
+    if !is_project && !lowercase_path.ends_with(".pto") {
+        return Err(StitchError::UnknownInput(path.to_string()));
+    }
+
+    let s = std::fs::read_to_string(path).map_err(|e| StitchError::Io(path.to_string(), e))?;
+
+    if is_project {
+
+        let project = Project::from_json_str(&s).map_err(|e| StitchError::Project(path.to_string(), e))?;
+
+        let lens_vignetting = project.lens_vignetting;
+
+        let photos = project.photos.into_iter().map(|fields| {
+            let saved_dimensions = (fields.image_width, fields.image_height);
+            let source_path = media_paths::project_image_path(path, &fields.source_path);
+            let vignetting = fields.lens_id.and_then(|lens_id| lens_vignetting.get(lens_id)).copied();
+            let mut photo = SourcePhoto::load(PhotoFields{source_path, ..fields})?;
+            photo.vignetting = vignetting.unwrap_or_default();
+
+            if saved_dimensions != (photo.fields.image_width, photo.fields.image_height) {
+                warn!("saved image dimensions {}x{} do not match {} ({}x{})",
+                    saved_dimensions.0, saved_dimensions.1, photo.fields.source_path, photo.fields.image_width, photo.fields.image_height);
+            }
+
+            Ok(photo)
+        }).collect::<Result<Vec<_>, StitchError>>()?;
+
+        Ok(StitchInput{photos, camera_model: project.camera_model, seam_map: project.seam_map})
+    }
+    else {
+
+        let (pto_file, pto_file_warnings) = read_pto::read_pto_file_lenient(&s).map_err(|e| StitchError::Pto(path.to_string(), e))?;
+
+        for warning in &pto_file_warnings {
+            warn!("skipped invalid PTO line: {}", warning);
+        }
+
+        let lens_ids = entities::pto_lens_ids(&pto_file);
+
+        let photos = media_paths::pto_image_paths(path, &pto_file).into_iter().enumerate().map(|(index, source_path)| {
+            let mut photo = SourcePhoto::load(PhotoFields {
+                source_path,
+                image_width: 0,
+                image_height: 0,
+                orientation: WorldRectangle::new(0.0, 0.0),
+                lens: LensParameters::default(),
+                lens_id: lens_ids.get(index).copied(),
+                pose: CameraPose::default(),
+                visible: true,
+                locked: false,
+                color_correction: ColorCorrection::default(),
+            })?;
+
+            photo.fields.orientation = WorldRectangle::new(photo.fields.image_width as f32, photo.fields.image_height as f32);
+            photo.fields.lens = entities::pto_lens_parameters(&pto_file, index, photo.image_orientation).unwrap_or_default();
+            photo.fields.pose = entities::pto_camera_pose(&pto_file, index, photo.image_orientation).unwrap_or_default();
+            photo.vignetting = entities::pto_vignetting(&pto_file, index).unwrap_or_default();
+            Ok(photo)
+        }).collect::<Result<Vec<_>, StitchError>>()?;
+
+        let first_photo = photos.first().map(|photo| (photo.fields.image_width, photo.fields.lens.fov));
+        let projection = entities::pto_sphere_projection(&pto_file, first_photo);
+
+        Ok(StitchInput{photos, camera_model: CameraModel::Spherical(projection), seam_map: None})
+    }
+}
+
+/// samples `image` at texture coords (range [0,1], y up), clamped to its edges: RGBA in the range [0,255]
+pub fn sample(image: &RgbaImage, (u, v): (f64, f64), sampling: Sampling) -> [f64; 4] {
+
+    if image.width() == 0 || image.height() == 0 {
+        return [0.0; 4];
+    }
+
+    //pixel centers are at half-pixel texture coords
+    let x = u * image.width() as f64 - 0.5;
+    let y = (1.0 - v) * image.height() as f64 - 0.5;
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let pixel = |x: i64, y: i64| {
+        let x = x.clamp(0, image.width() as i64 - 1) as u32;
+        let y = y.clamp(0, image.height() as i64 - 1) as u32;
+        image.get_pixel(x, y).0
+    };
+
+    //(pixel offset, weight) pairs
+    let weights = |t: f64| -> Vec<(i64, f64)> {
+        match sampling {
+            Sampling::Bilinear => vec![(0, 1.0 - t), (1, t)],
+            Sampling::Bicubic => vec![(-1, catmull_rom(1.0 + t)), (0, catmull_rom(t)), (1, catmull_rom(1.0 - t)), (2, catmull_rom(2.0 - t))],
+        }
+    };
+
+    let mut color = [0.0; 4];
+
+    for (dy, wy) in weights(ty) {
+        for (dx, wx) in weights(tx) {
+            for (sum, &channel) in color.iter_mut().zip(pixel(x0 + dx, y0 + dy).iter()) {
+                *sum += wx * wy * channel as f64;
+            }
+        }
+    }
+
+    //bicubic overshoot
+    for channel in &mut color {
+        *channel = channel.clamp(0.0, 255.0);
+    }
+
+    color
+}
+
+/// the Catmull-Rom cubic kernel at distance `x`
+fn catmull_rom(x: f64) -> f64 {
+
+    let x = x.abs();
+
+    if x <= 1.0 {
+        1.5 * x.powi(3) - 2.5 * x.powi(2) + 1.0
+    }
+    else if x < 2.0 {
+        -0.5 * x.powi(3) + 2.5 * x.powi(2) - 4.0 * x + 2.0
+    }
+    else {
+        0.0
+    }
+}
+
+/// The output image's location in WorldCoords
+struct Canvas {
+    /// top left corner
+    top_left: WorldCoords,
+    /// output pixels per WorldCoords unit
+    scale: f64,
+    width: u32,
+    height: u32,
+}
+
+impl Canvas {
+
+    /// the output image containing the visible photos, at `scale` output pixels per WorldCoords unit
+    fn new(photos: &[SourcePhoto], camera_model: &CameraModel, scale: f64) -> Result<Self, StitchError> {
+
+        let (min, max) = visible_bounds(photos, camera_model).ok_or(StitchError::NoPhotos)?;
+
+        let width = ((max.x - min.x) * scale).ceil().max(1.0);
+        let height = ((max.y - min.y) * scale).ceil().max(1.0);
+
+        if width > MAX_DIMENSION || height > MAX_DIMENSION || width * height > MAX_PIXELS {
+            return Err(StitchError::TooLarge(width, height));
+        }
+
+        Ok(Self {
+            top_left: WorldCoords{x: min.x, y: max.y},
+            scale,
+            width: width as u32,
+            height: height as u32,
+        })
+    }
+
+    /// the center of an output pixel (WorldCoords y is up)
+    fn point(&self, x: u32, y: u32) -> WorldCoords {
+
+        WorldCoords {
+            x: self.top_left.x + (x as f64 + 0.5) / self.scale,
+            y: self.top_left.y - (y as f64 + 0.5) / self.scale,
+        }
+    }
+
+    /// calls `f(x, y, texture coords)` for each output pixel where `photo` (with `bounds`) has a source image sample
+    fn for_each_source_texture_coords(
+        &self,
+        photo: &SourcePhoto,
+        (min, max): (WorldCoords, WorldCoords),
+        camera_model: &CameraModel,
+        mut f: impl FnMut(u32, u32, (f64, f64)),
+    ) {
+
+        let pixel = |coord: f64, limit: u32| coord.max(0.0).min(limit as f64) as u32;
+
+        let x_range = pixel(((min.x - self.top_left.x) * self.scale).floor(), self.width)..pixel(((max.x - self.top_left.x) * self.scale).ceil(), self.width);
+        let y_range = pixel(((self.top_left.y - max.y) * self.scale).floor(), self.height)..pixel(((self.top_left.y - min.y) * self.scale).ceil(), self.height);
+
+        for y in y_range {
+            for x in x_range.clone() {
+                if let Some(uv) = photo.source_texture_coords(camera_model, self.point(x, y)) {
+                    f(x, y, uv);
+                }
+            }
+        }
+    }
+}
+
+/// A visible photo to stitch
+struct CanvasPhoto<'a> {
+    /// in the input photos (see SeamMap::labels)
+    index: usize,
+    photo: &'a SourcePhoto,
+    /// WorldCoords (min, max)
+    bounds: (WorldCoords, WorldCoords),
+}
+
+/// WorldCoords (min, max) corners of a rectangle containing the visible photos, if there are any
+fn visible_bounds(photos: &[SourcePhoto], camera_model: &CameraModel) -> Option<(WorldCoords, WorldCoords)> {
+
+    photos.iter().filter(|photo| photo.fields.visible).map(|photo| photo.bounds(camera_model)).reduce(|(min1, max1), (min2, max2)| {
+        (
+            WorldCoords{x: min1.x.min(min2.x), y: min1.y.min(min2.y)},
+            WorldCoords{x: max1.x.max(max2.x), y: max1.y.max(max2.y)},
+        )
+    })
+}
+
+/// finds seams between the visible photos (see seams::find_seams) on a grid covering them, if there are any
+pub fn find_seams(photos: &[SourcePhoto], camera_model: &CameraModel) -> Option<SeamMap> {
+
+    let (min, max) = visible_bounds(photos, camera_model)?;
+    let mut seam_map = SeamMap::with_max_cells(min, max);
+
+    let colors: Vec<Vec<Option<[f32; 3]>>> = photos.iter().map(|photo| {
+        (0..seam_map.height).flat_map(|y| (0..seam_map.width).map(move |x| (x, y))).map(|(x, y)| {
+
+            if !photo.fields.visible {
+                return None;
+            }
+
+            photo.source_texture_coords(camera_model, seam_map.cell_center(x, y)).map(|uv| {
+                let color = photo.corrected_sample(uv, Sampling::Bilinear);
+                [(color[0] / 255.0) as f32, (color[1] / 255.0) as f32, (color[2] / 255.0) as f32]
+            })
+        }).collect()
+    }).collect();
+
+    seam_map.find_seams(&colors);
+    Some(seam_map)
+}
+
+/// samples the colors (vignetting corrected, see OverlapSample) of each pair of visible photos on a grid covering them
+fn overlap_samples(photos: &[SourcePhoto], camera_model: &CameraModel) -> Vec<OverlapSample> {
+
+    let (min, max) = match visible_bounds(photos, camera_model) {
+        Some(bounds) => bounds,
+        None => return Vec::new(),
+    };
+
+    let cell_size = (max.x - min.x).max(max.y - min.y) / MAX_OVERLAP_GRID_SIDE as f64;
+    if cell_size <= 0.0 {
+        return Vec::new();
+    }
+
+    let width = ((max.x - min.x) / cell_size).ceil() as usize;
+    let height = ((max.y - min.y) / cell_size).ceil() as usize;
+
+    let mut samples = Vec::new();
+
+    for y in 0..height {
+        for x in 0..width {
+            let point = WorldCoords{x: min.x + (x as f64 + 0.5) * cell_size, y: min.y + (y as f64 + 0.5) * cell_size};
+
+            //(photo index, color, radius)
+            let colors: Vec<(usize, [f64; 3], f64)> = photos.iter().enumerate()
+                .filter(|(_, photo)| photo.fields.visible)
+                .filter_map(|(index, photo)| {
+                    let uv = photo.source_texture_coords(camera_model, point)?;
+                    Some((index, photo.devignetted_sample(uv, Sampling::Bilinear), photo.radius(uv)))
+                })
+                .collect();
+
+            for (n, &(i, color_i, radius_i)) in colors.iter().enumerate() {
+                for &(j, color_j, radius_j) in &colors[n + 1..] {
+                    samples.push(OverlapSample{photos: (i, j), colors: (color_i, color_j), radii: (radius_i, radius_j)});
+                }
+            }
+        }
+    }
+
+    samples
+}
+
+/// estimates color corrections (see exposure::estimate_color_corrections) that make the visible photos match where they overlap
+///
+/// (hidden photos, and photos without usable overlaps, get None)
+pub fn estimate_color_corrections(photos: &[SourcePhoto], camera_model: &CameraModel) -> Vec<Option<ColorCorrection>> {
+
+    exposure::estimate_color_corrections(photos.len(), &overlap_samples(photos, camera_model))
+}
+
+/// estimates each photo's lens vignetting (see exposure::estimate_vignetting) from where the visible photos overlap
+///
+/// (photos with the same lens id share a lens)
+pub fn estimate_vignetting(photos: &[SourcePhoto], camera_model: &CameraModel) -> Vec<Vignetting> {
+
+    let (current, lens_index) = optimize::lens_groups(photos.iter().map(|photo| (photo.fields.lens_id, photo.vignetting)));
+
+    let estimated = exposure::estimate_vignetting(&lens_index, &current, &overlap_samples(photos, camera_model));
+
+    lens_index.into_iter().map(|index| estimated[index]).collect()
+}
+
+/// resamples the visible photos into one image containing all of them (with lens distortion corrected)
+///
+/// where `seam_map` is given, overlaps are cut at its seams
+///
+/// pixels with no photos are transparent
+pub fn stitch(photos: &[SourcePhoto], camera_model: &CameraModel, seam_map: Option<&SeamMap>, options: &StitchOptions) -> Result<RgbaImage, StitchError> {
+
+    let canvas = Canvas::new(photos, camera_model, options.scale)?;
+
+    let photos: Vec<CanvasPhoto> = photos.iter().enumerate()
+        .filter(|(_, photo)| photo.fields.visible)
+        .map(|(index, photo)| CanvasPhoto{index, photo, bounds: photo.bounds(camera_model)})
+        .collect();
+
+    let owners = seam_map.map(|seam_map| pixel_owners(&photos, camera_model, &canvas, Some(seam_map)));
+
+    Ok(match options.blend_mode {
+        BlendMode::Average => stitch_weighted_average(&photos, camera_model, &canvas, owners.as_deref(), options.sampling, |_| 1.0),
+        BlendMode::Feather => stitch_weighted_average(&photos, camera_model, &canvas, owners.as_deref(), options.sampling, blend::edge_distance),
+        BlendMode::MultiBand => {
+            let owners = owners.unwrap_or_else(|| pixel_owners(&photos, camera_model, &canvas, None));
+            stitch_multiband(&photos, camera_model, &canvas, &owners, options.sampling)
+        },
+    })
+}
+
+/// assigns each output pixel to one of the photos covering it (by CanvasPhoto::index):
+/// its `seam_map` label, or else the photo where it's farthest from the image edges
+fn pixel_owners(photos: &[CanvasPhoto], camera_model: &CameraModel, canvas: &Canvas, seam_map: Option<&SeamMap>) -> Vec<Option<usize>> {
+
+    //(photo index, edge distance, is the seam_map label)
+    let mut owners: Vec<Option<(usize, f64, bool)>> = vec![None; (canvas.width * canvas.height) as usize];
+
+    for photo in photos {
+        canvas.for_each_source_texture_coords(photo.photo, photo.bounds, camera_model, |x, y, uv| {
+            let owner = &mut owners[(y * canvas.width + x) as usize];
+            let distance = blend::edge_distance(uv);
+            let labeled = seam_map.map_or(false, |seam_map| seam_map.label(canvas.point(x, y)) == Some(photo.index));
+
+            let replace = match *owner {
+                None => true,
+                Some((_, owner_distance, owner_labeled)) => (labeled, distance) > (owner_labeled, owner_distance),
+            };
+
+            if replace {
+                *owner = Some((photo.index, distance, labeled));
+            }
+        });
+    }
+
+    owners.into_iter().map(|owner| owner.map(|(index, _, _)| index)).collect()
+}
+
+/// overlapping photos are averaged, as in average_effect.frag,
+/// each sample weighted by `weight(source image texture coords)` (which must be positive)
+///
+/// if `owners` are given, each pixel is only from its owner
+fn stitch_weighted_average(
+    photos: &[CanvasPhoto],
+    camera_model: &CameraModel,
+    canvas: &Canvas,
+    owners: Option<&[Option<usize>]>,
+    sampling: Sampling,
+    weight: impl Fn((f64, f64)) -> f64,
+) -> RgbaImage {
+
+    let mut sums = vec![[0.0; 3]; (canvas.width * canvas.height) as usize];
+    let mut weights = vec![0.0; sums.len()];
+
+    for photo in photos {
+        canvas.for_each_source_texture_coords(photo.photo, photo.bounds, camera_model, |x, y, uv| {
+            let index = (y * canvas.width + x) as usize;
+
+            if owners.map_or(false, |owners| owners[index] != Some(photo.index)) {
+                return;
+            }
+
+            let color = photo.photo.corrected_sample(uv, sampling);
+            let w = weight(uv).max(f64::MIN_POSITIVE);
+            for (sum, channel) in sums[index].iter_mut().zip(color.iter()) {
+                *sum += w * channel;
+            }
+            weights[index] += w;
+        });
+    }
+
+    RgbaImage::from_fn(canvas.width, canvas.height, |x, y| {
+        let index = (y * canvas.width + x) as usize;
+        let (sum, total_weight) = (sums[index], weights[index]);
+
+        if total_weight == 0.0 {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        let average = |channel: f64| (channel / total_weight).round() as u8;
+        Rgba([average(sum[0]), average(sum[1]), average(sum[2]), 255])
+    })
+}
+
+/// overlapping photos are blended by Laplacian pyramid (see blend::MultiBandBlender),
+/// with each pixel's high frequencies from its owner
+fn stitch_multiband(
+    photos: &[CanvasPhoto],
+    camera_model: &CameraModel,
+    canvas: &Canvas,
+    owners: &[Option<usize>],
+    sampling: Sampling,
+) -> RgbaImage {
+
+    let (width, height) = (canvas.width as usize, canvas.height as usize);
+
+    let mut blender = MultiBandBlender::new(width, height);
+
+    for photo in photos {
+
+        let mut color = [Plane::new(width, height), Plane::new(width, height), Plane::new(width, height)];
+        let mut coverage = Plane::new(width, height);
+        let mut weight = Plane::new(width, height);
+
+        canvas.for_each_source_texture_coords(photo.photo, photo.bounds, camera_model, |x, y, uv| {
+            let (x, y) = (x as usize, y as usize);
+            let sample = photo.photo.corrected_sample(uv, sampling);
+
+            for (plane, &channel) in color.iter_mut().zip(sample.iter()) {
+                plane.set(x, y, (channel / 255.0) as f32);
+            }
+            coverage.set(x, y, 1.0);
+
+            if owners[y * width + x] == Some(photo.index) {
+                weight.set(x, y, 1.0);
+            }
+        });
+
+        blender.add(&color, &coverage, &weight);
+    }
+
+    let [red, green, blue] = blender.result();
+
+    RgbaImage::from_fn(canvas.width, canvas.height, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+
+        if owners[y * width + x].is_none() {
+            return Rgba([0, 0, 0, 0]);
+        }
+
+        let channel = |plane: &Plane| (plane.get(x, y).clamp(0.0, 1.0) * 255.0).round() as u8;
+        Rgba([channel(&red), channel(&green), channel(&blue), 255])
+    })
+}
+
+/// the output image format, by file extension: PNG or JPEG
+fn output_format(path: &str) -> Result<ImageFormat, StitchError> {
+
+    let lowercase_path = path.to_lowercase();
+
+    if lowercase_path.ends_with(".png") {
+        Ok(ImageFormat::Png)
+    }
+    else if lowercase_path.ends_with(".jpg") || lowercase_path.ends_with(".jpeg") {
+        Ok(ImageFormat::Jpeg)
+    }
+    else {
+        Err(StitchError::UnknownOutput(path.to_string()))
+    }
+}
+
+/// writes `image` as PNG or JPEG, by file extension (JPEG has no transparency: pixels with no photos are black)
+pub fn save(image: RgbaImage, path: &str) -> Result<(), StitchError> {
+
+    let format = output_format(path)?;
+
+    let result = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image).to_rgb8().save_with_format(path, format),
+        _ => image.save_with_format(path, format),
+    };
+
+    result.map_err(|e| StitchError::Image(path.to_string(), e))
+}
+
+/// stitches the input file's photos into the output file
+pub fn run(args: &StitchArgs) -> Result<(), StitchError> {
+
+    let mut input = load_input(&args.input_file)?;
+
+    //fail before estimating colors or finding seams
+    Canvas::new(&input.photos, &input.camera_model, args.options.scale)?;
+
+    if args.options.match_colors {
+        info!("estimating color corrections");
+        let corrections = estimate_color_corrections(&input.photos, &input.camera_model);
+
+        for (photo, correction) in input.photos.iter_mut().zip(corrections) {
+            if let Some(correction) = correction {
+                photo.fields.color_correction = correction;
+            }
+        }
+    }
+
+    let seam_map = match (args.options.seams, input.seam_map) {
+        (false, _) => None,
+        (true, Some(seam_map)) => Some(seam_map),
+        (true, None) => {
+            info!("finding seams");
+            find_seams(&input.photos, &input.camera_model)
+        },
+    };
+
+    let image = stitch(&input.photos, &input.camera_model, seam_map.as_ref(), &args.options)?;
+
+    info!("writing {}x{} panorama to {}", image.width(), image.height(), args.output_file);
+    save(image, &args.output_file)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use assert_matches::*;
+    use assert_approx_eq::assert_approx_eq;
+
+    use crate::lens::LensParameters;
+    use crate::spherical::{CameraPose, SphereProjection, ProjectionKind};
+
+    fn strings(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// a solid color photo, centered at `center` in the planar camera model
+    fn solid_photo(width: u32, height: u32, color: [u8; 4], center: WorldCoords) -> SourcePhoto {
+
+        let mut orientation = WorldRectangle::new(width as f32, height as f32);
+        orientation.set_translation(center);
+
+        SourcePhoto {
+            fields: PhotoFields {
+                source_path: "solid.png".to_string(),
+                image_width: width,
+                image_height: height,
+                orientation,
+                lens: LensParameters{fov: 60.0, ..Default::default()},
+                lens_id: Some(0),
+                pose: CameraPose::default(),
+                visible: true,
+                locked: false,
+                color_correction: ColorCorrection::default(),
+            },
+            image_orientation: ImageOrientation::Normal,
+            vignetting: Vignetting::default(),
+            image: RgbaImage::from_pixel(width, height, Rgba(color)),
+        }
+    }
+
+    #[test]
+    fn stitch_args_test() {
+
+        assert_matches!(StitchArgs::from_args(&strings(&[])), Ok(None));
+        assert_matches!(StitchArgs::from_args(&strings(&["a.pto"])), Ok(None));
+
+        assert_eq!(
+            StitchArgs::from_args(&strings(&["--stitch", "out.png", "a.pto"])).unwrap(),
+            Some(StitchArgs{output_file: "out.png".to_string(), input_file: "a.pto".to_string(), options: StitchOptions::default()})
+        );
+
+        let args = StitchArgs::from_args(&strings(&["--stitch", "--bilinear", "out.jpg", "a.project.json", "--scale", "0.5"])).unwrap().unwrap();
+        assert_eq!(args.options, StitchOptions{sampling: Sampling::Bilinear, blend_mode: BlendMode::Average, seams: false, match_colors: false, scale: 0.5});
+        assert_eq!(args.input_file, "a.project.json");
+
+        let args = StitchArgs::from_args(&strings(&["--stitch", "out.png", "a.pto", "--multiband"])).unwrap().unwrap();
+        assert_eq!(args.options.blend_mode, BlendMode::MultiBand);
+
+        let args = StitchArgs::from_args(&strings(&["--stitch", "--feather", "out.png", "a.pto"])).unwrap().unwrap();
+        assert_eq!(args.options.blend_mode, BlendMode::Feather);
+
+        let args = StitchArgs::from_args(&strings(&["--stitch", "--seams", "out.png", "a.pto"])).unwrap().unwrap();
+        assert!(args.options.seams);
+
+        let args = StitchArgs::from_args(&strings(&["--stitch", "out.png", "a.pto", "--match-colors"])).unwrap().unwrap();
+        assert!(args.options.match_colors);
+
+        assert_matches!(StitchArgs::from_args(&strings(&["--stitch", "out.png"])), Err(StitchError::Usage));
+        assert_matches!(StitchArgs::from_args(&strings(&["--stitch", "out.png", "a.pto", "--scale", "-1"])), Err(StitchError::Usage));
+        assert_matches!(StitchArgs::from_args(&strings(&["--stitch", "out.png", "a.pto", "--scale"])), Err(StitchError::Usage));
+        assert_matches!(StitchArgs::from_args(&strings(&["--stitch", "out.png", "a.pto", "--blend"])), Err(StitchError::Usage));
+        assert_matches!(StitchArgs::from_args(&strings(&["--stitch", "out.tif", "a.pto"])), Err(StitchError::UnknownOutput(path)) if path == "out.tif");
+        assert!(StitchArgs::from_args(&strings(&["--stitch", "OUT.JPEG", "a.pto"])).is_ok());
+    }
+
+    #[test]
+    fn sample_test() {
+
+        //2x1: black, white
+        let mut image = RgbaImage::new(2, 1);
+        image.put_pixel(1, 0, Rgba([255, 255, 255, 255]));
+
+        for &sampling in &[Sampling::Bilinear, Sampling::Bicubic] {
+
+            //pixel centers
+            assert_approx_eq!(sample(&image, (0.25, 0.5), sampling)[0], 0.0);
+            assert_approx_eq!(sample(&image, (0.75, 0.5), sampling)[0], 255.0);
+
+            //halfway between
+            assert_approx_eq!(sample(&image, (0.5, 0.5), sampling)[0], 127.5);
+
+            //clamped to the edges
+            assert_approx_eq!(sample(&image, (0.0, 0.0), sampling)[0], 0.0);
+            assert_approx_eq!(sample(&image, (1.0, 1.0), sampling)[0], 255.0);
+        }
+
+        assert_approx_eq!(sample(&image, (0.375, 0.5), Sampling::Bilinear)[0], 63.75);
+
+        //Catmull-Rom is sharper
+        assert!(sample(&image, (0.375, 0.5), Sampling::Bicubic)[0] < 63.75);
+
+        assert_eq!(sample(&RgbaImage::new(0, 0), (0.5, 0.5), Sampling::Bicubic), [0.0; 4]);
+    }
+
+    #[test]
+    fn stitch_test() {
+
+        let red = solid_photo(4, 2, [200, 0, 100, 255], WorldCoords{x: 0.0, y: 0.0});
+        let blue = solid_photo(4, 2, [100, 0, 200, 255], WorldCoords{x: 2.0, y: 0.0});
+        let mut photos = vec![red, blue];
+
+        let output = stitch(&photos, &CameraModel::Planar, None, &StitchOptions::default()).unwrap();
+
+        assert_eq!(output.dimensions(), (6, 2));
+        assert_eq!(output.get_pixel(0, 0), &Rgba([200, 0, 100, 255]));
+        assert_eq!(output.get_pixel(2, 1), &Rgba([150, 0, 150, 255]));
+        assert_eq!(output.get_pixel(5, 1), &Rgba([100, 0, 200, 255]));
+
+        //2 output pixels per WorldCoords unit
+        let output = stitch(&photos, &CameraModel::Planar, None, &StitchOptions{scale: 2.0, ..Default::default()}).unwrap();
+        assert_eq!(output.dimensions(), (12, 4));
+
+        //30000x10000 pixels: each side is allowed, but not their total
+        assert_matches!(stitch(&photos, &CameraModel::Planar, None, &StitchOptions{scale: 5000.0, ..Default::default()}), Err(StitchError::TooLarge(_, _)));
+
+        //hidden photos are skipped
+        photos[1].fields.visible = false;
+        let output = stitch(&photos, &CameraModel::Planar, None, &StitchOptions::default()).unwrap();
+        assert_eq!(output.dimensions(), (4, 2));
+        assert_eq!(output.get_pixel(3, 1), &Rgba([200, 0, 100, 255]));
+
+        photos[0].fields.visible = false;
+        assert_matches!(stitch(&photos, &CameraModel::Planar, None, &StitchOptions::default()), Err(StitchError::NoPhotos));
+    }
+
+    #[test]
+    fn stitch_feather_test() {
+
+        let red = solid_photo(40, 20, [200, 0, 100, 255], WorldCoords{x: 0.0, y: 0.0});
+        let blue = solid_photo(40, 20, [100, 0, 200, 255], WorldCoords{x: 24.0, y: 0.0});
+        let options = StitchOptions{blend_mode: BlendMode::Feather, ..Default::default()};
+
+        let output = stitch(&[red, blue], &CameraModel::Planar, None, &options).unwrap();
+        assert_eq!(output.dimensions(), (64, 20));
+
+        //outside the overlap: each photo's own color
+        assert_eq!(output.get_pixel(0, 10), &Rgba([200, 0, 100, 255]));
+        assert_eq!(output.get_pixel(63, 10), &Rgba([100, 0, 200, 255]));
+
+        //the overlap's middle is an even mix; its edges are dominated by the inner photo
+        let (left, right) = (output.get_pixel(31, 10), output.get_pixel(32, 10));
+        assert!(left[0] > 150 && right[0] < 150);
+        assert_eq!(left[0] as u32 + right[0] as u32, 300);
+        assert!(output.get_pixel(25, 10)[0] > 180);
+        assert!(output.get_pixel(38, 10)[0] < 120);
+
+        //a gradual transition across the overlap
+        let reds: Vec<u8> = (0..64).map(|x| output.get_pixel(x, 10)[0]).collect();
+        assert!(reds.windows(2).all(|pair| pair[1] <= pair[0]));
+    }
+
+    #[test]
+    fn stitch_multiband_test() {
+
+        let red = solid_photo(40, 20, [200, 0, 100, 255], WorldCoords{x: 0.0, y: 0.0});
+        let blue = solid_photo(40, 20, [100, 0, 200, 255], WorldCoords{x: 24.0, y: 0.0});
+        let options = StitchOptions{blend_mode: BlendMode::MultiBand, ..Default::default()};
+
+        let output = stitch(&[red, blue], &CameraModel::Planar, None, &options).unwrap();
+        assert_eq!(output.dimensions(), (64, 20));
+
+        //far from the overlap: each photo's own color
+        assert_eq!(output.get_pixel(0, 10), &Rgba([200, 0, 100, 255]));
+        assert_eq!(output.get_pixel(63, 10), &Rgba([100, 0, 200, 255]));
+
+        //a gradual transition across the overlap
+        let reds: Vec<u8> = (0..64).map(|x| output.get_pixel(x, 10)[0]).collect();
+        assert!(reds.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(reds.windows(2).all(|pair| pair[0] - pair[1] < 30));
+    }
+
+    #[test]
+    fn stitch_seams_test() {
+
+        let (red, black, blue) = ([200, 0, 100, 255], [0, 0, 0, 255], [100, 0, 200, 255]);
+
+        //a moving object in the left photo's part of the overlap (output columns 24 to 40)
+        let mut left = solid_photo(40, 20, red, WorldCoords{x: 0.0, y: 0.0});
+        for y in 0..20 {
+            for x in 28..36 {
+                left.image.put_pixel(x, y, Rgba(black));
+            }
+        }
+        let right = solid_photo(40, 20, blue, WorldCoords{x: 24.0, y: 0.0});
+        let photos = [left, right];
+
+        let seam_map = find_seams(&photos, &CameraModel::Planar).unwrap();
+
+        //each output pixel is from one photo: the object is not averaged with the background
+        for &blend_mode in &[BlendMode::Average, BlendMode::Feather] {
+            let options = StitchOptions{blend_mode, ..Default::default()};
+            let output = stitch(&photos, &CameraModel::Planar, Some(&seam_map), &options).unwrap();
+
+            for y in 0..20 {
+                for x in 0..64 {
+                    let pixel = output.get_pixel(x, y).0;
+                    assert!(pixel == red || pixel == black || pixel == blue, "{:?} at ({}, {})", pixel, x, y);
+                }
+            }
+        }
+
+        //the seam goes around the object
+        let output = stitch(&photos, &CameraModel::Planar, Some(&seam_map), &StitchOptions::default()).unwrap();
+        let object: Vec<[u8; 4]> = (28..36).map(|x| output.get_pixel(x, 10).0).collect();
+        assert!(object.iter().all(|&pixel| pixel == object[0]));
+
+        //multi-band blending uses the seams as its blend masks
+        let options = StitchOptions{blend_mode: BlendMode::MultiBand, ..Default::default()};
+        assert!(stitch(&photos, &CameraModel::Planar, Some(&seam_map), &options).is_ok());
+
+        assert_eq!(find_seams(&[], &CameraModel::Planar), None);
+    }
+
+    #[test]
+    fn stitch_color_correction_test() {
+
+        let left = solid_photo(40, 20, [100, 150, 50, 255], WorldCoords{x: 0.0, y: 0.0});
+        let mut right = solid_photo(40, 20, [100, 150, 50, 255], WorldCoords{x: 24.0, y: 0.0});
+
+        //1 EV darker, with a blue cast
+        let cast = ColorCorrection{exposure: -1.0, red: 1.0, blue: 2.0};
+        let [red, green, blue] = cast.apply([100.0 / 255.0, 150.0 / 255.0, 50.0 / 255.0]);
+        right.image = RgbaImage::from_pixel(40, 20, Rgba([(red * 255.0) as u8, (green * 255.0) as u8, (blue * 255.0) as u8, 255]));
+
+        let mut photos = vec![left, right];
+        let corrections: Vec<ColorCorrection> = estimate_color_corrections(&photos, &CameraModel::Planar).into_iter().map(Option::unwrap).collect();
+        assert!(corrections[1].exposure - corrections[0].exposure > 0.9);
+        assert!(corrections[1].blue < corrections[0].blue);
+
+        for (photo, correction) in photos.iter_mut().zip(corrections) {
+            photo.fields.color_correction = correction;
+        }
+
+        //corrections are applied: the photos match
+        let output = stitch(&photos, &CameraModel::Planar, None, &StitchOptions::default()).unwrap();
+        let (left, right) = (output.get_pixel(0, 10), output.get_pixel(63, 10));
+        for channel in 0..3 {
+            assert!((left[channel] as i32 - right[channel] as i32).abs() <= 3, "{:?} {:?}", left, right);
+        }
+
+        assert!(estimate_color_corrections(&[], &CameraModel::Planar).is_empty());
+
+        //hidden photos aren't estimated
+        photos[1].fields.visible = false;
+        assert_eq!(estimate_color_corrections(&photos, &CameraModel::Planar), vec![None, None]);
+    }
+
+    #[test]
+    fn stitch_vignetting_test() {
+
+        let vignetting = Vignetting{a: 1.0, b: -0.4, c: 0.0, d: 0.0};
+
+        //a gray scene, photographed with vignetting
+        let vignetted_photo = |center: WorldCoords| {
+            let mut photo = solid_photo(40, 20, [0; 4], center);
+            for (x, y, pixel) in photo.image.enumerate_pixels_mut() {
+                let uv = ((x as f64 + 0.5) / 40.0, 1.0 - (y as f64 + 0.5) / 20.0);
+                let value = (127.0 * exposure::encoded_gain(vignetting.brightness(Vignetting::radius(uv, 40, 20)))).round() as u8;
+                *pixel = Rgba([value, value, value, 255]);
+            }
+            photo
+        };
+
+        let mut photos = vec![vignetted_photo(WorldCoords{x: 0.0, y: 0.0}), vignetted_photo(WorldCoords{x: 24.0, y: 0.0})];
+
+        let brightness_range = |photos: &[SourcePhoto]| {
+            let output = stitch(photos, &CameraModel::Planar, None, &StitchOptions::default()).unwrap();
+            let values: Vec<u8> = (0..64).map(|x| output.get_pixel(x, 10)[0]).collect();
+            values.iter().max().unwrap() - values.iter().min().unwrap()
+        };
+        assert!(brightness_range(&photos) > 15);
+
+        //the photos share a lens
+        let estimated = estimate_vignetting(&photos, &CameraModel::Planar);
+        assert_eq!(estimated[0], estimated[1]);
+        assert!(estimated[0].brightness(1.0) < 0.8);
+
+        for (photo, vignetting) in photos.iter_mut().zip(estimated) {
+            photo.vignetting = vignetting;
+        }
+
+        //corrections are applied: even brightness
+        assert!(brightness_range(&photos) <= 4, "{}", brightness_range(&photos));
+
+        assert!(estimate_vignetting(&[], &CameraModel::Planar).is_empty());
+    }
+
+    #[test]
+    fn stitch_spherical_test() {
+
+        //rotated 45 degrees: the output's corners are not covered
+        let mut photo = solid_photo(20, 10, [0, 255, 0, 255], WorldCoords{x: 0.0, y: 0.0});
+        photo.fields.pose = CameraPose{yaw: 0.0, pitch: 0.0, roll: 45.0};
+
+        let projection = SphereProjection{scale: 20.0, kind: ProjectionKind::Equirectangular};
+        let output = stitch(&[photo], &CameraModel::Spherical(projection), None, &StitchOptions::default()).unwrap();
+
+        let (width, height) = output.dimensions();
+        assert_eq!(output.get_pixel(width / 2, height / 2), &Rgba([0, 255, 0, 255]));
+        assert_eq!(output.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn save_test() {
+
+        assert_matches!(save(RgbaImage::new(1, 1), "out.tiff"), Err(StitchError::UnknownOutput(_)));
+        assert_matches!(load_input("a.txt").err(), Some(StitchError::UnknownInput(_)));
+        assert_matches!(load_input("no_such_dir/a.pto").err(), Some(StitchError::Io(_, _)));
+    }
+}
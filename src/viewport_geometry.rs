@@ -4,6 +4,8 @@ use std::fmt::{Display, Formatter};
 use std::num::NonZeroUsize;
 use std::ops::Add;
 
+use serde::{Serialize, Deserialize};
+
 #[derive(Debug, Copy, Clone)]
 pub struct ViewportGeometry {
     pub camera_position: WorldCoords,
@@ -155,7 +157,7 @@ pub struct PixelCoords {
     pub y: f64,
 }
 
-#[derive(Debug, PartialOrd, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialOrd, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct WorldCoords {
     /// x location in world units: [left, right]
     pub x: f64,
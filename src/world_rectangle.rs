@@ -2,10 +2,9 @@
 use std::fmt::{Display,Formatter};
 
 use three_d::{Vec2,Vec3,Vec4,Mat4,Transform,InnerSpace};
-use cgmath::{Deg, AbsDiffEq};
+use cgmath::{Deg, AbsDiffEq, SquareMatrix};
 
-use serde::{Serialize, Deserialize, Serializer};
-use serde::ser::SerializeStruct;
+use serde::{Serialize, Deserialize};
 
 use crate::viewport_geometry::WorldCoords;
 
@@ -14,7 +13,8 @@ use crate::viewport_geometry::WorldCoords;
 /// In the rectangle's local coordinate system:
 /// * (0,0) is the center
 /// * (+/-0.5, +/-0.5) are corners
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(into = "WorldRectangleFields", from = "WorldRectangleFields")]
 pub struct WorldRectangle {
 
     pub scale: Mat4,     //in WorldCoords units
@@ -39,16 +39,33 @@ impl AbsDiffEq<WorldRectangle> for WorldRectangle {
     }
 }
 
-impl Serialize for WorldRectangle {
+/// The serialized form of a WorldRectangle
+#[derive(Serialize, Deserialize)]
+struct WorldRectangleFields {
+    width: f32,
+    height: f32,
+    center: WorldCoords,
+    /// counterclockwise rotation around center in degrees
+    angle: f32,
+}
 
-    fn serialize<S>(&self, serializer: S) -> Result<<S as Serializer>::Ok, <S as Serializer>::Error> where
-        S: Serializer {
+impl From<WorldRectangle> for WorldRectangleFields {
+    fn from(world_rectangle: WorldRectangle) -> Self {
+        Self {
+            width: world_rectangle.width(),
+            height: world_rectangle.height(),
+            center: world_rectangle.translation(),
+            angle: world_rectangle.rotation(),
+        }
+    }
+}
 
-        let mut state = serializer.serialize_struct("WorldRectangle", 3)?;
-        state.serialize_field("scale", &self.scale)?;
-        state.serialize_field("translate", &self.translate)?;
-        state.serialize_field("rotate", &self.rotate)?;
-        state.end()
+impl From<WorldRectangleFields> for WorldRectangle {
+    fn from(fields: WorldRectangleFields) -> Self {
+        let mut world_rectangle = WorldRectangle::new(fields.width, fields.height);
+        world_rectangle.set_rotation(fields.angle);
+        world_rectangle.set_translation(fields.center);
+        world_rectangle
     }
 }
 
@@ -103,21 +120,23 @@ impl WorldRectangle {
     #[allow(dead_code)]
     pub fn set_from_json_serde_string(&mut self, s: &str) -> Result<(), Box<dyn std::error::Error>> {
 
-        #[derive(Deserialize)]
-        struct SavedFields {
-            scale: Mat4,
-            translate: Mat4,
-            rotate: Mat4,
-        }
-
-        let saved_fields: SavedFields = serde_json::from_str(s)?;
-        self.scale = saved_fields.scale;
-        self.translate = saved_fields.translate;
-        self.rotate = saved_fields.rotate;
+        *self = serde_json::from_str(s)?;
 
         Ok(())
     }
 
+    /// width in WorldCoords units
+    pub fn width(&self) -> f32 {
+
+        self.scale.x.magnitude()
+    }
+
+    /// height in WorldCoords units
+    pub fn height(&self) -> f32 {
+
+        self.scale.y.magnitude()
+    }
+
     pub fn to_world(&self) -> Mat4 {
 
         self.translate.concat(&self.rotate).concat(&self.scale)
@@ -242,6 +261,15 @@ impl WorldRectangle {
         WorldCoords{ x: world_coords.x as f64, y: world_coords.y as f64 }
     }
 
+    /// the inverse of `world_coords`: None if this rectangle has no area
+    pub fn local_coords(&self, world_coords: WorldCoords) -> Option<LocalCoords> {
+
+        let world_coords = Vec4::new(world_coords.x as f32, world_coords.y as f32, 0.0, 1.0);
+
+        let local_coords = self.to_world().invert()? * world_coords;
+
+        Some(LocalCoords{ x: local_coords.x as f64, y: local_coords.y as f64 })
+    }
 
 }
 
@@ -265,7 +293,13 @@ mod test {
         let mut serde_out = WorldRectangle::new(0.0, 0.0);
         serde_out.set_from_json_serde_string(&serde_string)?;
 
-        assert_eq!(serde_in, serde_out);
+        assert_abs_diff_eq!(serde_in, serde_out);
+
+        //compact representation
+        assert_eq!(
+            serde_json::to_value(WorldRectangle::new(300.0, 200.0))?,
+            serde_json::json!({"width": 300.0, "height": 200.0, "center": {"x": 0.0, "y": 0.0}, "angle": 0.0})
+        );
 
         Ok(())
     }
@@ -538,8 +572,18 @@ mod test {
 
                 assert_eq!(world_coords, WorldCoords { x: x + 50.0, y: y + 100.0 });
             }
+
+            //inverse
+            {
+                let local_coords = world_rectangle.local_coords(WorldCoords { x: x + 50.0, y: y + 100.0 }).unwrap();
+
+                assert_abs_diff_eq!(local_coords.x, 0.5, epsilon = 1e-4);
+                assert_abs_diff_eq!(local_coords.y, -0.5, epsilon = 1e-4);
+            }
         }
 
+        assert!(WorldRectangle::new(0.0, 100.0).local_coords(WorldCoords { x: 0.0, y: 0.0 }).is_none());
+
         Ok(())
     }
 }
\ No newline at end of file
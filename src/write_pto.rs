@@ -0,0 +1,320 @@
+use std::fmt::{Write, Display, Formatter};
+use std::error::Error;
+
+use crate::read_pto::{PtoFile, PtoLine, Panorama, Mode, Image, ImageVariable, OptimizeVariable, Mask, Parameter, ParameterValue, ControlPointPair};
+
+
+/// A text value which can't be written to a PTO file: PTO files have no way to quote a `"`
+#[derive(Debug, PartialEq, Clone)]
+pub struct WritePtoError {
+    pub text: String,
+}
+
+impl Display for WritePtoError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PTO text values can't contain '\"': {}", self.text)
+    }
+}
+
+impl Error for WritePtoError {}
+
+fn check_text(text: &str) -> Result<(), WritePtoError> {
+
+    if text.contains('"') {
+        return Err(WritePtoError{text: text.to_string()});
+    }
+    Ok(())
+}
+
+fn check_parameters(parameters: &[Parameter]) -> Result<(), WritePtoError> {
+
+    parameters.iter().try_for_each(|p| match &p.value {
+        ParameterValue::Text(s) => check_text(s),
+        _ => Ok(()),
+    })
+}
+
+/// checks that a line's text values can be written
+fn check_line(line: &PtoLine) -> Result<(), WritePtoError> {
+
+    match line {
+        PtoLine::Panorama(panorama) => check_parameters(&panorama.other_parameters),
+        PtoLine::Mode(mode) => check_parameters(&mode.parameters),
+        PtoLine::Image(image) => {
+            check_text(&image.file_name)?;
+            check_parameters(&image.other_parameters)
+        },
+        _ => Ok(()),
+    }
+}
+
+fn parameter_value(value: &ParameterValue) -> String {
+
+    match value {
+        ParameterValue::Number(n) => format!("{}", n),
+        ParameterValue::Link(id) => format!("={}", id),
+        ParameterValue::Text(s) => format!("\"{}\"", s),
+        ParameterValue::List(values) => values.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(","),
+    }
+}
+
+fn image_variable(variable: &ImageVariable) -> String {
+
+    match variable {
+        ImageVariable::Value(n) => format!("{}", n),
+        ImageVariable::Link(id) => format!("={}", id),
+    }
+}
+
+fn parameters(parameters: &[Parameter]) -> String {
+
+    parameters.iter().fold(String::new(), |mut acc, p| {
+        write!(acc, " {}{}", p.name, parameter_value(&p.value)).unwrap();
+        acc
+    })
+}
+
+fn panorama_line(panorama: &Panorama) -> String {
+
+    format!("p f{} w{} h{} v{}{}",
+        panorama.projection,
+        panorama.width,
+        panorama.height,
+        panorama.fov,
+        parameters(&panorama.other_parameters),
+    )
+}
+
+fn mode_line(mode: &Mode) -> String {
+
+    format!("m{}", parameters(&mode.parameters))
+}
+
+fn image_line(image: &Image) -> String {
+
+    format!("i w{} h{} f{} v{} r{} p{} y{} a{} b{} c{} d{} e{}{} n\"{}\"",
+        image.width,
+        image.height,
+        image.projection,
+        image_variable(&image.fov),
+        image_variable(&image.roll),
+        image_variable(&image.pitch),
+        image_variable(&image.yaw),
+        image_variable(&image.a),
+        image_variable(&image.b),
+        image_variable(&image.c),
+        image_variable(&image.d),
+        image_variable(&image.e),
+        parameters(&image.other_parameters),
+        image.file_name,
+    )
+}
+
+fn optimize_variables_line(variables: &[OptimizeVariable]) -> String {
+
+    variables.iter().fold("v".to_string(), |mut acc, v| {
+        write!(acc, " {}{}", v.name, v.image_id).unwrap();
+        acc
+    })
+}
+
+fn mask_line(mask: &Mask) -> String {
+
+    let polygon: Vec<String> = mask.polygon.iter().map(|(x, y)| format!("{} {}", x, y)).collect();
+
+    format!("k i{} t{} p\"{}\"", mask.image_id, mask.mask_type, polygon.join(" "))
+}
+
+fn control_point_pair(pair: &ControlPointPair) -> String {
+
+    format!("c n{} N{} x{} y{} X{} Y{} t{}",
+        pair.cp1.image_id,
+        pair.cp2.image_id,
+        pair.cp1.x_coord,
+        pair.cp1.y_coord,
+        pair.cp2.x_coord,
+        pair.cp2.y_coord,
+        u64::from(pair.point_type),
+    )
+}
+
+fn pto_line(line: &PtoLine) -> String {
+
+    match line {
+        PtoLine::Panorama(panorama) => panorama_line(panorama),
+        PtoLine::Mode(mode) => mode_line(mode),
+        PtoLine::Image(image) => image_line(image),
+        PtoLine::OptimizeVariables(variables) => optimize_variables_line(variables),
+        PtoLine::Mask(mask) => mask_line(mask),
+        PtoLine::ControlPointPair(pair) => control_point_pair(pair),
+        PtoLine::Comment(s) => format!("#{}", s),
+        PtoLine::Blank => String::new(),
+        PtoLine::Unknown(s) => s.clone(),
+    }
+}
+
+/// Writes a PtoFile as Hugin PTO file contents.
+///
+/// Lines are written in their stored order; comments and unknown lines are written unchanged.
+/// Fails if a text value (e.g. an image file name) contains a `"`.
+pub fn write_pto_file(pto_file: &PtoFile) -> Result<String, WritePtoError> {
+
+    pto_file.lines.iter().try_for_each(check_line)?;
+
+    Ok(pto_file.lines.iter().fold(String::new(), |mut acc, line| {
+        acc.push_str(&pto_line(line));
+        acc.push('\n');
+        acc
+    }))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::read_pto::{read_pto_file, ControlPoint, ControlPointType};
+    use crate::media_paths::MediaPaths;
+
+    #[test]
+    fn image_line_test() {
+
+        let pto_file = read_pto_file("i w920 h614 f0 v=0 Eev0.5 r-1.5 p2 y30.25 a=0 b0.01 c-0.02 d1 e-1 n\"DSC 9108.JPG\"").unwrap();
+
+        assert_eq!(
+            image_line(pto_file.images()[0]),
+            "i w920 h614 f0 v=0 r-1.5 p2 y30.25 a=0 b0.01 c-0.02 d1 e-1 Eev0.5 n\"DSC 9108.JPG\""
+        );
+    }
+
+    #[test]
+    fn cropped_lines_test() {
+
+        let pto_file_contents =
+"p f2 w3000 h1500 v360 k0 E11.9 R0 S150,2850,300,1200 n\"TIFF_m c:LZW r:CROP\"
+i w920 h614 f0 v50.2734 r0.117 p-0.554 y-10.02 a0 b0 c0 d0 e0 Eev11.9 Vm5 S24,896,12,600 n\"DSC_9108_12_5.JPG\"
+";
+        let parsed = read_pto_file(pto_file_contents).unwrap();
+        let written = write_pto_file(&parsed).unwrap();
+
+        assert_eq!(written, pto_file_contents);
+        assert_eq!(read_pto_file(&written).unwrap(), parsed);
+    }
+
+    #[test]
+    fn optimize_variables_line_test() {
+
+        assert_eq!(optimize_variables_line(&[]), "v");
+        assert_eq!(optimize_variables_line(&[
+            OptimizeVariable{name: "y".to_string(), image_id: 1},
+            OptimizeVariable{name: "TrX".to_string(), image_id: 2},
+        ]), "v y1 TrX2");
+    }
+
+    #[test]
+    fn mask_line_test() {
+
+        let mask = Mask{image_id: 1, mask_type: 0, polygon: vec![(10.0, 20.0), (30.5, 40.0)]};
+        assert_eq!(mask_line(&mask), "k i1 t0 p\"10 20 30.5 40\"");
+    }
+
+    #[test]
+    fn control_point_pair_test() {
+
+        assert_eq!(
+            control_point_pair(&ControlPointPair {
+                cp1: ControlPoint::new(0, 568.542826048136, 117.691966641595),
+                cp2: ControlPoint::new(1, 54.4570607766205, 98.7300002744364),
+                point_type: ControlPointType::Normal,
+            }),
+            "c n0 N1 x568.542826048136 y117.691966641595 X54.4570607766205 Y98.7300002744364 t0"
+        );
+
+        assert_eq!(
+            control_point_pair(&ControlPointPair {
+                cp1: ControlPoint::new(2, 10.0, 20.0),
+                cp2: ControlPoint::new(2, 10.5, 400.0),
+                point_type: ControlPointType::VerticalLine,
+            }),
+            "c n2 N2 x10 y20 X10.5 Y400 t1"
+        );
+
+        assert_eq!(
+            control_point_pair(&ControlPointPair {
+                cp1: ControlPoint::new(0, 1.0, 2.0),
+                cp2: ControlPoint::new(1, 3.0, 4.0),
+                point_type: ControlPointType::StraightLine(5),
+            }),
+            "c n0 N1 x1 y2 X3 Y4 t5"
+        );
+    }
+
+    #[test]
+    fn round_trip_test() {
+
+let pto_file_contents =
+"# hugin project file
+#hugin_ptoversion 2
+p f2 w3000 h1500 v360  k0 E0 R0 n\"TIFF_m c:LZW r:CROP\"
+m g1 i0 f0 m2 p0.00784314
+
+# image lines
+#-hugin  cropFactor=1.53
+i w920 h614 f0 v50.2734 Ra0 Rb0 Rc0 Rd0 Re0 Eev11.9 Er1 Eb1 r0.117 p-0.554 y-10.02 TrX0 TrY0 TrZ0 Tpy0 Tpp0 j0 a0.00190984684 b-0.00282668791 c0.00953214827 d0 e0 g0 t0 Va1 Vb0 Vc0 Vd0 Vx0 Vy0 Vm5 n\"DSC_9108_12_5.JPG\"
+#-hugin  cropFactor=1.53
+i w920 h614 f0 v=0 Ra=0 Rb=0 Rc=0 Rd=0 Re=0 Eev11.9 Er1 Eb1 r-0.2 p0.3 y22.5 TrX0 TrY0 TrZ0 Tpy0 Tpp0 j0 a=0 b=0 c=0 d=0 e=0 g=0 t=0 Va=0 Vb=0 Vc=0 Vd=0 Vx=0 Vy=0 Vm5 n\"DSC_9109_12_5.JPG\"
+
+# specify variables that should be optimized
+v Ra0 Rb0 Rc0 Rd0 Re0 Vb0 Vc0 Vd0
+v Eev1 r1 p1 y1
+v
+
+k i1 t0 p\"12.5 3 400 3 400 200.25\"
+
+# control points
+c n0 N1 x568.542826048136 y117.691966641595 X54.4570607766205 Y98.7300002744364 t0
+c n0 N1 x700.1 y400.2 X180.3 Y390.4 t0
+c n1 N1 x12 y30 X14 Y500 t1
+c n0 N0 x10 y300 X900 Y310 t2
+c n0 N1 x5 y5 X800 Y10 t3
+
+#hugin_optimizeReferenceImage 0
+*
+";
+        let parsed = read_pto_file(pto_file_contents).unwrap();
+        let written = write_pto_file(&parsed).unwrap();
+        let reparsed = read_pto_file(&written).unwrap();
+
+        assert_eq!(parsed, reparsed);
+
+        //writing is stable after the first round trip
+        assert_eq!(written, write_pto_file(&reparsed).unwrap());
+    }
+
+    #[test]
+    #[ignore = "needs the dev media directory (../panorama-explorer-dev-media)"]
+    fn dev_media_round_trip_test() {
+
+        let path = MediaPaths::dev_media().pto_file.unwrap();
+        let pto_file_contents = std::fs::read_to_string(&path).unwrap();
+
+        let parsed = read_pto_file(&pto_file_contents).unwrap();
+        let written = write_pto_file(&parsed).unwrap();
+
+        assert_eq!(parsed, read_pto_file(&written).unwrap());
+    }
+
+    #[test]
+    fn quote_in_text_test() {
+
+        let mut pto_file = read_pto_file("i w920 h614 f0 v50 r0 p0 y0 a0 b0 c0 d0 e0 n\"a.jpg\"").unwrap();
+        assert!(write_pto_file(&pto_file).is_ok());
+
+        pto_file.images_mut()[0].file_name = "a\"b.jpg".to_string();
+        assert_eq!(write_pto_file(&pto_file), Err(WritePtoError{text: "a\"b.jpg".to_string()}));
+
+        let mut pto_file = read_pto_file("p f2 w3000 h1500 v360 n\"TIFF\"").unwrap();
+        pto_file.panorama_mut().unwrap().other_parameters[0].value = ParameterValue::Text("\"".to_string());
+        assert!(write_pto_file(&pto_file).is_err());
+    }
+}